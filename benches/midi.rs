@@ -0,0 +1,107 @@
+//! Parse, load and playback benchmarks on synthetic files built in memory.
+//!
+//! Run with `cargo bench`.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use std::hint::black_box;
+use std::sync::{Arc, LazyLock};
+
+use common::{TrackBuilder, smf, write_temp};
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use midiplayer_rs::midi::loader::{load_midi_bytes, load_midi_file};
+use midiplayer_rs::midi::player::{
+    ParsedMidi, PlaybackOptions, parse_midi_events, play_parsed_events_with_options,
+};
+use midiplayer_rs::midi::utils::Clock;
+
+const TIME_DIV: u16 = 96;
+const TRACKS: u8 = 64;
+const NOTES_PER_TRACK: u32 = 4_096;
+
+/// A Format 1 file of `TRACKS` tracks, each playing chords of short notes on
+/// its own channel, so most ticks have events from many tracks.
+static FILE: LazyLock<Vec<u8>> = LazyLock::new(|| {
+    let tracks: Vec<Vec<u8>> = (0..TRACKS)
+        .map(|track| {
+            let channel = track % 16;
+            let mut builder = TrackBuilder::new();
+            for n in 0..NOTES_PER_TRACK {
+                let key = 36 + (n % 48) as u8;
+                builder = builder
+                    .note_on(if n % 4 == 0 { 12 } else { 0 }, channel, key, 100)
+                    .note_off(6, channel, key);
+            }
+            builder.end(0)
+        })
+        .collect();
+    smf(1, TIME_DIV, &tracks)
+});
+
+static PARSED: LazyLock<ParsedMidi> = LazyLock::new(|| {
+    let (tracks, time_div, _) = load_midi_bytes(&FILE).unwrap();
+    parse_midi_events(tracks, time_div)
+});
+
+/// A clock that never moves, so every delay is zero.
+struct FrozenClock;
+
+impl Clock for FrozenClock {
+    fn now_100ns(&self) -> i64 {
+        0
+    }
+}
+
+fn load_file(c: &mut Criterion) {
+    let path = write_temp(&FILE);
+    let mut group = c.benchmark_group("load");
+    group.throughput(Throughput::Bytes(FILE.len() as u64));
+    group.bench_function("load_file", |b| {
+        b.iter(|| black_box(load_midi_file(&path).unwrap()))
+    });
+    group.finish();
+    let _ = std::fs::remove_file(path);
+}
+
+fn parse_events(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Bytes(FILE.len() as u64));
+    group.bench_function("parse_events", |b| {
+        b.iter(|| {
+            let (tracks, time_div, _) = load_midi_bytes(&FILE).unwrap();
+            black_box(parse_midi_events(tracks, time_div))
+        })
+    });
+    group.finish();
+}
+
+fn play_loop(c: &mut Criterion) {
+    let parsed = &*PARSED;
+    let mut group = c.benchmark_group("play");
+    group.throughput(Throughput::Elements(parsed.events.len() as u64));
+    group.bench_function("play_loop", |b| {
+        b.iter(|| {
+            let options = PlaybackOptions {
+                clock: Some(Arc::new(FrozenClock)),
+                let_notes_ring: true,
+                ..Default::default()
+            };
+            play_parsed_events_with_options(
+                parsed,
+                TIME_DIV,
+                |data, track| {
+                    black_box((data, track));
+                },
+                Some(Box::new(|delay| {
+                    black_box(delay);
+                })),
+                options,
+            );
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, load_file, parse_events, play_loop);
+criterion_main!(benches);
@@ -5,8 +5,9 @@
 use lazy_static::lazy_static;
 use libloading::{Error, Library, Symbol};
 #[cfg(target_os = "windows")]
-use std::os::windows::ffi::OsStrExt;
 use std::ffi::OsStr;
+#[cfg(target_os = "windows")]
+use std::os::windows::ffi::OsStrExt;
 use std::{
     ffi::{c_char, c_void},
     sync::atomic::{AtomicBool, Ordering},
@@ -40,6 +41,85 @@ create_stub_symbol!(stub_bool_with_u8_ptr, (path: *const u8), false, bool);
 create_stub_symbol!(stub_f32, (), 0.0, f32);
 create_stub_symbol!(stub_u64, (), 0, u64);
 
+/// `DriverSettings` mode that changes a setting.
+const OM_SET: u32 = 0x0;
+/// `DriverSettings` mode that reads a setting.
+const OM_GET: u32 = 0x1;
+
+/// The settings `DriverSettings` can read and change, with their ids from
+/// OmniMIDI's KDMAPI header.
+///
+/// Every value is a 32-bit integer. Flags are nonzero when enabled, see
+/// `DriverSetting::is_flag`.
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DriverSetting {
+    CapFramerate = 0x10000,
+    DebugMode = 0x10001,
+    DisableFadeOut = 0x10002,
+    DontMissNotes = 0x10003,
+    EnableSfx = 0x10004,
+    FullVelocity = 0x10005,
+    IgnoreVelocityRange = 0x10006,
+    IgnoreAllEvents = 0x10007,
+    IgnoreSysEx = 0x10008,
+    IgnoreSysReset = 0x10009,
+    LimitRangeTo88 = 0x10010,
+    Mt32Mode = 0x10011,
+    MonoRendering = 0x10012,
+    NoteOff1 = 0x10013,
+    EventProcessingWithAudio = 0x10014,
+    SincInterpolation = 0x10015,
+    SleepStates = 0x10016,
+    AudioBitDepth = 0x10017,
+    AudioFrequency = 0x10018,
+    CurrentEngine = 0x10019,
+    /// Length of the audio buffer, in milliseconds.
+    BufferLength = 0x10020,
+    /// Rendering time limit, in percent, past which voices are killed.
+    MaxRenderingTime = 0x10021,
+    MinIgnoreVelocity = 0x10022,
+    MaxIgnoreVelocity = 0x10023,
+    /// Volume from 0 to 10000.
+    OutputVolume = 0x10024,
+    Transpose = 0x10025,
+    MaxVoices = 0x10026,
+    SincInterpolationConversion = 0x10027,
+    OverrideNoteLength = 0x10028,
+    NoteLength = 0x10029,
+    EnableDelayNoteOff = 0x10030,
+    DelayNoteOffValue = 0x10031,
+}
+
+impl DriverSetting {
+    /// Whether the setting is switched on and off rather than set to a
+    /// number.
+    pub fn is_flag(self) -> bool {
+        matches!(
+            self,
+            DriverSetting::CapFramerate
+                | DriverSetting::DebugMode
+                | DriverSetting::DisableFadeOut
+                | DriverSetting::DontMissNotes
+                | DriverSetting::EnableSfx
+                | DriverSetting::FullVelocity
+                | DriverSetting::IgnoreVelocityRange
+                | DriverSetting::IgnoreAllEvents
+                | DriverSetting::IgnoreSysEx
+                | DriverSetting::IgnoreSysReset
+                | DriverSetting::LimitRangeTo88
+                | DriverSetting::Mt32Mode
+                | DriverSetting::MonoRendering
+                | DriverSetting::NoteOff1
+                | DriverSetting::EventProcessingWithAudio
+                | DriverSetting::SincInterpolation
+                | DriverSetting::SleepStates
+                | DriverSetting::OverrideNoteLength
+                | DriverSetting::EnableDelayNoteOff
+        )
+    }
+}
+
 /// The dynamic bindings for KDMAPI
 pub struct KDMAPIBinds {
     is_kdmapi_available: Option<Symbol<'static, unsafe extern "C" fn() -> bool>>,
@@ -66,7 +146,7 @@ pub struct KDMAPIBinds {
 impl KDMAPIBinds {
     /// Calls `IsKDMAPIAvailable`
     pub fn is_kdmapi_available(&self) -> bool {
-        unsafe { self.is_kdmapi_available.as_ref().map_or(false, |f| f()) }
+        unsafe { self.is_kdmapi_available.as_ref().is_some_and(|f| f()) }
     }
 
     /// Calls `InitializeKDMAPIStream` and returns a stream struct with access
@@ -145,7 +225,9 @@ impl KDMAPIStream {
     /// Calls `ResetKDMAPIStream`
     pub fn reset(&self) {
         unsafe {
-            let _ = self.binds.reset_kdmapi_stream.as_ref().map_or((), |f| f());
+            if let Some(f) = self.binds.reset_kdmapi_stream.as_ref() {
+                f();
+            }
         }
     }
 
@@ -197,14 +279,65 @@ impl KDMAPIStream {
     /// Calls `DriverSettings`
     pub fn driver_settings(&self, setting: u32, mode: u32, value: *mut c_void, cb_value: u32) {
         unsafe {
-            let _ = self
-                .binds
-                .driver_settings
-                .as_ref()
-                .map_or((), |f| f(setting, mode, value, cb_value));
+            if let Some(f) = self.binds.driver_settings.as_ref() {
+                f(setting, mode, value, cb_value);
+            }
         }
     }
 
+    /// Changes `setting` to `value` through `DriverSettings`.
+    pub fn set_setting(&self, setting: DriverSetting, value: u32) {
+        let mut value = value;
+        self.driver_settings(
+            setting as u32,
+            OM_SET,
+            &mut value as *mut u32 as *mut c_void,
+            size_of::<u32>() as u32,
+        );
+    }
+
+    /// Reads `setting` through `DriverSettings`. Returns `None` if the
+    /// driver doesn't export it.
+    pub fn get_setting(&self, setting: DriverSetting) -> Option<u32> {
+        self.binds.driver_settings.as_ref()?;
+        let mut value = 0u32;
+        self.driver_settings(
+            setting as u32,
+            OM_GET,
+            &mut value as *mut u32 as *mut c_void,
+            size_of::<u32>() as u32,
+        );
+        Some(value)
+    }
+
+    /// Switches a flag setting on or off.
+    pub fn set_flag(&self, setting: DriverSetting, enabled: bool) {
+        debug_assert!(setting.is_flag(), "{:?} is not a flag", setting);
+        self.set_setting(setting, enabled as u32);
+    }
+
+    /// Sets the most voices the synth plays at once.
+    pub fn set_max_voices(&self, voices: u32) {
+        self.set_setting(DriverSetting::MaxVoices, voices);
+    }
+
+    /// Sets the length of the audio buffer, in milliseconds.
+    pub fn set_buffer_length(&self, ms: u32) {
+        self.set_setting(DriverSetting::BufferLength, ms);
+    }
+
+    /// Sets the rendering time limit in percent, past which the synth kills
+    /// voices to keep up.
+    pub fn set_max_rendering_time(&self, percent: u32) {
+        self.set_setting(DriverSetting::MaxRenderingTime, percent);
+    }
+
+    /// Sets the output volume, from 0.0 to 1.0.
+    pub fn set_output_volume(&self, volume: f32) {
+        let volume = (volume.clamp(0.0, 1.0) * 10_000.0).round() as u32;
+        self.set_setting(DriverSetting::OutputVolume, volume);
+    }
+
     /// Calls `LoadCustomSoundFontsList`
     pub fn load_custom_soundfonts_list(&self, path: &str) -> bool {
         #[cfg(target_os = "windows")]
@@ -219,7 +352,7 @@ impl KDMAPIStream {
             self.binds
                 .load_custom_soundfonts_list
                 .as_ref()
-                .map_or(false, |f| f(path.as_ptr()))
+                .is_some_and(|f| f(path.as_ptr()))
         }
     }
 
@@ -237,9 +370,9 @@ impl KDMAPIStream {
 impl Drop for KDMAPIStream {
     fn drop(&mut self) {
         unsafe {
-            let _ = self.binds.terminate_kdmapi_stream.as_ref().map_or((), |f| {
+            if let Some(f) = self.binds.terminate_kdmapi_stream.as_ref() {
                 f();
-            });
+            }
         }
         self.binds.is_stream_open.store(false, Ordering::Relaxed);
     }
@@ -1,2 +1,25 @@
+//! Loading, parsing and real-time playback of MIDI files.
+//!
+//! The `midiplayer_rs` binary is built on this library, so everything it does
+//! can be embedded in another program. A file is parsed into a `ParsedMidi`
+//! once, then played to any `MidiOutput`, or to a plain callback:
+//!
+//! ```no_run
+//! use midiplayer_rs::midi::player::{load_and_parse, play_parsed_events};
+//!
+//! let (parsed, time_div) = load_and_parse("song.mid")?;
+//! println!("{} notes", parsed.note_count);
+//! play_parsed_events(&parsed, time_div, |data, _track| println!("{:06X}", data), None);
+//! # Ok::<(), midiplayer_rs::midi::loader::MidiLoadError>(())
+//! ```
+
 pub mod midi;
-pub mod kdmapi;
\ No newline at end of file
+pub mod kdmapi;
+pub mod output;
+pub mod playlist;
+pub mod stats_logger;
+
+pub use kdmapi::KDMAPIStream;
+pub use midi::player::{Event, ParsedMidi, PlaybackOptions, PlaybackStats, PlaybackStrategy};
+pub use midi::track_data::TrackData;
+pub use output::MidiOutput;
@@ -1,23 +1,43 @@
 // Super simple command line midi player
 
-mod midi;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::thread;
-use std::time::{Duration, Instant};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicI8, AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use thousands::Separable;
 
-use clap::{Parser, ValueHint};
+use clap::{Parser, ValueEnum, ValueHint};
 
-use crate::midi::player::play_parsed_events;
-use crate::midi::{loader::load_midi_file, player::parse_midi_events};
-
-mod stats_logger;
-
-mod kdmapi;
-use crate::kdmapi::KDMAPI;
-use crate::stats_logger::StatsLogger;
+use midiplayer_rs::kdmapi::KDMAPI;
+use midiplayer_rs::midi::dedupe::dedupe_note_ons;
+use midiplayer_rs::midi::loader::{LoadOptions, LoadedMidi, MidiFormat, TrackSelection};
+#[cfg(feature = "mmap")]
+use midiplayer_rs::midi::loader::load_midi_file_mapped;
+#[cfg(not(feature = "mmap"))]
+use midiplayer_rs::midi::loader::load_midi_file_selected;
+use midiplayer_rs::midi::player::{
+    CatchUpMode, ParsedMidi, PlaybackOptions, all_notes_off, play_parsed_events_looped,
+    play_parsed_events_with_options, solo_channels,
+};
+use midiplayer_rs::midi::player::{
+    ParseProgress, parse_midi_events_single_threaded_with_progress,
+    parse_midi_events_with_progress, parse_midi_patterns_with_progress,
+};
+use midiplayer_rs::midi::reset::SynthReset;
+use midiplayer_rs::midi::summary::Summary;
+use midiplayer_rs::midi::truncate::{truncate_duration, truncate_events};
+use midiplayer_rs::midi::utils::{AtomicF32, TimerResolutionGuard, set_spin_margin};
+use midiplayer_rs::output::{MidiOutput, PortRouter};
+#[cfg(feature = "midir")]
+use midiplayer_rs::output::MidirBackend;
+#[cfg(target_os = "windows")]
+use midiplayer_rs::output::WinmmBackend;
+use midiplayer_rs::playlist::{expand_playlist, shuffle};
+use midiplayer_rs::stats_logger::{StatsCsv, StatsMonitor};
 
 macro_rules! must {
     ($expr:expr) => {
@@ -32,36 +52,579 @@ macro_rules! must {
 }
 
 #[derive(Parser, Debug)]
-#[command(name = "midi_player", about = "Play a MIDI file", author, version)]
+#[command(name = "midi_player", about = "Play MIDI files", author, version)]
 struct Args {
-    /// Midi file to play
+    /// Midi file or directory of files to play, can be given multiple times
     #[arg(
         short = 'f',
         long = "file",
         value_name = "midi_file",
-        value_hint = ValueHint::FilePath,
-        required = true
+        value_hint = ValueHint::AnyPath,
+        required_unless_present = "list_devices"
     )]
-    file: String,
+    files: Vec<String>,
+
+    /// Play the files in random order
+    #[arg(long = "shuffle")]
+    shuffle: bool,
+
+    /// Only load and play these track indices, e.g. `0-4,10`
+    #[arg(long = "tracks", value_name = "list")]
+    tracks: Option<TrackSelection>,
+
+    /// Parse on a single thread instead of using all cores
+    #[arg(long = "single-threaded")]
+    single_threaded: bool,
+
+    /// Read tracks whose declared length is shorter than their data up to
+    /// their End of Track
+    #[arg(long = "repair")]
+    repair: bool,
+
+    /// Collapse Note Ons stacked on the same key and tick into one note
+    #[arg(long = "dedupe")]
+    dedupe: bool,
+
+    /// Only play the first N events, e.g. to audition a huge file quickly
+    #[arg(long = "max-events", value_name = "N")]
+    max_events: Option<usize>,
+
+    /// Only play this many seconds from the start of each file
+    #[arg(long = "max-duration", value_name = "seconds", value_parser = parse_seconds)]
+    max_duration: Option<Duration>,
+
+    /// Transpose notes by this many semitones
+    #[arg(
+        long = "transpose",
+        value_name = "semitones",
+        allow_hyphen_values = true,
+        default_value_t = 0
+    )]
+    transpose: i8,
+
+    /// Playback speed factor, e.g. `1.5`
+    #[arg(long = "speed", value_name = "factor", default_value_t = 1.0)]
+    speed: f32,
+
+    /// Channels to mute, e.g. `9` or `0,2,9` (0-15)
+    #[arg(
+        long = "mute-channels",
+        value_name = "list",
+        conflicts_with = "solo_channels"
+    )]
+    mute_channels: Option<String>,
+
+    /// Only play these channels (0-15)
+    #[arg(long = "solo-channels", value_name = "list")]
+    solo_channels: Option<String>,
+
+    /// Send channels on other channels, e.g. `0=3,1=4`
+    #[arg(long = "map-channels", value_name = "list")]
+    map_channels: Option<String>,
+
+    /// Play drums on channel 10 with other keys, e.g. `36=35`
+    #[arg(long = "map-drums", value_name = "list")]
+    map_drums: Option<String>,
+
+    /// Start position, as `mm:ss` or a tick
+    #[arg(long = "start", value_name = "mm:ss|tick")]
+    start: Option<Position>,
+
+    /// Wait for the SMPTE offset of each file before playing it, so it
+    /// starts where its time code says. Not waited for with `--start`
+    #[arg(long = "smpte-offset")]
+    smpte_offset: bool,
+
+    /// Stop position, as `mm:ss` or a tick. With `--loop`, the part between
+    /// `--start` and `--end` is looped
+    #[arg(long = "end", value_name = "mm:ss|tick")]
+    end: Option<Position>,
+
+    /// Play the file this many times, 0 loops forever
+    #[arg(long = "loop", value_name = "count")]
+    loop_count: Option<u32>,
+
+    /// Reset the synth before each file, also restoring default volume and pan
+    #[arg(long = "reset", value_name = "none|gm|gs|xg", default_value_t = SynthReset::None)]
+    reset: SynthReset,
+
+    /// Fade out over this many seconds at the end of each file
+    #[arg(long = "fade-out", value_name = "seconds", value_parser = parse_seconds)]
+    fade_out: Option<Duration>,
+
+    /// How many milliseconds playback may fall behind and still catch up
+    #[arg(long = "max-drift", value_name = "ms", default_value_t = 10)]
+    max_drift: u64,
+
+    /// What to do when playback falls behind: play every event, or skip
+    /// controllers, or skip notes too until it's back in time
+    #[arg(
+        long = "catch-up",
+        value_name = "absorb|skip-non-note|skip-all",
+        default_value_t = CatchUpMode::Absorb
+    )]
+    catch_up: CatchUpMode,
+
+    /// How many milliseconds playback may be behind before `--catch-up`
+    /// skips events
+    #[arg(long = "catch-up-threshold", value_name = "ms", default_value_t = 50)]
+    catch_up_threshold: u64,
+
+    /// How many microseconds before each event to stop sleeping and
+    /// busy-wait instead. More is more precise on coarse timers
+    #[arg(long = "spin-margin", value_name = "us", default_value_t = 2_000)]
+    spin_margin: u64,
+
+    /// After each file, print how far playback was off from the ideal timing
+    #[arg(long = "timing-stats")]
+    timing_stats: bool,
+
+    /// Click on every beat, following the tempo and time signature
+    #[arg(long = "metronome")]
+    metronome: bool,
+
+    /// Send MIDI Clock, Start and Stop so external gear can follow along
+    #[arg(long = "midi-clock")]
+    midi_clock: bool,
+
+    /// Don't print progress or Ev/s, only the summaries
+    #[arg(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Print the summary of each file as a JSON object, implies `--quiet`
+    #[arg(long = "json")]
+    json: bool,
+
+    /// Append per-frame Ev/s and synth stats to this CSV file
+    #[arg(long = "stats-csv", value_name = "path", value_hint = ValueHint::FilePath)]
+    stats_csv: Option<String>,
+
+    /// Output backend, by default KDMAPI if it's available
+    #[arg(long = "backend", visible_alias = "output", value_enum)]
+    backend: Option<Backend>,
+
+    /// Output port for the midir and WinMM backends, matched by name or
+    /// given by its index from --list-devices
+    #[cfg(any(feature = "midir", target_os = "windows"))]
+    #[arg(long = "port", visible_alias = "device", value_name = "name|index")]
+    port: Option<String>,
+
+    /// Play the tracks of other MIDI ports on other outputs, e.g.
+    /// `1=midir:Yamaha,2=kdmapi`. Ports that aren't listed play on the
+    /// output of --backend
+    #[arg(long = "route", value_name = "port=backend[:device],...")]
+    route: Option<String>,
+
+    /// Load and parse the files and print their summaries without playing
+    /// them or opening an output
+    #[arg(long = "validate", visible_alias = "dry-run")]
+    validate: bool,
+
+    /// List the output devices of every backend and exit
+    #[arg(long = "list-devices")]
+    list_devices: bool,
 }
 
-struct Shared {
-    evps_logger: StatsLogger,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Backend {
+    /// OmniMIDI through KDMAPI
+    Kdmapi,
+    /// A hardware or virtual port through midir
+    #[cfg(feature = "midir")]
+    Midir,
+    /// A Windows MIDI device through WinMM, by default the MIDI mapper
+    #[cfg(target_os = "windows")]
+    Winmm,
 }
 
-fn main() {
-    let args = Args::parse();
-    let file = args.file;
+/// A position in a file, given as `mm:ss` or a tick.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Position {
+    Time(Duration),
+    Tick(u64),
+}
+
+impl FromStr for Position {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid position '{}', expected mm:ss or a tick", s);
+        match s.split_once(':') {
+            Some((minutes, seconds)) => {
+                let minutes: u64 = minutes.trim().parse().map_err(|_| invalid())?;
+                let seconds: f64 = seconds.trim().parse().map_err(|_| invalid())?;
+                if !(0.0..60.0).contains(&seconds) {
+                    return Err(invalid());
+                }
+                Ok(Position::Time(
+                    Duration::from_secs(minutes * 60) + Duration::from_secs_f64(seconds),
+                ))
+            }
+            None => s.trim().parse().map(Position::Tick).map_err(|_| invalid()),
+        }
+    }
+}
+
+/// Parse a comma separated list of channels, e.g. `0,2,9`.
+fn parse_channels(list: &str) -> Result<Vec<u8>, String> {
+    list.split(',')
+        .map(|part| match part.trim().parse::<u8>() {
+            Ok(channel) if channel < 16 => Ok(channel),
+            _ => Err(format!("Invalid channel '{}', expected 0-15", part.trim())),
+        })
+        .collect()
+}
+
+/// Parse a list of mappings between values below `N`, e.g. `0=3,1=4`.
+/// Values that aren't listed map to themselves.
+fn parse_map<const N: usize>(list: &str) -> Result<[u8; N], String> {
+    let mut map = std::array::from_fn(|value| value as u8);
+    for part in list.split(',') {
+        let invalid = || {
+            format!(
+                "Invalid mapping '{}', expected from=to with values below {}",
+                part.trim(),
+                N
+            )
+        };
+        let (from, to) = part.split_once('=').ok_or_else(invalid)?;
+        match (from.trim().parse::<usize>(), to.trim().parse::<usize>()) {
+            (Ok(from), Ok(to)) if from < N && to < N => map[from] = to as u8,
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(map)
+}
+
+/// An output for the tracks of one MIDI port, see `--route`.
+#[derive(Clone, Debug, PartialEq)]
+struct Route {
+    port: u8,
+    backend: Backend,
+    device: Option<String>,
+}
+
+/// Parse a list of routes from ports to outputs, e.g. `1=midir:Yamaha,2=kdmapi`.
+fn parse_routes(list: &str) -> Result<Vec<Route>, String> {
+    list.split(',')
+        .map(|part| {
+            let invalid = || {
+                format!(
+                    "Invalid route '{}', expected port=backend or port=backend:device",
+                    part.trim()
+                )
+            };
+            let (port, output) = part.split_once('=').ok_or_else(invalid)?;
+            let port = port
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|&port| port < 128)
+                .ok_or_else(invalid)?;
+            let (backend, device) = match output.split_once(':') {
+                Some((backend, device)) => (backend, Some(device.trim().to_string())),
+                None => (output, None),
+            };
+            let backend = <Backend as ValueEnum>::from_str(backend.trim(), true)
+                .map_err(|_| invalid())?;
+            Ok(Route {
+                port,
+                backend,
+                device,
+            })
+        })
+        .collect()
+}
+
+fn check_speed(speed: f32) -> Result<f32, String> {
+    if speed > 0.0 && speed.is_finite() {
+        Ok(speed)
+    } else {
+        Err(format!(
+            "Invalid speed {}, it must be greater than 0",
+            speed
+        ))
+    }
+}
+
+/// Parse a positive length in seconds, e.g. `2.5`.
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    match s.trim().parse::<f64>().map(Duration::try_from_secs_f64) {
+        Ok(Ok(length)) if !length.is_zero() => Ok(length),
+        _ => Err(format!(
+            "Invalid length '{}', expected seconds greater than 0",
+            s
+        )),
+    }
+}
+
+type Output = Arc<dyn MidiOutput + Send + Sync>;
+
+fn open_kdmapi() -> Result<Output, String> {
+    let kdmapi = KDMAPI
+        .as_ref()
+        .map_err(|e| format!("Failed to load KDMAPI: {}", e))?;
+    if !kdmapi.is_kdmapi_available() {
+        return Err("KDMAPI is not available".to_string());
+    }
+    Ok(Arc::new(kdmapi.open_stream()?))
+}
+
+#[cfg(target_os = "windows")]
+fn open_winmm(device: Option<&str>) -> Result<Output, String> {
+    let backend = WinmmBackend::open(device)?;
+    println!("MIDI output device: {}", backend.device_name());
+    Ok(Arc::new(backend))
+}
+
+#[cfg(feature = "midir")]
+fn open_midir(port: Option<&str>) -> Result<Output, String> {
+    let backend = MidirBackend::connect(port)?;
+    println!("MIDI output port: {}", backend.port_name());
+    Ok(Arc::new(backend))
+}
+
+/// Print the devices of each backend, with the indices --port takes.
+fn list_devices() {
+    let available = KDMAPI
+        .as_ref()
+        .is_ok_and(|kdmapi| kdmapi.is_kdmapi_available());
+    println!(
+        "kdmapi: {}",
+        if available { "OmniMIDI" } else { "not available" }
+    );
+
+    #[cfg(feature = "midir")]
+    print_devices("midir", MidirBackend::port_names());
+    #[cfg(target_os = "windows")]
+    print_devices("winmm", WinmmBackend::device_names());
+}
+
+#[cfg(any(feature = "midir", target_os = "windows"))]
+fn print_devices(backend: &str, names: Result<Vec<String>, String>) {
+    match names {
+        Ok(names) if names.is_empty() => println!("{}: no devices", backend),
+        Ok(names) => {
+            println!("{}:", backend);
+            for (index, name) in names.iter().enumerate() {
+                println!("  {}: {}", index, name);
+            }
+        }
+        Err(err) => println!("{}: {}", backend, err),
+    }
+}
+
+/// Backends tried in order when none is requested. Windows always has
+/// WinMM to fall back to.
+const FALLBACK_BACKENDS: &[Backend] = &[
+    Backend::Kdmapi,
+    #[cfg(target_os = "windows")]
+    Backend::Winmm,
+    #[cfg(all(feature = "midir", not(target_os = "windows")))]
+    Backend::Midir,
+];
+
+#[cfg_attr(
+    not(any(feature = "midir", target_os = "windows")),
+    allow(unused_variables)
+)]
+fn open_backend(backend: Backend, device: Option<&str>) -> Result<Output, String> {
+    match backend {
+        Backend::Kdmapi => open_kdmapi(),
+        #[cfg(feature = "midir")]
+        Backend::Midir => open_midir(device),
+        #[cfg(target_os = "windows")]
+        Backend::Winmm => open_winmm(device),
+    }
+}
+
+/// Open the requested backend, or the first one that works.
+fn open_output(args: &Args) -> Result<Output, String> {
+    #[cfg(any(feature = "midir", target_os = "windows"))]
+    let device = args.port.as_deref();
+    #[cfg(not(any(feature = "midir", target_os = "windows")))]
+    let device = None;
 
-    let (tracks, time_div) = must!(load_midi_file(file));
+    if let Some(backend) = args.backend {
+        return open_backend(backend, device);
+    }
+
+    let mut errors = Vec::new();
+    for &backend in FALLBACK_BACKENDS {
+        match open_backend(backend, device) {
+            Ok(output) => return Ok(output),
+            Err(err) => errors.push(err),
+        }
+    }
+    Err(format!(
+        "No MIDI output could be opened. Install OmniMIDI, or choose another output with --backend.\n  {}",
+        errors.join("\n  ")
+    ))
+}
+
+/// The outputs of a run and the index of each routed port's output.
+type Outputs = (Vec<Output>, Vec<(u8, usize)>);
+
+/// Open the outputs of `routes` after `main`, which plays every other port.
+/// Routes to the same backend and device share one output.
+fn open_routes(main: Output, routes: &[Route]) -> Result<Outputs, String> {
+    let mut outputs = vec![main];
+    let mut opened: Vec<(Backend, Option<&str>)> = Vec::new();
+    let mut port_outputs = Vec::with_capacity(routes.len());
+    for route in routes {
+        let key = (route.backend, route.device.as_deref());
+        let index = match opened.iter().position(|&opened| opened == key) {
+            Some(index) => index + 1,
+            None => {
+                outputs.push(open_backend(route.backend, route.device.as_deref())?);
+                opened.push(key);
+                outputs.len() - 1
+            }
+        };
+        port_outputs.push((route.port, index));
+    }
+    Ok((outputs, port_outputs))
+}
+
+/// Print parser progress over the previous line.
+fn print_progress(progress: ParseProgress, single_threaded: bool) {
+    let line = match progress {
+        ParseProgress::Warning(warning) => {
+            eprintln!("Warning: {}", warning);
+            return;
+        }
+        ParseProgress::TracksParsed { done: 0, total, .. } if single_threaded => {
+            format!("Parsing {} tracks...", total)
+        }
+        ParseProgress::TracksParsed { done: 0, total, .. } => {
+            format!("Parsing {} tracks in parallel...", total)
+        }
+        ParseProgress::TracksParsed {
+            done,
+            total,
+            events,
+        } => format!(
+            "Finished track {}/{} -> {} events parsed",
+            done,
+            total,
+            events.separate_with_commas()
+        ),
+        ParseProgress::Merging { tracks } => format!("Merging events from {} tracks...", tracks),
+        ParseProgress::BuildingDeltas => "Building delta table...".to_string(),
+        ParseProgress::Done { events, notes } => format!(
+            "Parsing complete: {} total events, {} notes",
+            events.separate_with_commas(),
+            notes.separate_with_commas()
+        ),
+    };
+    // Finished tracks overwrite each other, every other step keeps its line
+    let newline = !matches!(progress, ParseProgress::TracksParsed { done, .. } if done > 0);
+
+    let mut stdout = io::stdout().lock();
+    let _ = write!(stdout, "\r\x1b[K{}", line);
+    if newline {
+        let _ = writeln!(stdout);
+    }
+    let _ = stdout.flush();
+}
+
+/// Load and parse a file, printing its summary.
+fn load_and_parse(file: &Path, args: &Args) -> Result<(ParsedMidi, u16), String> {
+    let load_options = LoadOptions {
+        repair: args.repair,
+    };
+    #[cfg(feature = "mmap")]
+    let loaded = load_midi_file_mapped(file, args.tracks.as_ref(), &load_options);
+    #[cfg(not(feature = "mmap"))]
+    let loaded = load_midi_file_selected(file, args.tracks.as_ref(), &load_options);
+    let LoadedMidi {
+        tracks,
+        time_div,
+        format,
+        warnings,
+    } = loaded.map_err(|err| format!("{}: {}", file.display(), err))?;
     let num_tracks = tracks.len();
 
+    if !args.quiet && !args.json {
+        println!("MIDI format: {}", format);
+    }
+    if format == MidiFormat::Sequential {
+        eprintln!(
+            "Warning: Format 2 file, its {} patterns are played one after another",
+            num_tracks
+        );
+    }
+    for warning in &warnings {
+        eprintln!("Warning: {}", warning);
+    }
+
+    if let Some(selection) = args.tracks.as_ref().filter(|_| !args.json) {
+        let included: Vec<String> = (0..num_tracks)
+            .filter(|&idx| selection.contains(idx))
+            .map(|idx| idx.to_string())
+            .collect();
+        println!("Playing tracks: {}", included.join(", "));
+    }
+
     let start = Instant::now();
-    let parsed = parse_midi_events(tracks, time_div);
-    let total_ms = parsed.total_duration.as_millis();
-    let minutes = total_ms / 60_000;
-    let seconds = (total_ms % 60_000) / 1_000;
-    let millis = total_ms % 1_000;
+    let quiet = args.quiet || args.json;
+    let single_threaded = args.single_threaded && format != MidiFormat::Sequential;
+    let progress = |progress| {
+        if !quiet {
+            print_progress(progress, single_threaded);
+        }
+    };
+    let mut parsed = if format == MidiFormat::Sequential {
+        parse_midi_patterns_with_progress(tracks, time_div, &progress)
+    } else if single_threaded {
+        parse_midi_events_single_threaded_with_progress(tracks, time_div, &progress)
+    } else {
+        parse_midi_events_with_progress(tracks, time_div, &progress)
+    };
+
+    if args.dedupe {
+        let removed = dedupe_note_ons(&mut parsed);
+        if removed > 0 && !args.json {
+            println!("Removed {} duplicate Note Ons", removed);
+        }
+    }
+    if let Some(max_events) = args.max_events {
+        truncate_events(&mut parsed, time_div, max_events);
+    }
+    if let Some(max_duration) = args.max_duration {
+        truncate_duration(&mut parsed, time_div, max_duration);
+    }
+
+    let summary = Summary::new(
+        &file.display().to_string(),
+        num_tracks,
+        &parsed,
+        time_div,
+        start.elapsed(),
+    );
+
+    if summary.duration_saturated {
+        eprintln!(
+            "Warning: total duration overflowed and was clamped, the file is probably malformed"
+        );
+    }
+
+    if !parsed.warnings.is_empty() && !args.json {
+        let mut corrupt_tracks: Vec<u16> = parsed.warnings.iter().map(|w| w.track).collect();
+        corrupt_tracks.dedup();
+        eprintln!(
+            "Warning: {} tracks had recoverable errors:",
+            corrupt_tracks.len()
+        );
+        for warning in &parsed.warnings {
+            eprintln!("  - {}", warning);
+        }
+    }
+
+    if args.json {
+        println!("{}", summary.to_json());
+        return Ok((parsed, time_div));
+    }
 
     println!(
         "Parsed MIDI Summary:\n\
@@ -70,62 +633,335 @@ fn main() {
      - Events: {}\n\
      - Note Count: {}\n\
      - Total Ticks: {}\n\
-     - Total Duration: {:02}:{:02}.{:03}\n\
+     - Total Duration: {}\n\
      - Parse Time: {:.2?}",
-        num_tracks.separate_with_commas(),
-        time_div,
-        parsed.events.len().separate_with_commas(),
-        parsed.note_count.separate_with_commas(),
-        parsed.total_ticks.separate_with_commas(),
-        minutes,
-        seconds,
-        millis,
-        start.elapsed()
+        summary.tracks.separate_with_commas(),
+        summary.time_division,
+        summary.events.separate_with_commas(),
+        summary.note_count.separate_with_commas(),
+        summary.total_ticks.separate_with_commas(),
+        format_duration(summary.total_duration),
+        summary.parse_time
     );
 
-    let kdmapi_ref = KDMAPI.as_ref().unwrap();
-    let stream = kdmapi_ref.open_stream().unwrap();
-    let stream = Arc::new(stream);
+    if parsed.max_polyphony > 0 {
+        println!(
+            " - Max Polyphony: {} (tick {})",
+            parsed.max_polyphony.separate_with_commas(),
+            parsed.max_polyphony_tick.separate_with_commas()
+        );
+    }
+
+    let mut ports: Vec<u8> = parsed.track_ports.iter().flatten().copied().collect();
+    ports.sort_unstable();
+    ports.dedup();
+    if ports.len() > 1 {
+        let ports: Vec<String> = ports.iter().map(|port| port.to_string()).collect();
+        println!(" - Ports: {}", ports.join(", "));
+    }
+
+    if let Some(offset) = parsed.smpte_offset {
+        println!(" - SMPTE Offset: {}", offset);
+    }
+
+    if let Some((channel, count)) = parsed.busiest_channel() {
+        println!(
+            " - Busiest Channel: {} ({} notes)",
+            channel,
+            count.separate_with_commas()
+        );
+    }
+
+    if let Some(budget) = summary.min_send_budget {
+        println!(
+            " - Min Event Interval: {:.2?} (tick {})",
+            budget.interval,
+            budget.tick.separate_with_commas()
+        );
+    }
+
+    Ok((parsed, time_div))
+}
+
+/// Format a duration as `mm:ss.mmm`.
+fn format_duration(duration: Duration) -> String {
+    let total_ms = duration.as_millis();
+    format!(
+        "{:02}:{:02}.{:03}",
+        total_ms / 60_000,
+        (total_ms % 60_000) / 1_000,
+        total_ms % 1_000
+    )
+}
+
+/// Parse every file of the playlist without playing it. Returns how many
+/// files failed.
+fn validate(playlist: &[PathBuf], args: &Args) -> usize {
+    let mut failed = 0;
+    for (n, file) in playlist.iter().enumerate() {
+        if playlist.len() > 1 && !args.json {
+            println!("[{}/{}] {}", n + 1, playlist.len(), file.display());
+        }
+        if let Err(err) = load_and_parse(file, args) {
+            eprintln!("Error: {}", err);
+            failed += 1;
+        }
+    }
+
+    if playlist.len() > 1 && !args.json {
+        println!(
+            "Validated {} of {} files",
+            playlist.len() - failed,
+            playlist.len()
+        );
+    }
+    failed
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    if args.list_devices {
+        list_devices();
+        return ExitCode::SUCCESS;
+    }
+    set_spin_margin(Duration::from_micros(args.spin_margin));
+    let _timer_resolution = TimerResolutionGuard::raise();
+    let speed = must!(check_speed(args.speed));
+    let muted_channels = match (&args.mute_channels, &args.solo_channels) {
+        (Some(list), _) => must!(parse_channels(list))
+            .iter()
+            .fold(0u16, |mask, &channel| mask | (1 << channel)),
+        (_, Some(list)) => solo_channels(&must!(parse_channels(list))),
+        (None, None) => 0,
+    };
+
+    let channel_map = args
+        .map_channels
+        .as_deref()
+        .map(|list| must!(parse_map(list)));
+    let drum_map = args.map_drums.as_deref().map(|list| must!(parse_map(list)));
+    let routes = args
+        .route
+        .as_deref()
+        .map_or_else(Vec::new, |list| must!(parse_routes(list)));
+    let mut playlist = must!(expand_playlist(&args.files));
+    if playlist.is_empty() {
+        must!(Err("No MIDI files to play"));
+    }
+    if args.shuffle {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        shuffle(&mut playlist, seed);
+    }
+
+    if args.validate {
+        if validate(&playlist, &args) > 0 {
+            return ExitCode::FAILURE;
+        }
+        return ExitCode::SUCCESS;
+    }
 
-    let play_stream = Arc::clone(&stream);
+    let stream = must!(open_output(&args));
+    let (outputs, port_outputs) = must!(open_routes(Arc::clone(&stream), &routes));
 
-    let shared = Arc::new(Shared {
-        evps_logger: StatsLogger::new(60 as usize),
+    // Ctrl-C stops playback, which silences the synth, instead of leaving
+    // notes hanging
+    let stop = Arc::new(AtomicBool::new(false));
+    let interrupted = Arc::clone(&stop);
+    // A second Ctrl-C exits right away. Without a handler, Ctrl-C just ends
+    // the process as usual
+    let _ = ctrlc::set_handler(move || {
+        if interrupted.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
     });
 
-    let counter = Arc::new(AtomicU32::new(0));
-
-    // logger thread
-    let sd = shared.clone();
-    let counter_clone = counter.clone();
-    thread::spawn(move || {
-        let mut last_flush = Instant::now();
-        loop {
-            // drain the atomic into local var
-            let count = counter_clone.swap(0, Ordering::Relaxed);
-            if count > 0 {
-                sd.evps_logger.increment(count);
+    let stats_csv = args
+        .stats_csv
+        .as_ref()
+        .map(|path| Arc::new(Mutex::new(must!(StatsCsv::create(path)))));
+
+    // Stats thread, shared by the whole playlist. JSON output has no use for
+    // it unless the stats are written to a CSV file
+    let quiet = args.quiet || args.json;
+    let monitor = (!args.json || stats_csv.is_some()).then(|| {
+        let frame_csv = stats_csv.clone();
+        let output = Some(Arc::clone(&stream));
+        StatsMonitor::start(Duration::from_secs(1), 60, output, move |stats| {
+            if let Some(csv) = frame_csv.as_ref()
+                && let Ok(mut csv) = csv.lock()
+                && let Err(err) = csv.write_frame(&stats)
+            {
+                eprintln!("Error: failed to write stats: {}", err);
+            }
+            if !quiet {
+                println!("Ev/s: {}", stats.events_per_sec.separate_with_commas());
             }
+        })
+    });
+    let counter = monitor.as_ref().map(StatsMonitor::counter);
+
+    let mut played = 0usize;
+    let mut total_events = 0usize;
+    let mut total_notes = 0u64;
+    let mut total_duration = Duration::ZERO;
 
-            if last_flush.elapsed() >= Duration::from_millis(16) {
-                sd.evps_logger.next_frame();
-                println!("Ev/s: {}", sd.evps_logger.get_eps().separate_with_commas());
-                last_flush = Instant::now();
+    for (n, file) in playlist.iter().enumerate() {
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        if n > 0 {
+            // Don't let the previous song leak into this one
+            for output in &outputs {
+                output.reset();
+                all_notes_off(&mut |data, _track| output.send_direct_data(data));
             }
+        }
+        if playlist.len() > 1 && !args.json {
+            println!("[{}/{}] {}", n + 1, playlist.len(), file.display());
+        }
 
-            thread::sleep(Duration::from_millis(1));
+        let (parsed, time_div) = match load_and_parse(file, &args) {
+            Ok(loaded) => loaded,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                continue;
+            }
+        };
+
+        let start_tick = match args.start {
+            Some(Position::Tick(tick)) => tick,
+            Some(Position::Time(time)) if time > parsed.total_duration => {
+                eprintln!("Error: the start position is past the end of the file");
+                continue;
+            }
+            Some(Position::Time(time)) => parsed.duration_to_tick(time_div, time),
+            None => 0,
+        };
+        let end_tick = match args.end {
+            Some(Position::Tick(tick)) => Some(tick),
+            Some(Position::Time(time)) => Some(parsed.duration_to_tick(time_div, time)),
+            None => None,
+        };
+        if end_tick.is_some_and(|end| end <= start_tick) {
+            eprintln!("Error: the end position must be after the start position");
+            continue;
         }
-    });
 
-    // event loop — as cheap as it gets
-    let counter_clone = counter.clone();
-    play_parsed_events(
-        &parsed,
-        time_div,
-        move |data, _track| {
-            counter_clone.fetch_add(1, Ordering::Relaxed);
-            play_stream.send_direct_data(data);
-        },
-        None,
-    );
+        for output in &outputs {
+            args.reset.send(
+                &mut |data, _track| output.send_direct_data(data),
+                &mut |message| output.send_long_data(message),
+            );
+        }
+
+        // event loop — as cheap as it gets
+        let router = Arc::new(PortRouter::new(
+            outputs.clone(),
+            &parsed.track_ports,
+            &port_outputs,
+        ));
+        let play_router = Arc::clone(&router);
+        let counter = counter.clone();
+        let options = PlaybackOptions {
+            send_long_data: Some(Box::new(move |message, track| {
+                router.send_long_data(message, track);
+            })),
+            start_tick,
+            smpte_offset: args.smpte_offset,
+            end_tick,
+            channel_mute: (muted_channels != 0).then(|| Arc::new(AtomicU16::new(muted_channels))),
+            transpose: (args.transpose != 0).then(|| Arc::new(AtomicI8::new(args.transpose))),
+            speed: (speed != 1.0).then(|| Arc::new(AtomicF32::new(speed))),
+            channel_map,
+            drum_map,
+            metronome: args.metronome,
+            midi_clock: args.midi_clock,
+            fade_out: args.fade_out,
+            max_drift: Duration::from_millis(args.max_drift),
+            catch_up: args.catch_up,
+            catch_up_threshold: Duration::from_millis(args.catch_up_threshold),
+            timing_stats: args.timing_stats,
+            stop: Some(Arc::clone(&stop)),
+            ..Default::default()
+        };
+        let send = move |data, track| {
+            if let Some(counter) = counter.as_ref() {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+            play_router.send_direct_data(data, track);
+        };
+        let timing = match args.loop_count {
+            Some(count) => play_parsed_events_looped(
+                &parsed,
+                time_div,
+                send,
+                None,
+                (count > 0).then_some(count),
+                options,
+            ),
+            None => play_parsed_events_with_options(&parsed, time_div, send, None, options),
+        };
+        if args.timing_stats && !args.json {
+            println!(
+                "Timing: mean error {:.2?}, max lateness {:.2?}, {} drift clamps",
+                timing.mean_error(),
+                timing.max_lateness,
+                timing.drift_clamps
+            );
+        }
+
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        played += 1;
+        total_events += parsed.events.len();
+        total_notes += parsed.note_count;
+        total_duration = total_duration.saturating_add(parsed.total_duration);
+    }
+
+    if playlist.len() > 1 && !args.json {
+        println!(
+            "Playlist Summary:\n\
+     - Files Played: {} of {}\n\
+     - Events: {}\n\
+     - Note Count: {}\n\
+     - Total Duration: {}",
+            played,
+            playlist.len(),
+            total_events.separate_with_commas(),
+            total_notes.separate_with_commas(),
+            format_duration(total_duration)
+        );
+    }
+
+    let evps_logger = monitor.map(StatsMonitor::stop);
+
+    let mut failed = false;
+    if let Some(csv) = stats_csv.as_ref()
+        && let Ok(mut csv) = csv.lock()
+        && let Err(err) = csv.flush()
+    {
+        eprintln!("Error: {}", err);
+        failed = true;
+    }
+
+    if !args.json
+        && let Some(evps_logger) = evps_logger
+    {
+        println!(
+            "Peak Ev/s: {}",
+            evps_logger.get_peak_eps().separate_with_commas()
+        );
+    }
+
+    // Returning closes the outputs, which exiting right away would skip
+    if stop.load(Ordering::Relaxed) {
+        ExitCode::from(130)
+    } else if played == 0 || failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
 }
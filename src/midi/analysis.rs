@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use crate::midi::player::{Event, ParsedMidi};
+use crate::midi::time_division::TimeDivision;
+use crate::midi::utils::duration_from_100ns;
+
+/// The tightest spacing between two consecutive events in a file.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SendBudget {
+    /// Wall time between the two events at the tempo in effect.
+    pub interval: Duration,
+    /// Tick of the event the interval starts at.
+    pub tick: u64,
+}
+
+/// Find the smallest nonzero interval between consecutive events.
+///
+/// This is the time a backend has to send each message in the densest part
+/// of the file. Returns `None` if every event happens on the same tick.
+pub fn min_send_budget(parsed: &ParsedMidi, time_div: u16) -> Option<SendBudget> {
+    let division = TimeDivision::from_raw(time_div);
+    let mut bpm_us_per_qn = 500_000u64;
+    let mut tick = parsed.first_tick;
+    let mut best: Option<SendBudget> = None;
+    let mut event_idx = 0usize;
+
+    for (idx, delta_ticks) in parsed.gaps() {
+        // Apply any tempo changes up to and including the event before the gap.
+        while event_idx <= idx as usize {
+            if let Some(event) = parsed.events.get(event_idx)
+                && event.is_tempo()
+            {
+                bpm_us_per_qn = event.data() as u64;
+            }
+            event_idx += 1;
+        }
+
+        let nanos = division.ticks_to_us(delta_ticks, bpm_us_per_qn) * 1000;
+        let interval = Duration::from_nanos(nanos.min(u64::MAX as u128) as u64);
+
+        if !interval.is_zero() && best.is_none_or(|b| interval < b.interval) {
+            best = Some(SendBudget { interval, tick });
+        }
+
+        tick += delta_ticks;
+    }
+
+    best
+}
+
+/// Find the tick of the first event at or after `time` into the song, with
+/// tempo changes applied. Returns `None` if the song is shorter than `time`.
+pub fn tick_at_time(parsed: &ParsedMidi, time_div: u16, time: Duration) -> Option<u64> {
+    let division = TimeDivision::from_raw(time_div);
+    let target_us = time.as_micros();
+    let mut bpm_us_per_qn = 500_000u64;
+    let mut elapsed_us = 0u128;
+    let mut tick = parsed.first_tick;
+    let mut event_idx = 0usize;
+
+    for (idx, delta_ticks) in parsed.gaps() {
+        if elapsed_us >= target_us {
+            return Some(tick);
+        }
+
+        while event_idx <= idx as usize {
+            if let Some(event) = parsed.events.get(event_idx)
+                && event.is_tempo()
+            {
+                bpm_us_per_qn = event.data() as u64;
+            }
+            event_idx += 1;
+        }
+
+        elapsed_us += division.ticks_to_us(delta_ticks, bpm_us_per_qn);
+        tick += delta_ticks;
+    }
+
+    (elapsed_us >= target_us).then_some(tick)
+}
+
+/// Compute the time every event is played at, relative to the start.
+///
+/// Uses the same tempo multiplier and rounding as playback at normal speed,
+/// without sleeping or sending anything, so the result is the exact schedule
+/// the player aims for.
+pub fn render_timeline(parsed: &ParsedMidi, time_div: u16) -> Vec<(Duration, Event)> {
+    let division = TimeDivision::from_raw(time_div);
+    let mut multiplier = division.multiplier(500_000);
+    let mut now = 0i64;
+    // Fraction of a 100ns unit dropped by the last truncation
+    let mut carry = 0.0f64;
+    let mut gaps = parsed.gaps().peekable();
+    let mut timeline = Vec::with_capacity(parsed.events.len());
+
+    for (idx, event) in parsed.events.iter().enumerate() {
+        timeline.push((duration_from_100ns(now), event));
+
+        if event.is_tempo() {
+            multiplier = division.multiplier(event.data() as u64);
+        }
+
+        if let Some((_, ticks)) = gaps.next_if(|&(i, _)| i as usize == idx) {
+            let exact = ticks as f64 * multiplier + carry;
+            let units = exact as i64;
+            carry = exact - units as f64;
+            now = now.saturating_add(units);
+        }
+    }
+
+    timeline
+}
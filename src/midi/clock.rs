@@ -0,0 +1,70 @@
+use crate::midi::player::{Event, ParsedMidi, insert_events};
+use crate::midi::time_division::TimeDivision;
+
+/// MIDI Clock, sent 24 times per quarter note.
+pub const CLOCK: u32 = 0xF8;
+/// Start, sent at the beginning of the song.
+pub const START: u32 = 0xFA;
+/// Stop, sent at the end of the song or when playback stops early.
+pub const STOP: u32 = 0xFC;
+/// Continue, sent instead of Start when playback starts after a seek.
+pub const CONTINUE: u32 = 0xFB;
+/// Song Position Pointer, sent before Continue.
+pub const SONG_POSITION: u32 = 0xF2;
+const CLOCKS_PER_QUARTER: u64 = 24;
+
+/// The tick of every MIDI Clock before `parsed.total_ticks`, 24 per quarter
+/// note from tick 0. Clocks fall on the nearest tick before their exact time
+/// when the division isn't a multiple of 24. Files with an SMPTE division
+/// have no quarter notes and so no clocks.
+pub fn clock_ticks(parsed: &ParsedMidi, time_div: u16) -> Vec<u64> {
+    let TimeDivision::Metrical(tpq) = TimeDivision::from_raw(time_div) else {
+        return Vec::new();
+    };
+    if tpq == 0 {
+        return Vec::new();
+    }
+
+    (0..)
+        .map(|n: u64| n * u64::from(tpq) / CLOCKS_PER_QUARTER)
+        .take_while(|&tick| tick < parsed.total_ticks)
+        .collect()
+}
+
+/// Song Position Pointer for `tick`, in MIDI beats (sixteenth notes) from the
+/// start of the song. Positions between beats are rounded down, and ones
+/// past the largest pointer are clamped to it.
+pub fn song_position(tick: u64, time_div: u16) -> u32 {
+    let beats = match TimeDivision::from_raw(time_div) {
+        TimeDivision::Metrical(tpq) if tpq > 0 => (tick * 4 / u64::from(tpq)).min(0x3FFF),
+        _ => 0,
+    } as u32;
+    SONG_POSITION | (beats & 0x7F) << 8 | (beats >> 7) << 16
+}
+
+/// Copy `parsed` with MIDI Clock for external gear to follow: Start at tick
+/// 0, a clock at every tick from `clock_ticks` and Stop at the end.
+///
+/// Like the metronome clicks, the messages are placed by tick, so the clock
+/// rate follows tempo and speed changes. Starting at tick 0 keeps the bars of
+/// the gear in line with the file's, so any silence before the first event
+/// is played. The messages are on the track one past the last track of the
+/// file, so muting tracks never stops them.
+pub fn with_midi_clock(parsed: &ParsedMidi, time_div: u16) -> ParsedMidi {
+    let clocks = clock_ticks(parsed, time_div);
+    if clocks.is_empty() {
+        return parsed.clone();
+    }
+
+    let track = parsed.track_note_counts.len() as u16;
+    let mut events = Vec::with_capacity(clocks.len() + 2);
+    events.push((0, Event::new(START, track)));
+    events.extend(
+        clocks
+            .into_iter()
+            .map(|tick| (tick, Event::new(CLOCK, track))),
+    );
+    events.push((parsed.total_ticks, Event::new(STOP, track)));
+
+    insert_events(parsed, events)
+}
@@ -0,0 +1,97 @@
+use crate::midi::player::{EventKind, Events, ParsedMidi, event_kind, push_gap};
+
+/// Index of a packed message's channel and key in per-key tables.
+fn key_index(data: u32) -> usize {
+    ((data & 0x0F) << 7 | (data >> 8) & 0x7F) as usize
+}
+
+/// Collapse Note Ons of the same channel and key at the same tick into the
+/// first one, and drop as many Note Offs of that key to match.
+///
+/// Stacked Note Ons only take up synth voices, since all of them sound the
+/// same. The dropped Note Offs are the first ones that follow, so the kept
+/// note is released by the last one, when the stack would have gone silent.
+/// The note counts and polyphony of `parsed` are updated; its timing stays
+/// the same. Returns the number of Note Ons removed.
+pub fn dedupe_note_ons(parsed: &mut ParsedMidi) -> u64 {
+    let mut kept_at: Vec<Option<u64>> = vec![None; 16 * 128];
+    let mut extra_offs = vec![0u32; 16 * 128];
+    let mut held = vec![0u32; 16 * 128];
+    let mut voices = 0u64;
+    let mut removed = 0u64;
+
+    let mut note_count = parsed.note_count;
+    let mut channel_note_counts = parsed.channel_note_counts;
+    let mut track_note_counts = parsed.track_note_counts.clone();
+    let mut max_polyphony = 0u64;
+    let mut max_polyphony_tick = 0u64;
+
+    let mut events = Events::with_capacity(parsed.events.len());
+    let mut deltas = Vec::with_capacity(parsed.deltas.len());
+    let mut prev_tick = parsed.first_tick;
+
+    for (tick, event) in parsed.iter_with_ticks() {
+        let data = event.data();
+        let keep = if event.is_tempo() || event.is_long() {
+            true
+        } else {
+            let key = key_index(data);
+            match event_kind(data) {
+                EventKind::NoteOn if kept_at[key] == Some(tick) => {
+                    extra_offs[key] += 1;
+                    removed += 1;
+                    note_count -= 1;
+                    channel_note_counts[(data & 0x0F) as usize] -= 1;
+                    if let Some(count) = track_note_counts.get_mut(event.track() as usize) {
+                        *count = count.saturating_sub(1);
+                    }
+                    false
+                }
+                EventKind::NoteOn => {
+                    kept_at[key] = Some(tick);
+                    held[key] += 1;
+                    voices += 1;
+                    if voices > max_polyphony {
+                        max_polyphony = voices;
+                        max_polyphony_tick = tick;
+                    }
+                    true
+                }
+                EventKind::NoteOff if extra_offs[key] > 0 => {
+                    extra_offs[key] -= 1;
+                    false
+                }
+                EventKind::NoteOff => {
+                    if held[key] > 0 {
+                        held[key] -= 1;
+                        voices -= 1;
+                    }
+                    true
+                }
+                _ => true,
+            }
+        };
+
+        if keep {
+            if !events.is_empty() && tick > prev_tick {
+                push_gap(&mut deltas, (events.len() - 1) as u32, tick - prev_tick);
+            }
+            prev_tick = tick;
+            events.push(event);
+        }
+    }
+
+    if removed > 0 {
+        events.shrink_to_fit();
+        deltas.shrink_to_fit();
+        parsed.events = events;
+        parsed.deltas = deltas;
+        parsed.rebuild_tick_index();
+        parsed.note_count = note_count;
+        parsed.channel_note_counts = channel_note_counts;
+        parsed.track_note_counts = track_note_counts;
+        parsed.max_polyphony = max_polyphony;
+        parsed.max_polyphony_tick = max_polyphony_tick;
+    }
+    removed
+}
@@ -1,88 +1,656 @@
-use std::fs::File;
-use std::io::{self, BufReader, Read};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Read};
+use std::ops::{Range, RangeInclusive};
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
 
-use crate::midi::track_data::TrackData;
+use rayon::prelude::*;
+
+use crate::midi::time_division::TimeDivision;
+use crate::midi::track_data::{SharedBuffer, TrackBytes, TrackData};
+
+/// Why a MIDI file couldn't be loaded.
+#[derive(Debug)]
+pub enum MidiLoadError {
+    /// The data doesn't start with an MThd chunk.
+    NotMidi,
+    /// The MThd chunk has a length other than 6.
+    BadHeaderLength(u32),
+    /// The time division has no ticks, either 0 ticks per quarter note or
+    /// SMPTE timing with 0 ticks per frame.
+    BadTimeDivision(u16),
+    /// The file ends in the middle of the MThd chunk.
+    TruncatedHeader,
+    /// An unknown chunk, at the position of track `index`, runs past the end
+    /// of the file.
+    TruncatedTrack { index: usize },
+    Io(io::Error),
+}
+
+impl fmt::Display for MidiLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MidiLoadError::NotMidi => write!(f, "Not a MIDI file"),
+            MidiLoadError::BadHeaderLength(len) => write!(f, "Invalid header length {}", len),
+            MidiLoadError::BadTimeDivision(raw) => {
+                write!(f, "Invalid time division {:#06X}", raw)
+            }
+            MidiLoadError::TruncatedHeader => write!(f, "The file ends in the middle of its header"),
+            MidiLoadError::TruncatedTrack { index } => {
+                write!(f, "The file ends in the middle of track {}", index)
+            }
+            MidiLoadError::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for MidiLoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MidiLoadError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MidiLoadError {
+    fn from(err: io::Error) -> Self {
+        MidiLoadError::Io(err)
+    }
+}
+
+/// Something wrong with a file that was loaded anyway.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadWarning {
+    /// The header has a format other than 0, 1 or 2. The file is read as
+    /// Format 1.
+    UnknownFormat(u16),
+    /// The file ends after `found` of the `declared` tracks.
+    MissingTracks { found: usize, declared: usize },
+    /// The file ends `read` bytes into track `index`, which should have
+    /// `declared` bytes. What was read is kept.
+    TruncatedTrack {
+        index: usize,
+        read: usize,
+        declared: usize,
+    },
+    /// Track `index` runs past its declared length and was read as `length`
+    /// bytes instead, see `LoadOptions::repair`.
+    RepairedTrack {
+        index: usize,
+        length: usize,
+        declared: usize,
+    },
+}
+
+impl fmt::Display for LoadWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadWarning::UnknownFormat(raw) => {
+                write!(f, "unknown MIDI format {}, playing it as Format 1", raw)
+            }
+            LoadWarning::MissingTracks { found, declared } => {
+                write!(f, "file ends after {} of {} tracks", found, declared)
+            }
+            LoadWarning::TruncatedTrack {
+                index,
+                read,
+                declared,
+            } => write!(
+                f,
+                "track {} is truncated ({} of {} bytes), playing what was read",
+                index, read, declared
+            ),
+            LoadWarning::RepairedTrack {
+                index,
+                length,
+                declared,
+            } => write!(
+                f,
+                "track {} is longer than its declared length, reading {} bytes instead of {}",
+                index, length, declared
+            ),
+        }
+    }
+}
+
+/// Optional behaviour for `load_midi_buffer_selected` and the loaders built
+/// on it.
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    /// Read a track whose declared length stops short of its End of Track
+    /// up to its real end instead, see `repaired_length`.
+    pub repair: bool,
+}
+
+/// A loaded file.
+#[derive(Debug)]
+pub struct LoadedMidi {
+    pub tracks: Vec<TrackData>,
+    /// The raw time division, see `TimeDivision::from_raw`.
+    pub time_div: u16,
+    pub format: MidiFormat,
+    /// Problems the file was loaded in spite of, in file order.
+    pub warnings: Vec<LoadWarning>,
+}
+
+impl LoadedMidi {
+    /// The tracks, time division and format, dropping the warnings.
+    pub fn into_parts(self) -> (Vec<TrackData>, u16, MidiFormat) {
+        (self.tracks, self.time_div, self.format)
+    }
+}
+
+/// The SMF format from the file header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MidiFormat {
+    /// Format 0: a single track holding every channel.
+    SingleTrack,
+    /// Format 1: tracks that are played at the same time.
+    Simultaneous,
+    /// Format 2: independent patterns that are played one after another.
+    Sequential,
+}
+
+impl MidiFormat {
+    /// The format for the header value, `None` if it isn't 0, 1 or 2.
+    pub fn from_raw(raw: u16) -> Option<Self> {
+        match raw {
+            0 => Some(MidiFormat::SingleTrack),
+            1 => Some(MidiFormat::Simultaneous),
+            2 => Some(MidiFormat::Sequential),
+            _ => None,
+        }
+    }
+
+    /// The header value of the format.
+    pub fn number(self) -> u16 {
+        match self {
+            MidiFormat::SingleTrack => 0,
+            MidiFormat::Simultaneous => 1,
+            MidiFormat::Sequential => 2,
+        }
+    }
+}
+
+impl fmt::Display for MidiFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            MidiFormat::SingleTrack => "single track",
+            MidiFormat::Simultaneous => "simultaneous tracks",
+            MidiFormat::Sequential => "sequential patterns",
+        };
+        write!(f, "{} ({})", self.number(), description)
+    }
+}
+
+/// A set of track indices, parsed from a list like `0-4,10`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackSelection {
+    ranges: Vec<RangeInclusive<usize>>,
+}
+
+impl TrackSelection {
+    /// Whether the track at `idx` is part of the selection.
+    pub fn contains(&self, idx: usize) -> bool {
+        self.ranges.iter().any(|r| r.contains(&idx))
+    }
+}
+
+impl FromStr for TrackSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_idx = |part: &str| {
+            part.trim()
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid track index '{}'", part.trim()))
+        };
+
+        let mut ranges = Vec::new();
+        for part in s.split(',').filter(|p| !p.trim().is_empty()) {
+            let range = match part.split_once('-') {
+                Some((start, end)) => {
+                    let (start, end) = (parse_idx(start)?, parse_idx(end)?);
+                    if start > end {
+                        return Err(format!("Invalid track range '{}'", part.trim()));
+                    }
+                    start..=end
+                }
+                None => {
+                    let idx = parse_idx(part)?;
+                    idx..=idx
+                }
+            };
+            ranges.push(range);
+        }
+
+        if ranges.is_empty() {
+            return Err("Track selection is empty".into());
+        }
+        Ok(TrackSelection { ranges })
+    }
+}
+
+/// Magic bytes at the start of gzip data.
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// Decompress `bytes` into memory if they are gzip data. Returns `None` if
+/// they aren't compressed.
+#[cfg(feature = "gzip")]
+fn gunzip(bytes: &[u8]) -> Option<io::Result<Vec<u8>>> {
+    if !bytes.starts_with(&GZIP_MAGIC) {
+        return None;
+    }
+    let mut decompressed = Vec::new();
+    Some(
+        flate2::read::MultiGzDecoder::new(bytes)
+            .read_to_end(&mut decompressed)
+            .map(|_| decompressed),
+    )
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gunzip(bytes: &[u8]) -> Option<io::Result<Vec<u8>>> {
+    bytes
+        .starts_with(&GZIP_MAGIC)
+        .then(|| Err(gzip_unsupported()))
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gzip_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "gzip-compressed files need the gzip feature",
+    )
+}
+
+/// Wrap `reader` in a gzip decoder if its data starts with the gzip magic.
+/// The tracks are still read into memory whole, so a compressed file takes
+/// as much memory as its decompressed size.
+pub(crate) fn decompressing_reader<R: BufRead + Send + 'static>(
+    mut reader: R,
+) -> io::Result<Box<dyn Read + Send>> {
+    if !reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        return Ok(Box::new(reader));
+    }
+    #[cfg(feature = "gzip")]
+    return Ok(Box::new(flate2::bufread::MultiGzDecoder::new(reader)));
+    #[cfg(not(feature = "gzip"))]
+    Err(gzip_unsupported())
+}
 
 /// Load a MIDI file.
-/// This returns a vector of TrackData and the time division.
-pub fn load_midi_file<P: AsRef<Path>>(filename: P) -> io::Result<(Vec<TrackData>, u16)> {
-    let file = File::open(&filename)?;
-    let mut reader = BufReader::new(file);
+/// This returns a vector of TrackData, the raw time division (see
+/// `TimeDivision::from_raw`) and the file's format. Warnings are dropped, see
+/// `load_midi_file_selected` to get them.
+pub fn load_midi_file<P: AsRef<Path>>(filename: P) -> Result<(Vec<TrackData>, u16, MidiFormat), MidiLoadError> {
+    load_midi_file_selected(filename, None, &LoadOptions::default()).map(LoadedMidi::into_parts)
+}
+
+/// Load a MIDI file, reading only the tracks in `selection`.
+///
+/// Unselected tracks are skipped without copying their data and are returned
+/// as empty tracks, so track indices stay the same as in the file.
+pub fn load_midi_file_selected<P: AsRef<Path>>(
+    filename: P,
+    selection: Option<&TrackSelection>,
+    options: &LoadOptions,
+) -> Result<LoadedMidi, MidiLoadError> {
+    let bytes = fs::read(&filename)?;
+    load_midi_buffer_selected(Arc::new(bytes), selection, options)
+}
+
+/// Load a MIDI file through a memory map, reading only the tracks in
+/// `selection`.
+///
+/// The tracks borrow their data from the map, so even very large files are
+/// never copied into memory.
+#[cfg(feature = "mmap")]
+pub fn load_midi_file_mapped<P: AsRef<Path>>(
+    filename: P,
+    selection: Option<&TrackSelection>,
+    options: &LoadOptions,
+) -> Result<LoadedMidi, MidiLoadError> {
+    let file = fs::File::open(&filename)?;
+    // SAFETY: the file must not be truncated or modified while it's mapped.
+    // Nothing else is expected to write to a file while it's being played.
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    load_midi_buffer_selected(Arc::new(map), selection, options)
+}
+
+/// Load a MIDI file from any reader, e.g. a `Cursor<Vec<u8>>` or a network stream.
+/// This returns a vector of TrackData, the raw time division and the format.
+pub fn load_midi_reader<R: Read>(reader: R) -> Result<(Vec<TrackData>, u16, MidiFormat), MidiLoadError> {
+    load_midi_reader_selected(reader, None)
+}
+
+/// Load a MIDI file from any reader, reading only the tracks in `selection`.
+/// The whole input is read into memory first.
+pub fn load_midi_reader_selected<R: Read>(
+    mut reader: R,
+    selection: Option<&TrackSelection>,
+) -> Result<(Vec<TrackData>, u16, MidiFormat), MidiLoadError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    load_midi_buffer_selected(Arc::new(bytes), selection, &LoadOptions::default())
+        .map(LoadedMidi::into_parts)
+}
+
+/// Load a MIDI file that is already in memory. The bytes are copied once
+/// into a buffer shared by all tracks.
+pub fn load_midi_bytes(bytes: &[u8]) -> Result<(Vec<TrackData>, u16, MidiFormat), MidiLoadError> {
+    load_midi_buffer_selected(Arc::new(bytes.to_vec()), None, &LoadOptions::default())
+        .map(LoadedMidi::into_parts)
+}
+
+/// Load a MIDI file from a shared buffer, reading only the tracks in
+/// `selection`.
+///
+/// The tracks borrow their data from `buffer` instead of copying it. Track
+/// chunks are located with a quick sequential scan, then their `TrackData`
+/// are built in parallel.
+///
+/// With the `gzip` feature, gzip-compressed data is recognized by its magic
+/// bytes and the whole file is decompressed into memory first.
+///
+/// A file cut short or with a broken track still loads, with what could be
+/// read and a warning about it.
+pub fn load_midi_buffer_selected(
+    buffer: SharedBuffer,
+    selection: Option<&TrackSelection>,
+    options: &LoadOptions,
+) -> Result<LoadedMidi, MidiLoadError> {
+    if let Some(decompressed) = gunzip((*buffer).as_ref()) {
+        return load_midi_buffer_selected(Arc::new(decompressed?), selection, options);
+    }
+
+    let bytes: &[u8] = (*buffer).as_ref();
+    let mut reader = bytes;
+
+    let mut warnings = Vec::new();
+    let (num_tracks, time_div, format) = read_header(&mut reader, &mut warnings)?;
+    let mut header = [0u8; 4];
+    let mut buf4 = [0u8; 4];
+
+    // Find each track's data. `None` marks an unselected track.
+    let mut chunks: Vec<Option<Range<usize>>> = Vec::with_capacity(num_tracks);
+
+    while chunks.len() < num_tracks {
+        let idx = chunks.len();
+
+        // Read the chunk header and length
+        if let Err(err) = reader
+            .read_exact(&mut header)
+            .and_then(|_| reader.read_exact(&mut buf4))
+        {
+            if err.kind() != io::ErrorKind::UnexpectedEof {
+                return Err(err.into());
+            }
+            warnings.push(LoadWarning::MissingTracks {
+                found: chunks.len(),
+                declared: num_tracks,
+            });
+            break;
+        }
+        let mut length = u32::from_be_bytes(buf4) as usize;
+
+        if options.repair
+            && &header == b"MTrk"
+            && let Some(repaired) = repaired_length(reader, length)
+        {
+            warnings.push(LoadWarning::RepairedTrack {
+                index: idx,
+                length: repaired,
+                declared: length,
+            });
+            length = repaired;
+        }
+
+        // Keep whatever is there if the file is cut short
+        let start = bytes.len() - reader.len();
+        let available = length.min(reader.len());
+        reader = &reader[available..];
+        let truncated = available < length;
+
+        if &header != b"MTrk" {
+            if truncated {
+                return Err(MidiLoadError::TruncatedTrack { index: idx });
+            }
+            // Unknown chunks must be skipped, per the SMF spec.
+            continue;
+        }
+        if selection.is_some_and(|sel| !sel.contains(idx)) {
+            chunks.push(None);
+            // A truncated track that isn't played doesn't matter, but it is
+            // the end of the file
+            if truncated {
+                break;
+            }
+            continue;
+        }
+
+        chunks.push(Some(start..start + available));
+
+        if truncated {
+            warnings.push(LoadWarning::TruncatedTrack {
+                index: idx,
+                read: available,
+                declared: length,
+            });
+            break;
+        }
+    }
+
+    // Set up the tracks in parallel, keeping the track order
+    let tracks = chunks
+        .into_par_iter()
+        .map(|range| {
+            let Some(range) = range else {
+                return TrackData::new(TrackBytes::empty());
+            };
+            let mut track = TrackData::new(TrackBytes::new(Arc::clone(&buffer), range));
+            track.update_tick();
+            track
+        })
+        .collect();
+
+    Ok(LoadedMidi {
+        tracks,
+        time_div,
+        format,
+        warnings,
+    })
+}
+
+/// The real length of a track chunk whose declared length stops before its
+/// End of Track, or `None` if the declared length looks right. `data` starts
+/// at the chunk's data and runs to the end of the file.
+///
+/// The track is extended to the first End of Track after its declared end,
+/// but never past the next `MTrk` marker, which is taken as the start of the
+/// next track. Without an End of Track the track runs up to that marker, or
+/// to the end of the file, and ends there like any track without one.
+fn repaired_length(data: &[u8], declared: usize) -> Option<usize> {
+    const END_OF_TRACK: [u8; 3] = [0xFF, 0x2F, 0x00];
+
+    let declared = declared.min(data.len());
+    if data[..declared].ends_with(&END_OF_TRACK) {
+        return None;
+    }
+    let boundary = declared
+        + data[declared..]
+            .windows(4)
+            .position(|window| window == b"MTrk")
+            .unwrap_or(data.len() - declared);
+    // The End of Track may start inside the declared length
+    let search_from = declared.saturating_sub(END_OF_TRACK.len() - 1);
+    let end = data[search_from..boundary]
+        .windows(END_OF_TRACK.len())
+        .position(|window| window == END_OF_TRACK)
+        .map_or(boundary, |pos| search_from + pos + END_OF_TRACK.len());
+    (end > declared).then_some(end)
+}
+
+/// Read and verify the MThd chunk.
+/// Returns the number of tracks, the raw time division and the format.
+/// Unknown formats are read as Format 1. Files wrapped in a RIFF RMID
+/// container are read from the SMF in their `data` chunk. Files too short
+/// for the chunk id are `NotMidi`, and an MThd chunk cut short is
+/// `TruncatedHeader`. An unknown format is added to `warnings`.
+pub(crate) fn read_header<R: Read>(
+    reader: &mut R,
+    warnings: &mut Vec<LoadWarning>,
+) -> Result<(usize, u16, MidiFormat), MidiLoadError> {
+    let eof_as = |eof: fn() -> MidiLoadError| {
+        move |err: io::Error| match err.kind() {
+            io::ErrorKind::UnexpectedEof => eof(),
+            _ => err.into(),
+        }
+    };
+    let not_midi = eof_as(|| MidiLoadError::NotMidi);
+    let truncated = eof_as(|| MidiLoadError::TruncatedHeader);
 
     // Read and verify the header;
     let mut header = [0u8; 4];
-    reader.read_exact(&mut header)?;
+    reader.read_exact(&mut header).map_err(not_midi)?;
+    if &header == b"RIFF" {
+        skip_rmid_header(reader)?;
+        reader.read_exact(&mut header).map_err(not_midi)?;
+    }
     if &header != b"MThd" {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "Not a MIDI file",
-        ));
+        return Err(MidiLoadError::NotMidi);
     }
 
     // Header length (big-endian)
     let mut buf4 = [0u8; 4];
-    reader.read_exact(&mut buf4)?;
+    reader.read_exact(&mut buf4).map_err(truncated)?;
     let header_len = u32::from_be_bytes(buf4);
     if header_len != 6 {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "Invalid header length",
-        ));
+        return Err(MidiLoadError::BadHeaderLength(header_len));
     }
 
     // Format and track count
     let mut buf2 = [0u8; 2];
-    reader.read_exact(&mut buf2)?;
-    let format = u16::from_be_bytes(buf2);
-    println!("MIDI format: {}", format);
-    if format == 0 {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "MIDI Format 0 is not supported.",
-        ));
-    }
+    reader.read_exact(&mut buf2).map_err(truncated)?;
+    let raw_format = u16::from_be_bytes(buf2);
+    let format = MidiFormat::from_raw(raw_format).unwrap_or_else(|| {
+        warnings.push(LoadWarning::UnknownFormat(raw_format));
+        MidiFormat::Simultaneous
+    });
 
-    reader.read_exact(&mut buf2)?;
+    reader.read_exact(&mut buf2).map_err(truncated)?;
     let num_tracks = u16::from_be_bytes(buf2) as usize;
 
     // Time division
-    reader.read_exact(&mut buf2)?;
+    reader.read_exact(&mut buf2).map_err(truncated)?;
+    // The raw value is kept as-is; SMPTE divisions are decoded by `TimeDivision`.
     let time_div = u16::from_be_bytes(buf2);
-    if (time_div & 0x8000) != 0 {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "SMPTE timing is not supported",
-        ));
+    if let TimeDivision::Metrical(0) | TimeDivision::Smpte { ticks_per_frame: 0, .. } =
+        TimeDivision::from_raw(time_div)
+    {
+        return Err(MidiLoadError::BadTimeDivision(time_div));
     }
 
-    // Allocate the tracks
-    let mut tracks = Vec::with_capacity(num_tracks);
+    Ok((num_tracks, time_div, format))
+}
 
-    for _ in 0..num_tracks {
-        // Read the track header
-        reader.read_exact(&mut header)?;
-        if &header != b"MTrk" {
-            // Skip the unknown chunk or break?
-            // We chose to break in this case.
-            break;
+/// Skip the rest of an RMID header, up to the start of its `data` chunk.
+/// The "RIFF" id must already have been read.
+fn skip_rmid_header<R: Read>(reader: &mut R) -> Result<(), MidiLoadError> {
+    // Running out of data before the data chunk means there's no MIDI data
+    let not_midi = |err: io::Error| match err.kind() {
+        io::ErrorKind::UnexpectedEof => MidiLoadError::NotMidi,
+        _ => err.into(),
+    };
+    let mut id = [0u8; 4];
+    let mut buf4 = [0u8; 4];
+
+    // RIFF size, then the form type
+    reader
+        .read_exact(&mut buf4)
+        .and_then(|_| reader.read_exact(&mut id))
+        .map_err(not_midi)?;
+    if &id != b"RMID" {
+        return Err(MidiLoadError::NotMidi);
+    }
+
+    loop {
+        reader
+            .read_exact(&mut id)
+            .and_then(|_| reader.read_exact(&mut buf4))
+            .map_err(not_midi)?;
+        if &id == b"data" {
+            return Ok(());
         }
 
-        // Read the track length
-        reader.read_exact(&mut buf4)?;
+        // RIFF chunks are padded to an even length
+        let length = u64::from(u32::from_le_bytes(buf4));
+        let padded = length + (length & 1);
+        io::copy(&mut (&mut *reader).take(padded), &mut io::sink())?;
+    }
+}
+
+/// Read the track chunks one at a time, handing each track to `on_track`
+/// as soon as it has been read.
+///
+/// Unknown chunks are skipped. A truncated last track is kept with the data
+/// that could be read, like `load_midi_buffer_selected` does. Returns the
+/// warnings about the tracks.
+pub(crate) fn read_tracks<R: Read>(
+    reader: &mut R,
+    num_tracks: usize,
+    mut on_track: impl FnMut(usize, TrackData),
+) -> Result<Vec<LoadWarning>, MidiLoadError> {
+    let mut warnings = Vec::new();
+    let mut header = [0u8; 4];
+    let mut buf4 = [0u8; 4];
+    let mut idx = 0;
+
+    while idx < num_tracks {
+        if let Err(err) = reader
+            .read_exact(&mut header)
+            .and_then(|_| reader.read_exact(&mut buf4))
+        {
+            if err.kind() != io::ErrorKind::UnexpectedEof {
+                return Err(err.into());
+            }
+            warnings.push(LoadWarning::MissingTracks {
+                found: idx,
+                declared: num_tracks,
+            });
+            break;
+        }
         let length = u32::from_be_bytes(buf4) as usize;
 
-        // Read the track data
-        let mut data = vec![0u8; length];
-        reader.read_exact(&mut data)?;
+        let mut data = Vec::new();
+        reader.take(length as u64).read_to_end(&mut data)?;
+        let truncated = data.len() < length;
+
+        if &header != b"MTrk" {
+            if truncated {
+                return Err(MidiLoadError::TruncatedTrack { index: idx });
+            }
+            continue;
+        }
 
-        // Initialize the track
-        let mut track = TrackData::new(length);
-        track.data = data;
+        let read = data.len();
+        let mut track = TrackData::new(data.into());
         track.update_tick();
+        on_track(idx, track);
+        idx += 1;
 
-        tracks.push(track);
+        if truncated {
+            warnings.push(LoadWarning::TruncatedTrack {
+                index: idx - 1,
+                read,
+                declared: length,
+            });
+            break;
+        }
     }
 
-    Ok((tracks, time_div))
+    Ok(warnings)
 }
@@ -0,0 +1,100 @@
+use crate::midi::player::{Event, ParsedMidi, TimeSignature, insert_events};
+use crate::midi::time_division::TimeDivision;
+use crate::midi::utils::pack_short_message;
+
+/// Channel the clicks are played on, GM percussion.
+pub const CLICK_CHANNEL: u8 = 9;
+/// Hi Wood Block, played on the first beat of each bar.
+pub const DOWNBEAT_KEY: u8 = 76;
+/// Low Wood Block, played on the other beats.
+pub const BEAT_KEY: u8 = 77;
+const CLICK_VELOCITY: u8 = 100;
+
+/// Time signature in effect until the file sets one.
+const DEFAULT_SIGNATURE: TimeSignature = TimeSignature {
+    numerator: 4,
+    denominator: 4,
+    clocks_per_click: 24,
+    thirty_seconds_per_quarter: 8,
+};
+
+/// The tick of every beat before `parsed.total_ticks`, and whether it is the
+/// first beat of a bar.
+///
+/// Beats follow the time signatures of the file, 4/4 until the first one, and
+/// each time signature change starts a new bar. Files with an SMPTE division
+/// have no beats.
+pub fn beat_ticks(parsed: &ParsedMidi, time_div: u16) -> Vec<(u64, bool)> {
+    let TimeDivision::Metrical(tpq) = TimeDivision::from_raw(time_div) else {
+        return Vec::new();
+    };
+    if tpq == 0 {
+        return Vec::new();
+    }
+
+    let mut beats = Vec::new();
+    let mut signatures = parsed.time_signatures.iter().copied().peekable();
+    let mut current = (0, DEFAULT_SIGNATURE);
+    loop {
+        // Of several signatures at the same tick, the last one wins
+        while let Some((_, signature)) = signatures.next_if(|&(tick, _)| tick <= current.0) {
+            current.1 = signature;
+        }
+        let (start, signature) = current;
+        let end = signatures.peek().map_or(parsed.total_ticks, |&(tick, _)| {
+            tick.min(parsed.total_ticks)
+        });
+
+        let beat = (u64::from(tpq) * 4 / u64::from(signature.denominator)).max(1);
+        let beats_per_bar = u64::from(signature.numerator.max(1));
+        let mut tick = start;
+        let mut n = 0u64;
+        while tick < end {
+            beats.push((tick, n.is_multiple_of(beats_per_bar)));
+            tick += beat;
+            n += 1;
+        }
+
+        match signatures.next() {
+            Some(next) => current = next,
+            None => return beats,
+        }
+    }
+}
+
+/// Copy `parsed` with a metronome click on every beat from `beat_ticks`.
+///
+/// The clicks are Note Ons on channel 10 (index 9), each released on the next
+/// beat. They are placed by tick like every other event, so they stay in sync
+/// through tempo changes, seeks and speed changes. Their track is one past the
+/// last track of the file, so muting tracks never silences them. The note
+/// counts and statistics of the copy still describe the file alone.
+pub fn with_metronome(parsed: &ParsedMidi, time_div: u16) -> ParsedMidi {
+    let beats = beat_ticks(parsed, time_div);
+    if beats.is_empty() {
+        return parsed.clone();
+    }
+
+    let track = parsed.track_note_counts.len() as u16;
+    let click = |key, velocity| {
+        Event::new(
+            pack_short_message(0x90 | CLICK_CHANNEL, key, velocity),
+            track,
+        )
+    };
+    let mut clicks = Vec::with_capacity(beats.len() * 2);
+    let mut held = None;
+    for (tick, downbeat) in beats {
+        if let Some(key) = held {
+            clicks.push((tick, click(key, 0)));
+        }
+        let key = if downbeat { DOWNBEAT_KEY } else { BEAT_KEY };
+        clicks.push((tick, click(key, CLICK_VELOCITY)));
+        held = Some(key);
+    }
+    if let Some(key) = held {
+        clicks.push((parsed.total_ticks, click(key, 0)));
+    }
+
+    insert_events(parsed, clicks)
+}
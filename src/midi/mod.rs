@@ -1,4 +1,15 @@
+pub mod analysis;
+pub mod clock;
+pub mod dedupe;
 pub mod loader;
+pub mod metronome;
 pub mod player;
+pub mod reset;
+pub mod stream;
+pub mod summary;
+pub mod time_division;
 pub mod track_data;
+pub mod track_mask;
+pub mod truncate;
 pub mod utils;
+pub mod writer;
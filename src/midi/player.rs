@@ -1,71 +1,647 @@
+use crate::midi::analysis::tick_at_time;
+use crate::midi::clock::{CONTINUE, START, STOP, song_position, with_midi_clock};
+use crate::midi::loader::{
+    LoadWarning, MidiFormat, MidiLoadError, decompressing_reader, read_header, read_tracks,
+};
+use crate::midi::metronome::with_metronome;
+use crate::midi::stream::TrackMerge;
+use crate::midi::time_division::TimeDivision;
 use crate::midi::track_data::TrackData;
-use crate::midi::utils::{delay_execution_100ns, get_time_100ns};
-use crossbeam_channel::{Receiver, Sender, bounded};
+use crate::midi::track_mask::TrackMask;
+use crate::midi::utils::{
+    AtomicF32, Clock, SystemClock, delay_execution_100ns, duration_from_100ns, duration_to_100ns,
+    pack_event, pack_short_message, split_short_messages, unpack_event, us_per_qn_to_bpm,
+};
+use crossbeam_channel::{Receiver, Sender, TryRecvError, bounded};
 use rayon::prelude::*;
-use std::io::{self, Write};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI8, AtomicU16, AtomicU64, Ordering};
 use std::thread;
 use std::time::Duration;
-use thousands::Separable;
 
-#[derive(Debug, Copy, Clone)]
+/// A single MIDI or tempo event.
+///
+/// The tempo flag is packed into the data (see `pack_event`) and the struct
+/// isn't padded, which makes an event 6 bytes instead of 8. The merged event
+/// list stores the same fields column by column, see `Events`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(C, packed(2))]
 pub struct Event {
-    pub data: u32,
+    packed: u32,
+    track: u16,
+}
+
+/// Status byte of long (SysEx) events. Their `data` holds the index into
+/// `ParsedMidi::long_msgs` in bits 8 to 30, bit 31 being the tempo flag.
+const LONG_STATUS: u32 = 0xF0;
+const MAX_LONG_MSGS: usize = 1 << 23;
+
+impl Event {
+    /// A short message or SysEx event from `track`.
+    #[inline(always)]
+    pub fn new(data: u32, track: u16) -> Self {
+        Self {
+            packed: pack_event(data, false),
+            track,
+        }
+    }
+
+    /// A tempo change to `us_per_qn` microseconds per quarter note. Tempo
+    /// events are on track 0.
+    #[inline(always)]
+    pub fn tempo(us_per_qn: u32) -> Self {
+        Self {
+            packed: pack_event(us_per_qn, true),
+            track: 0,
+        }
+    }
+
+    /// The packed short message, the SysEx reference of a long event, or the
+    /// microseconds per quarter note of a tempo event.
+    #[inline(always)]
+    pub fn data(&self) -> u32 {
+        unpack_event(self.packed).0
+    }
+
+    /// Index of the track the event comes from.
+    #[inline(always)]
+    pub fn track(&self) -> u16 {
+        self.track
+    }
+
+    #[inline(always)]
+    pub fn is_tempo(&self) -> bool {
+        unpack_event(self.packed).1
+    }
+
+    /// Whether this is a SysEx event, see `ParsedMidi::long_message`.
+    #[inline(always)]
+    pub fn is_long(&self) -> bool {
+        !self.is_tempo() && (self.data() & 0xFF) == LONG_STATUS
+    }
+
+    /// Whether this is a Note On with a nonzero velocity.
+    #[inline(always)]
+    pub fn is_note_on(&self) -> bool {
+        !self.is_tempo() && event_kind(self.data()) == EventKind::NoteOn
+    }
+}
+
+/// The merged event list, stored as one array per field.
+///
+/// The play loop reads the data of every event but the track only of the
+/// events it sends, so the tracks live in an array of their own and don't
+/// take up cache next to the data. The tempo flag is bit 31 of each data
+/// word, see `pack_event`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Events {
+    data: Vec<u32>,
+    tracks: Vec<u16>,
+}
+
+impl Events {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            data: Vec::with_capacity(capacity),
+            tracks: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn push(&mut self, event: Event) {
+        self.data.push(event.packed);
+        self.tracks.push(event.track);
+    }
+
+    /// The event at `idx`, put back together from its fields.
+    #[inline(always)]
+    pub fn get(&self, idx: usize) -> Option<Event> {
+        Some(Event {
+            packed: *self.data.get(idx)?,
+            track: self.tracks[idx],
+        })
+    }
+
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = Event> + DoubleEndedIterator + '_ {
+        self.data
+            .iter()
+            .zip(&self.tracks)
+            .map(|(&packed, &track)| Event { packed, track })
+    }
+
+    /// The data word and tempo flag of the event at `idx`, without its track.
+    ///
+    /// # Safety
+    ///
+    /// `idx` must be less than `len()`.
+    #[inline(always)]
+    unsafe fn data_unchecked(&self, idx: usize) -> (u32, bool) {
+        unpack_event(unsafe { *self.data.get_unchecked(idx) })
+    }
+
+    /// The track of the event at `idx`.
+    ///
+    /// # Safety
+    ///
+    /// `idx` must be less than `len()`.
+    #[inline(always)]
+    unsafe fn track_unchecked(&self, idx: usize) -> u16 {
+        unsafe { *self.tracks.get_unchecked(idx) }
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.data.shrink_to_fit();
+        self.tracks.shrink_to_fit();
+    }
+}
+
+/// What a packed short message does, see `event_kind`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EventKind {
+    NoteOn,
+    /// A Note Off, or a Note On with velocity 0.
+    NoteOff,
+    PolyPressure,
+    ControlChange,
+    ProgramChange,
+    ChannelPressure,
+    PitchBend,
+    /// System messages, including the status of SysEx events.
+    System,
+}
+
+impl EventKind {
+    /// Whether this is a Note On or a Note Off.
+    #[inline(always)]
+    pub fn is_note(self) -> bool {
+        matches!(self, EventKind::NoteOn | EventKind::NoteOff)
+    }
+}
+
+/// Classify a packed short message. Note Ons with velocity 0 are Note Offs,
+/// so every filter treats them as releases.
+#[inline(always)]
+pub fn event_kind(data: u32) -> EventKind {
+    match data & 0xF0 {
+        0x80 => EventKind::NoteOff,
+        0x90 if (data >> 16) & 0xFF == 0 => EventKind::NoteOff,
+        0x90 => EventKind::NoteOn,
+        0xA0 => EventKind::PolyPressure,
+        0xB0 => EventKind::ControlChange,
+        0xC0 => EventKind::ProgramChange,
+        0xD0 => EventKind::ChannelPressure,
+        0xE0 => EventKind::PitchBend,
+        _ => EventKind::System,
+    }
+}
+
+/// The kinds of text meta events that are kept by the parser.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MetaTextKind {
+    Copyright,
+    TrackName,
+    InstrumentName,
+    Lyric,
+    Marker,
+    CuePoint,
+}
+
+impl MetaTextKind {
+    pub fn from_meta_type(meta_type: u8) -> Option<Self> {
+        match meta_type {
+            0x02 => Some(MetaTextKind::Copyright),
+            0x03 => Some(MetaTextKind::TrackName),
+            0x04 => Some(MetaTextKind::InstrumentName),
+            0x05 => Some(MetaTextKind::Lyric),
+            0x06 => Some(MetaTextKind::Marker),
+            0x07 => Some(MetaTextKind::CuePoint),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetaText {
+    pub kind: MetaTextKind,
     pub track: u16,
-    pub is_tempo: bool,
+    /// The channel of the channel prefix meta event (0x20) in effect, if any.
+    /// A prefix lasts until the next channel message or prefix of its track.
+    pub channel: Option<u8>,
+    pub text: String,
 }
 
-#[derive(Debug, Clone)]
+/// A problem found in a corrupt track. The parser keeps the events before
+/// it and drops the rest of the track.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseWarning {
+    pub track: u16,
+    pub tick: u64,
+    pub message: &'static str,
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "track {}, tick {}: {}", self.track, self.tick, self.message)
+    }
+}
+
+/// Decode meta event text as UTF-8, falling back to Latin-1.
+fn decode_meta_text(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_owned(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// A time signature meta event (0x58).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub numerator: u8,
+    pub denominator: u32,
+    pub clocks_per_click: u8,
+    pub thirty_seconds_per_quarter: u8,
+}
+
+impl TimeSignature {
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        match *data {
+            [numerator, dd, clocks_per_click, thirty_seconds_per_quarter, ..] => Some(Self {
+                numerator,
+                denominator: 1u32.checked_shl(dd as u32)?,
+                clocks_per_click,
+                thirty_seconds_per_quarter,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// A key signature meta event (0x59).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeySignature {
+    /// Number of sharps if positive, flats if negative.
+    pub sharps: i8,
+    pub minor: bool,
+}
+
+impl KeySignature {
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        match *data {
+            [sf, mi, ..] => Some(Self {
+                sharps: sf as i8,
+                minor: mi != 0,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// An SMPTE offset meta event (0x54), the time code tick 0 of the file
+/// should be played at.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SmpteOffset {
+    /// 24, 25, 29 for 29.97 drop-frame, or 30.
+    pub frames_per_second: u8,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+    /// Hundredths of a frame.
+    pub subframes: u8,
+}
+
+impl SmpteOffset {
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        match *data {
+            [hr, minutes, seconds, frames, subframes, ..] => Some(Self {
+                // The frame rate is in bits 5 and 6 of the hours
+                frames_per_second: [24, 25, 29, 30][usize::from(hr >> 5 & 0x03)],
+                hours: hr & 0x1F,
+                minutes,
+                seconds,
+                frames,
+                subframes,
+            }),
+            _ => None,
+        }
+    }
+
+    /// How long after the start of the time code the offset is.
+    pub fn duration(&self) -> Duration {
+        // Drop-frame time code runs at 30000/1001 frames per second
+        let (rate, scale) = match self.frames_per_second {
+            29 => (30_000u64, 1_001u64),
+            fps => (u64::from(fps), 1),
+        };
+        let seconds =
+            u64::from(self.hours) * 3_600 + u64::from(self.minutes) * 60 + u64::from(self.seconds);
+        let subframes = u64::from(self.frames) * 100 + u64::from(self.subframes);
+        Duration::from_secs(seconds)
+            + Duration::from_nanos(subframes * 10_000_000 * scale / rate)
+    }
+}
+
+impl fmt::Display for SmpteOffset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}:{:02}:{:02}.{:02} ({} fps)",
+            self.hours,
+            self.minutes,
+            self.seconds,
+            self.frames,
+            self.subframes,
+            if self.frames_per_second == 29 {
+                "29.97 drop-frame".to_string()
+            } else {
+                self.frames_per_second.to_string()
+            }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct ParsedMidi {
-    pub events: Vec<Event>,
+    /// All events in tick order. Events at the same tick are ordered
+    /// deterministically: tempo changes first, then the other events by
+    /// track number, each track keeping its own order. Tempo changes at the
+    /// same tick are ordered the same way, so the last one in that order is
+    /// the tempo in effect.
+    ///
+    /// Across tracks, Note Ons at a tick come after the other events of the
+    /// tick, so a note retriggered by another track isn't cut off by the
+    /// Note Off of the previous one.
+    pub events: Events,
+    /// `(event index, ticks to wait after it)`. Gaps longer than `u32::MAX`
+    /// ticks are split over several entries with the same index, see `gaps`.
     pub deltas: Vec<(u32, u32)>,
+    /// The tick reached after every `TICK_INDEX_STRIDE` entries of `deltas`,
+    /// so `seek_to_tick` only walks a few entries.
+    pub(crate) tick_index: Vec<u64>,
+    /// `first_tick` and the length of `deltas` when `tick_index` was built.
+    /// The index isn't used when they no longer match.
+    pub(crate) tick_index_source: (u64, usize),
+    /// Tick of the first event in the file. Silence before it is skipped, so
+    /// the ticks counted through `deltas` start here.
+    pub first_tick: u64,
     pub total_ticks: u64,
     pub total_duration: Duration,
     pub note_count: u64,
+    /// Note Ons per channel.
+    pub channel_note_counts: [u64; 16],
+    /// Note Ons per track, indexed like the tracks of the file.
+    pub track_note_counts: Vec<u64>,
+    /// The most notes sounding at once, counting every Note On of a key
+    /// that is already held as another voice.
+    pub max_polyphony: u64,
+    /// The first tick at which `max_polyphony` is reached.
+    pub max_polyphony_tick: u64,
+    /// `(tick, microseconds per quarter note, time since the start)` of
+    /// each tempo segment, in tick order. The first segment starts at
+    /// `first_tick` with the default 500000 until the file sets a tempo, and
+    /// the times are measured from there like `total_duration`. Of several
+    /// tempo changes at the same tick, only the one in effect is kept.
+    pub tempo_map: Vec<(u64, u64, Duration)>,
+    /// The exact length in microseconds. `total_duration` is the same length
+    /// unless it's longer than a `Duration` can hold.
+    pub total_us: u128,
+    /// Set when `total_duration` was too large to represent and got clamped
+    /// to `Duration::MAX`, which means the file is malformed.
+    pub duration_saturated: bool,
+    /// Text meta events with their tick, in tick order.
+    pub meta_texts: Vec<(u64, MetaText)>,
+    pub time_signatures: Vec<(u64, TimeSignature)>,
+    pub key_signatures: Vec<(u64, KeySignature)>,
+    /// The output port of each track, from its first port meta event (0x21),
+    /// indexed like the tracks of the file. `None` if a track doesn't set
+    /// one. See `output::PortRouter` to play each port on its own output.
+    pub track_ports: Vec<Option<u8>>,
+    /// The SMPTE offset at tick 0 of the lowest track that has one. Offsets
+    /// later in a track are ignored, as the standard only allows them
+    /// before the first event.
+    pub smpte_offset: Option<SmpteOffset>,
+    /// SysEx message bytes, referenced by `long_msgs`.
+    pub long_data: Vec<u8>,
+    /// `(offset, len)` of each SysEx message in `long_data`.
+    pub long_msgs: Vec<(u32, u32)>,
+    /// Problems in corrupt tracks, in track order.
+    pub warnings: Vec<ParseWarning>,
+}
+
+impl ParsedMidi {
+    /// The channel with the most Note Ons and its count, or `None` if the
+    /// file has no notes. Ties go to the lowest channel.
+    pub fn busiest_channel(&self) -> Option<(u8, u64)> {
+        let (channel, &count) = self
+            .channel_note_counts
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|&(_, count)| count)?;
+        (count > 0).then_some((channel as u8, count))
+    }
+
+    /// The delta table with split entries combined: the index of each event
+    /// that is followed by a gap, and the length of the gap in ticks.
+    pub fn gaps(&self) -> impl Iterator<Item = (u32, u64)> + '_ {
+        let mut deltas = self.deltas.iter().peekable();
+        std::iter::from_fn(move || {
+            let &(idx, ticks) = deltas.next()?;
+            let mut total = u64::from(ticks);
+            while let Some(&(_, more)) = deltas.next_if(|&&(next, _)| next == idx) {
+                total += u64::from(more);
+            }
+            Some((idx, total))
+        })
+    }
+
+    /// The index of the first event at or after `tick`. Ticks before the
+    /// first event give 0, and ticks after the last one give `events.len()`.
+    ///
+    /// This is a binary search over a tick sampled every `TICK_INDEX_STRIDE`
+    /// delta entries, followed by a linear walk of up to that many entries,
+    /// see `seek_to_tick`.
+    pub fn index_at_tick(&self, tick: u64) -> usize {
+        seek_to_tick(self, tick).0
+    }
+
+    /// Recompute the index `seek_to_tick` uses from `deltas`. Needed after
+    /// changing entries of `deltas` in place; a changed `first_tick` or
+    /// length of `deltas` is noticed and falls back to walking the table.
+    pub fn rebuild_tick_index(&mut self) {
+        self.tick_index_source = (self.first_tick, self.deltas.len());
+        let mut tick = self.first_tick;
+        self.tick_index = self
+            .deltas
+            .chunks_exact(TICK_INDEX_STRIDE)
+            .map(|chunk| {
+                tick += chunk.iter().map(|&(_, ticks)| u64::from(ticks)).sum::<u64>();
+                tick
+            })
+            .collect();
+    }
+
+    /// Every event with the tick it is played at, walking the delta table
+    /// once. The first event is at `first_tick`.
+    pub fn iter_with_ticks(&self) -> impl Iterator<Item = (u64, Event)> + '_ {
+        let mut gaps = self.gaps().peekable();
+        let mut tick = self.first_tick;
+        self.events.iter().enumerate().map(move |(idx, event)| {
+            let event_tick = tick;
+            if let Some((_, ticks)) = gaps.next_if(|&(i, _)| i as usize == idx) {
+                tick += ticks;
+            }
+            (event_tick, event)
+        })
+    }
+
+    /// The time `tick` is played at, from the start of playback, with every
+    /// tempo change before it applied. Ticks before `first_tick` are at zero.
+    pub fn tick_to_duration(&self, time_div: u16, tick: u64) -> Duration {
+        let segment = self.tempo_map.partition_point(|&(start, _, _)| start <= tick);
+        let Some(&(start, us_per_qn, time)) = segment.checked_sub(1).map(|i| &self.tempo_map[i])
+        else {
+            return Duration::ZERO;
+        };
+        let us = TimeDivision::from_raw(time_div).ticks_to_us(tick - start, us_per_qn);
+        time.saturating_add(duration_from_us(us))
+    }
+
+    /// The last tick played at or before `time` from the start of playback,
+    /// the inverse of `tick_to_duration`. Times past the end keep counting
+    /// at the last tempo.
+    pub fn duration_to_tick(&self, time_div: u16, time: Duration) -> u64 {
+        let segment = self.tempo_map.partition_point(|&(_, _, start)| start <= time);
+        let Some(&(start, us_per_qn, start_time)) =
+            segment.checked_sub(1).map(|i| &self.tempo_map[i])
+        else {
+            return self.first_tick;
+        };
+        let us = (time - start_time).as_micros();
+        start.saturating_add(TimeDivision::from_raw(time_div).us_to_ticks(us, us_per_qn))
+    }
+
+    /// The SysEx message referenced by a long event, starting with 0xF0, or
+    /// the raw bytes of an 0xF7 escape event that aren't short messages.
+    pub fn long_message(&self, data: u32) -> Option<&[u8]> {
+        let (offset, len) = *self.long_msgs.get((data >> 8) as usize)?;
+        self.long_data
+            .get(offset as usize..(offset as usize + len as usize))
+    }
 }
 
 #[derive(Debug, Clone)]
 struct TrackEvents {
     events: Vec<(u64, Event)>, // (tick, event)
     tempo_changes: Vec<(u64, u64)>, // (tick, us_per_qn)
+    meta_texts: Vec<(u64, MetaText)>,
+    time_signatures: Vec<(u64, TimeSignature)>,
+    key_signatures: Vec<(u64, KeySignature)>,
+    smpte_offset: Option<SmpteOffset>,
+    port: Option<u8>,
+    sysex: Vec<Vec<u8>>,
     note_count: u64,
+    channel_note_counts: [u64; 16],
     max_tick: u64,
+    warnings: Vec<ParseWarning>,
 }
 
 fn parse_single_track(mut track: TrackData, track_idx: u16, time_div: u16) -> TrackEvents {
     let mut events = Vec::with_capacity(4096);
     let mut tempo_changes = Vec::with_capacity(16);
+    let mut meta_texts = Vec::new();
+    let mut time_signatures = Vec::new();
+    let mut key_signatures = Vec::new();
+    let mut smpte_offset = None;
+    let mut port = None;
+    // Channel of the channel prefix in effect for meta events
+    let mut channel_prefix = None;
+    let mut sysex = Vec::new();
+    // A SysEx message split over F7 continuation packets, kept at its first tick
+    let mut pending_sysex: Option<(u64, Vec<u8>)> = None;
     let mut note_count = 0u64;
+    let mut channel_note_counts = [0u64; 16];
     let mut max_tick = 0u64;
     let mut bpm_us_per_qn = 500_000u64;
+    let mut warnings = Vec::new();
+    let mut warn = |tick, message| {
+        warnings.push(ParseWarning {
+            track: track_idx,
+            tick,
+            message,
+        })
+    };
 
-    while track.length > 0 {
+    // Tracks without an End of Track event stop at the end of their data
+    while !track.is_finished() {
         let current_tick = track.tick;
         max_tick = max_tick.max(current_tick);
 
         track.update_command();
         track.update_message();
+        if track.length == 0 {
+            // The data ended mid-message
+            break;
+        }
 
         let message = track.message;
         let status = (message & 0xFF) as u8;
 
         if status < 0xF0 {
             // Regular MIDI message
-            if (0x90..=0x9F).contains(&status) {
-                let velocity = ((message >> 16) & 0xFF) as u8;
-                if velocity > 0 {
-                    note_count += 1;
-                }
+            channel_prefix = None;
+            if event_kind(message) == EventKind::NoteOn {
+                note_count += 1;
+                channel_note_counts[(message & 0x0F) as usize] += 1;
             }
 
-            events.push((
-                current_tick,
-                Event {
-                    data: message,
-                    track: track_idx,
-                    is_tempo: false,
-                },
-            ));
+            events.push((current_tick, Event::new(message, track_idx)));
+        } else if status == 0xF0 {
+            let mut message = Vec::with_capacity(track.long_msg.len() + 1);
+            message.push(0xF0);
+            message.extend_from_slice(&track.long_msg);
+
+            if message.ends_with(&[0xF7]) {
+                push_sysex(&mut events, &mut sysex, current_tick, message, track_idx);
+            } else {
+                pending_sysex = Some((current_tick, message));
+            }
+        } else if status == 0xF7 {
+            // Continuation packet of a SysEx, or else an escape of raw bytes
+            // to send as they are, like MIDI clock or MTC quarter frames
+            if let Some((_, message)) = pending_sysex.as_mut() {
+                message.extend_from_slice(&track.long_msg);
+                if message.ends_with(&[0xF7])
+                    && let Some((tick, message)) = pending_sysex.take()
+                {
+                    push_sysex(&mut events, &mut sysex, tick, message, track_idx);
+                }
+            } else if let Some(messages) = split_short_messages(&track.long_msg) {
+                for message in messages {
+                    if event_kind(message) == EventKind::NoteOn {
+                        note_count += 1;
+                        channel_note_counts[(message & 0x0F) as usize] += 1;
+                    }
+                    events.push((current_tick, Event::new(message, track_idx)));
+                }
+            } else {
+                let message = track.long_msg.clone();
+                push_sysex(&mut events, &mut sysex, current_tick, message, track_idx);
+            }
         } else if status == 0xFF {
             // Meta event - check for tempo
             let mut multiplier = 0.0f64;
@@ -75,363 +651,1952 @@ fn parse_single_track(mut track: TrackData, track_idx: u16, time_div: u16) -> Tr
             if bpm_us_per_qn != old_bpm {
                 tempo_changes.push((current_tick, bpm_us_per_qn));
             }
+
+            let meta_type = ((message >> 8) & 0xFF) as u8;
+            if meta_type == 0x51 && track.long_msg.len() < 3 {
+                warn(current_tick, "tempo event shorter than 3 bytes");
+            }
+            if let Some(kind) = MetaTextKind::from_meta_type(meta_type) {
+                meta_texts.push((
+                    current_tick,
+                    MetaText {
+                        kind,
+                        track: track_idx,
+                        channel: channel_prefix,
+                        text: decode_meta_text(&track.long_msg),
+                    },
+                ));
+            } else if meta_type == 0x58
+                && let Some(sig) = TimeSignature::from_bytes(&track.long_msg)
+            {
+                time_signatures.push((current_tick, sig));
+            } else if meta_type == 0x59
+                && let Some(sig) = KeySignature::from_bytes(&track.long_msg)
+            {
+                key_signatures.push((current_tick, sig));
+            } else if meta_type == 0x54 && current_tick == 0 && smpte_offset.is_none() {
+                smpte_offset = SmpteOffset::from_bytes(&track.long_msg);
+            } else if meta_type == 0x20
+                && let Some(&channel) = track.long_msg.first()
+            {
+                channel_prefix = Some(channel & 0x0F);
+            } else if meta_type == 0x21
+                && port.is_none()
+                && let Some(&number) = track.long_msg.first()
+            {
+                port = Some(number & 0x7F);
+            }
         }
 
         track.update_tick();
     }
 
+    if let Some(problem) = track.problem {
+        warn(track.tick, problem);
+    }
+    if let Some((tick, _)) = pending_sysex {
+        warn(tick, "SysEx message without an end");
+    }
     events.shrink_to_fit();
     tempo_changes.shrink_to_fit();
 
     TrackEvents {
         events,
         tempo_changes,
+        meta_texts,
+        time_signatures,
+        key_signatures,
+        smpte_offset,
+        port,
+        sysex,
         note_count,
+        channel_note_counts,
         max_tick,
+        warnings,
     }
 }
 
-pub fn parse_midi_events(tracks: Vec<TrackData>, time_div: u16) -> ParsedMidi {
-    let total_tracks = tracks.len();
-    
-    if tracks.is_empty() {
-        return ParsedMidi {
-            events: Vec::new(),
-            deltas: Vec::new(),
-            total_ticks: 0,
-            total_duration: Duration::ZERO,
-            note_count: 0,
-        };
+/// Convert microseconds to a duration, saturating at `Duration::MAX`.
+fn duration_from_us(us: u128) -> Duration {
+    match u64::try_from(us / 1_000_000) {
+        Ok(secs) => Duration::new(secs, (us % 1_000_000) as u32 * 1000),
+        Err(_) => Duration::MAX,
     }
+}
 
-    println!("\r\x1b[KParsing {} tracks in parallel...", total_tracks);
-    io::stdout().flush().unwrap();
-
-    let finished_counter = AtomicU64::new(0);
+/// Add a gap of `ticks` after event `idx` to the delta table, split over
+/// several entries if it doesn't fit in one so no time is lost.
+pub(crate) fn push_gap(deltas: &mut Vec<(u32, u32)>, idx: u32, ticks: u64) {
+    let mut remaining = ticks;
+    while remaining > 0 {
+        let part = remaining.min(u32::MAX as u64);
+        deltas.push((idx, part as u32));
+        remaining -= part;
+    }
+}
 
-    let track_results: Vec<TrackEvents> = tracks
-        .into_par_iter()
-        .enumerate()
-        .map(|(idx, track)| {
-            let result = parse_single_track(track, idx as u16, time_div);
-            
-            let finished = finished_counter.fetch_add(1, Ordering::Relaxed) + 1;
-            print!(
-                "\r\x1b[KFinished track {}/{} -> {} events parsed",
-                finished,
-                total_tracks,
-                result.events.len().separate_with_commas()
+/// Copy `parsed` with `extra` events added, given by tick and in order. The
+/// file's events come before the extra ones at the same tick. The note
+/// counts and statistics of the copy still describe the file alone.
+pub(crate) fn insert_events(parsed: &ParsedMidi, extra: Vec<(u64, Event)>) -> ParsedMidi {
+    // Built field by field so the events and deltas aren't copied just to be
+    // replaced
+    let mut merged = ParsedMidi {
+        events: Events::with_capacity(parsed.events.len() + extra.len()),
+        deltas: Vec::with_capacity(parsed.deltas.len() + extra.len()),
+        tick_index: Vec::new(),
+        tick_index_source: (0, 0),
+        first_tick: parsed.first_tick,
+        total_ticks: parsed.total_ticks,
+        total_duration: parsed.total_duration,
+        note_count: parsed.note_count,
+        channel_note_counts: parsed.channel_note_counts,
+        track_note_counts: parsed.track_note_counts.clone(),
+        max_polyphony: parsed.max_polyphony,
+        max_polyphony_tick: parsed.max_polyphony_tick,
+        tempo_map: parsed.tempo_map.clone(),
+        total_us: parsed.total_us,
+        duration_saturated: parsed.duration_saturated,
+        meta_texts: parsed.meta_texts.clone(),
+        time_signatures: parsed.time_signatures.clone(),
+        key_signatures: parsed.key_signatures.clone(),
+        track_ports: parsed.track_ports.clone(),
+        smpte_offset: parsed.smpte_offset,
+        long_data: parsed.long_data.clone(),
+        long_msgs: parsed.long_msgs.clone(),
+        warnings: parsed.warnings.clone(),
+    };
+    let mut song = parsed.iter_with_ticks().peekable();
+    let mut extra = extra.into_iter().peekable();
+    let mut prev_tick = 0;
+    while let Some((tick, event)) = match (song.peek(), extra.peek()) {
+        (Some(&(a, _)), Some(&(b, _))) if b < a => extra.next(),
+        (Some(_), _) => song.next(),
+        (None, _) => extra.next(),
+    } {
+        if merged.events.is_empty() {
+            merged.first_tick = tick;
+        } else if tick > prev_tick {
+            push_gap(
+                &mut merged.deltas,
+                (merged.events.len() - 1) as u32,
+                tick - prev_tick,
             );
-            io::stdout().flush().unwrap();
-            
-            result
-        })
-        .collect();
-
-    println!("\r\x1b[KMerging events from {} tracks...", total_tracks);
-    io::stdout().flush().unwrap();
-
-    // Calculate total events needed
-    let total_event_count: usize = track_results.iter().map(|t| t.events.len()).sum();
-    let total_tempo_changes: usize = track_results.iter().map(|t| t.tempo_changes.len()).sum();
-    
-    let mut all_events = Vec::with_capacity(total_event_count + total_tempo_changes);
-    let note_count: u64 = track_results.iter().map(|t| t.note_count).sum();
-    let total_ticks = track_results.iter().map(|t| t.max_tick).max().unwrap_or(0);
-
-    // Merge tempo changes
-    for track_result in &track_results {
-        for &(tick, us_per_qn) in &track_result.tempo_changes {
-            all_events.push((
-                tick,
-                Event {
-                    data: us_per_qn as u32,
-                    track: 0, // tempo events don't need track info
-                    is_tempo: true,
-                },
-            ));
         }
+        prev_tick = tick;
+        merged.events.push(event);
     }
+    merged.rebuild_tick_index();
+
+    merged
+}
 
-    // Merge regular events
-    for track_result in track_results {
-        all_events.extend(track_result.events);
+/// Store a complete SysEx message and add its long event, indexed locally
+/// to the track. Indices are made global when the tracks are merged.
+fn push_sysex(
+    events: &mut Vec<(u64, Event)>,
+    sysex: &mut Vec<Vec<u8>>,
+    tick: u64,
+    message: Vec<u8>,
+    track_idx: u16,
+) {
+    if sysex.len() >= MAX_LONG_MSGS {
+        return;
     }
+    events.push((
+        tick,
+        Event::new(LONG_STATUS | ((sysex.len() as u32) << 8), track_idx),
+    ));
+    sysex.push(message);
+}
 
-    // Sort all events by tick (stable sort to preserve track order for same tick)
-    all_events.par_sort_by_key(|&(tick, _)| tick);
+/// How far the parser has got, reported to the progress callback of the
+/// `*_with_progress` functions.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParseProgress {
+    /// `done` of `total` tracks have been parsed, the last one having
+    /// `events` events. Reported once with `done` at 0 before the first
+    /// track, then after each track, from whichever thread parsed it.
+    TracksParsed {
+        done: usize,
+        total: usize,
+        events: usize,
+    },
+    /// The events of all tracks are being merged.
+    Merging { tracks: usize },
+    /// The delta table is being built.
+    BuildingDeltas,
+    /// Parsing is complete.
+    Done { events: usize, notes: u64 },
+    /// Something is wrong with the file, which is read anyway. Only reported
+    /// by `load_and_parse_with_progress`, the loaders return their warnings.
+    Warning(LoadWarning),
+}
 
-    println!("\r\x1b[KBuilding delta table...");
-    io::stdout().flush().unwrap();
+/// Receives `ParseProgress` updates. Called from the parser's worker threads.
+pub type ProgressFn<'a> = &'a (dyn Fn(ParseProgress) + Sync);
 
-    // Build final events and deltas
-    let mut events = Vec::with_capacity(all_events.len());
-    let mut deltas = Vec::with_capacity(all_events.len() / 10);
-    
-    let mut prev_tick = 0u64;
-    let mut bpm_us_per_qn = 500_000u64;
-    let mut total_us_acc = 0u128;
-    
-    for (i, (tick, event)) in all_events.iter().enumerate() {
-        if *tick > prev_tick {
-            let delta_tick = tick - prev_tick;
-            
-            if i > 0 {
-                deltas.push(((i - 1) as u32, delta_tick.min(u32::MAX as u64) as u32));
-            }
-            
-            total_us_acc += (delta_tick as u128) * (bpm_us_per_qn as u128) / (time_div as u128);
-            prev_tick = *tick;
-        }
-        
-        if event.is_tempo {
-            bpm_us_per_qn = event.data as u64;
-        }
-        
-        events.push(*event);
+/// Ignores progress, for the functions that don't take a callback.
+fn no_progress(_: ParseProgress) {}
+
+pub fn parse_midi_events(tracks: Vec<TrackData>, time_div: u16) -> ParsedMidi {
+    parse_midi_events_with_progress(tracks, time_div, &no_progress)
+}
+
+/// `parse_midi_events`, reporting progress to `progress`.
+pub fn parse_midi_events_with_progress(
+    tracks: Vec<TrackData>,
+    time_div: u16,
+    progress: ProgressFn,
+) -> ParsedMidi {
+    if tracks.is_empty() {
+        return ParsedMidi::default();
     }
 
-    let total_nanos = total_us_acc.saturating_mul(1000);
-    let total_duration = if total_nanos > (u64::MAX as u128) {
-        Duration::from_nanos(u64::MAX)
-    } else {
-        Duration::from_nanos(total_nanos as u64)
-    };
+    let track_results = parse_tracks_parallel(tracks, time_div, progress);
+    merge_track_events(track_results, time_div, progress)
+}
 
-    events.shrink_to_fit();
-    deltas.shrink_to_fit();
+/// Parse the patterns of a Format 2 file, placing each one after the end of
+/// the previous one instead of playing them all at once.
+pub fn parse_midi_patterns(tracks: Vec<TrackData>, time_div: u16) -> ParsedMidi {
+    parse_midi_patterns_with_progress(tracks, time_div, &no_progress)
+}
 
-    println!(
-        "\r\x1b[KParsing complete: {} total events, {} notes",
-        events.len().separate_with_commas(),
-        note_count.separate_with_commas()
-    );
-    io::stdout().flush().unwrap();
+/// `parse_midi_patterns`, reporting progress to `progress`.
+pub fn parse_midi_patterns_with_progress(
+    tracks: Vec<TrackData>,
+    time_div: u16,
+    progress: ProgressFn,
+) -> ParsedMidi {
+    if tracks.is_empty() {
+        return ParsedMidi::default();
+    }
 
-    ParsedMidi {
-        events,
-        deltas,
-        total_ticks,
-        total_duration,
-        note_count,
+    let mut track_results = parse_tracks_parallel(tracks, time_div, progress);
+    chain_patterns(&mut track_results);
+    merge_track_events(track_results, time_div, progress)
+}
+
+/// Shift every pattern to start at the tick where the previous one ends.
+fn chain_patterns(track_results: &mut [TrackEvents]) {
+    let mut offset = 0u64;
+    for result in track_results {
+        for (tick, _) in &mut result.events {
+            *tick = tick.saturating_add(offset);
+        }
+        for (tick, _) in &mut result.tempo_changes {
+            *tick = tick.saturating_add(offset);
+        }
+        for (tick, _) in &mut result.meta_texts {
+            *tick = tick.saturating_add(offset);
+        }
+        for (tick, _) in &mut result.time_signatures {
+            *tick = tick.saturating_add(offset);
+        }
+        for (tick, _) in &mut result.key_signatures {
+            *tick = tick.saturating_add(offset);
+        }
+        for warning in &mut result.warnings {
+            warning.tick = warning.tick.saturating_add(offset);
+        }
+        // Only the first pattern starts at tick 0
+        if offset > 0 {
+            result.smpte_offset = None;
+        }
+        result.max_tick = result.max_tick.saturating_add(offset);
+        offset = result.max_tick;
     }
 }
 
-pub fn play_parsed_events(
-    parsed: &ParsedMidi,
+fn parse_tracks_parallel(
+    tracks: Vec<TrackData>,
     time_div: u16,
-    mut send_direct_data: impl FnMut(u32, u16) + Send + 'static,
-    delay_fn: Option<Box<dyn FnMut(i64) + Send + 'static>>,
-) {
-    if parsed.events.is_empty() {
-        return;
-    }
+    progress: ProgressFn,
+) -> Vec<TrackEvents> {
+    let total_tracks = tracks.len();
 
-    let default_delay = |ns: i64| delay_execution_100ns(ns);
-    let mut delay_fn = match delay_fn {
-        Some(f) => f,
-        None => Box::new(move |ns| default_delay(ns)),
-    };
+    progress(ParseProgress::TracksParsed {
+        done: 0,
+        total: total_tracks,
+        events: 0,
+    });
 
-    let mut bpm_us_per_qn: u64;
-    let mut tick: u64 = 0;
-    let mut multiplier: f64 = 0.0;
-    let max_drift: i64 = 100_000;
-    let mut old: i64 = 0;
-    let mut delta: i64 = 0;
-    let mut last_time = get_time_100ns();
+    let finished_counter = AtomicU64::new(0);
 
-    let mut i = 0;
-    let n = parsed.events.len();
-    let mut delta_idx = 0;
-    let n_deltas = parsed.deltas.len();
+    tracks
+        .into_par_iter()
+        .enumerate()
+        .map(|(idx, track)| {
+            let result = parse_single_track(track, idx as u16, time_div);
 
-    while i < n {
-        loop {
-            let packed = unsafe { *parsed.events.get_unchecked(i) };
-            let data = packed.data;
-            let is_tempo = packed.is_tempo;
+            let finished = finished_counter.fetch_add(1, Ordering::Relaxed) + 1;
+            progress(ParseProgress::TracksParsed {
+                done: finished as usize,
+                total: total_tracks,
+                events: result.events.len(),
+            });
 
-            if is_tempo {
-                bpm_us_per_qn = data as u64;
-                multiplier = (bpm_us_per_qn as f64) / (time_div as f64) * 10.0;
-            } else {
-                send_direct_data(data, packed.track);
-            }
+            result
+        })
+        .collect()
+}
 
-            if delta_idx < n_deltas {
-                let (idx, delta_ticks) = unsafe { *parsed.deltas.get_unchecked(delta_idx) };
-                if idx == i as u32 {
-                    let delta_tick = delta_ticks as u64;
-                    tick = tick.wrapping_add(delta_tick);
+/// Load and parse a MIDI file in one go.
+///
+/// Each track is handed to the parser as soon as it has been read, so
+/// reading the file overlaps with parsing. Returns the parsed events and the
+/// raw time division. The patterns of Format 2 files are played one after
+/// another, like `parse_midi_patterns` does. Warnings about the file are
+/// dropped, `load_and_parse_with_progress` reports them.
+pub fn load_and_parse<P: AsRef<Path>>(
+    filename: P,
+) -> Result<(ParsedMidi, u16), MidiLoadError> {
+    load_and_parse_with_progress(filename, &no_progress)
+}
 
-                    let now = get_time_100ns();
-                    let elapsed = (now - last_time) as i64;
-                    last_time = now;
+/// `load_and_parse`, reporting progress to `progress`.
+pub fn load_and_parse_with_progress<P: AsRef<Path>>(
+    filename: P,
+    progress: ProgressFn,
+) -> Result<(ParsedMidi, u16), MidiLoadError> {
+    let mut reader = decompressing_reader(BufReader::new(File::open(filename)?))?;
+    let mut warnings = Vec::new();
+    let (num_tracks, time_div, format) = read_header(&mut reader, &mut warnings)?;
+    for warning in warnings {
+        progress(ParseProgress::Warning(warning));
+    }
 
-                    let work_time = elapsed - old;
-                    old = (delta_tick as f64 * multiplier) as i64;
-                    delta = delta.wrapping_add(work_time);
+    progress(ParseProgress::TracksParsed {
+        done: 0,
+        total: num_tracks,
+        events: 0,
+    });
 
-                    let sleep_time = if delta > 0 { old - delta } else { old };
+    let (sender, receiver) = bounded::<(usize, TrackData)>(64);
+    let finished_counter = AtomicU64::new(0);
 
-                    if sleep_time <= 0 {
-                        delta = delta.min(max_drift);
-                    } else {
-                        delay_fn(sleep_time);
-                    }
+    let (read_result, mut track_results) = thread::scope(|scope| {
+        let loader = scope.spawn(move || {
+            read_tracks(&mut reader, num_tracks, |idx, track| {
+                // The receiver only goes away once all tracks are parsed
+                let _ = sender.send((idx, track));
+            })
+        });
 
-                    delta_idx += 1;
-                    i += 1;
-                    break;
-                }
-            }
+        let track_results: Vec<(usize, TrackEvents)> = receiver
+            .into_iter()
+            .par_bridge()
+            .map(|(idx, track)| {
+                let result = parse_single_track(track, idx as u16, time_div);
+
+                let finished = finished_counter.fetch_add(1, Ordering::Relaxed) + 1;
+                progress(ParseProgress::TracksParsed {
+                    done: finished as usize,
+                    total: num_tracks,
+                    events: result.events.len(),
+                });
+
+                (idx, result)
+            })
+            .collect();
+
+        let read_result = loader
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("Loader thread panicked").into()));
+        (read_result, track_results)
+    });
+    for warning in read_result? {
+        progress(ParseProgress::Warning(warning));
+    }
 
-            i += 1;
-            if i >= n {
-                break;
-            }
-        }
+    if track_results.is_empty() {
+        return Ok((ParsedMidi::default(), time_div));
+    }
 
-        if i >= n {
-            break;
-        }
+    // Tracks finish in any order, put them back in file order
+    track_results.sort_unstable_by_key(|&(idx, _)| idx);
+    let mut track_results: Vec<TrackEvents> =
+        track_results.into_iter().map(|(_, result)| result).collect();
+    if format == MidiFormat::Sequential {
+        chain_patterns(&mut track_results);
     }
+
+    Ok((merge_track_events(track_results, time_div, progress), time_div))
 }
 
-#[derive(Copy, Clone, Debug)]
-pub struct UnpackedEvent {
-    pub idx: u32,
-    pub data: u32,
-    pub track: u16,
-    pub is_tempo: bool,
+/// Parse all tracks on the calling thread.
+///
+/// Produces the same output as `parse_midi_events` without using rayon.
+pub fn parse_midi_events_single_threaded(tracks: Vec<TrackData>, time_div: u16) -> ParsedMidi {
+    parse_midi_events_single_threaded_with_progress(tracks, time_div, &no_progress)
+}
+
+/// `parse_midi_events_single_threaded`, reporting progress to `progress`.
+pub fn parse_midi_events_single_threaded_with_progress(
+    tracks: Vec<TrackData>,
+    time_div: u16,
+    progress: ProgressFn,
+) -> ParsedMidi {
+    let total_tracks = tracks.len();
+
+    if tracks.is_empty() {
+        return ParsedMidi::default();
+    }
+
+    progress(ParseProgress::TracksParsed {
+        done: 0,
+        total: total_tracks,
+        events: 0,
+    });
+
+    let track_results: Vec<TrackEvents> = tracks
+        .into_iter()
+        .enumerate()
+        .map(|(idx, track)| {
+            let result = parse_single_track(track, idx as u16, time_div);
+
+            progress(ParseProgress::TracksParsed {
+                done: idx + 1,
+                total: total_tracks,
+                events: result.events.len(),
+            });
+
+            result
+        })
+        .collect();
+
+    merge_track_events(track_results, time_div, progress)
+}
+
+/// Merge per-track results into a single tick-ordered event list and build
+/// the delta table.
+///
+/// Every track's events are already in tick order, so they are combined with
+/// a k-way merge instead of collecting and sorting all of them, which would
+/// need a second copy of every event.
+fn merge_track_events(
+    mut track_results: Vec<TrackEvents>,
+    time_div: u16,
+    progress: ProgressFn,
+) -> ParsedMidi {
+    let total_tracks = track_results.len();
+
+    progress(ParseProgress::Merging {
+        tracks: total_tracks,
+    });
+
+    // Calculate total events needed
+    let total_event_count: usize = track_results.iter().map(|t| t.events.len()).sum();
+    let total_tempo_changes: usize = track_results.iter().map(|t| t.tempo_changes.len()).sum();
+
+    let note_count: u64 = track_results.iter().map(|t| t.note_count).sum();
+    let track_note_counts: Vec<u64> = track_results.iter().map(|t| t.note_count).collect();
+    let mut channel_note_counts = [0u64; 16];
+    for track_result in &track_results {
+        for (total, count) in channel_note_counts.iter_mut().zip(track_result.channel_note_counts) {
+            *total += count;
+        }
+    }
+    let total_ticks = track_results.iter().map(|t| t.max_tick).max().unwrap_or(0);
+
+    // Merge text meta events
+    let mut meta_texts: Vec<(u64, MetaText)> = Vec::new();
+    for track_result in &mut track_results {
+        meta_texts.append(&mut track_result.meta_texts);
+    }
+    meta_texts.sort_by_key(|&(tick, _)| tick);
+
+    // Merge time and key signatures
+    let mut time_signatures = Vec::new();
+    let mut key_signatures = Vec::new();
+    for track_result in &mut track_results {
+        time_signatures.append(&mut track_result.time_signatures);
+        key_signatures.append(&mut track_result.key_signatures);
+    }
+    time_signatures.sort_by_key(|&(tick, _)| tick);
+    key_signatures.sort_by_key(|&(tick, _)| tick);
+    let smpte_offset = track_results.iter().find_map(|t| t.smpte_offset);
+    let track_ports = track_results.iter().map(|t| t.port).collect();
+
+    let warnings: Vec<ParseWarning> = track_results
+        .iter_mut()
+        .flat_map(|t| t.warnings.drain(..))
+        .collect();
+
+    // Merge SysEx messages into one buffer and point their events at it
+    let mut long_data = Vec::new();
+    let mut long_msgs: Vec<(u32, u32)> = Vec::new();
+    for track_result in &mut track_results {
+        let base = long_msgs.len();
+        for message in track_result.sysex.drain(..) {
+            long_msgs.push((long_data.len() as u32, message.len() as u32));
+            long_data.extend_from_slice(&message);
+        }
+
+        if base > 0 {
+            track_result.events.retain_mut(|(_, event)| {
+                if !event.is_long() {
+                    return true;
+                }
+                let idx = (event.data() >> 8) as usize + base;
+                *event = Event::new(LONG_STATUS | ((idx as u32) << 8), event.track());
+                idx < MAX_LONG_MSGS
+            });
+        }
+    }
+    long_msgs.truncate(MAX_LONG_MSGS);
+
+    progress(ParseProgress::BuildingDeltas);
+
+    // Merge the tempo changes and the tracks. At the same tick, tempo events
+    // come first, then tracks in file order, except that a track's next event
+    // waits while it is a Note On and other tracks still have Note Offs or
+    // controllers. Each track's own order is never changed.
+    //
+    // The tempo changes of each track are a source of their own, ahead of
+    // all the tracks, so they need no sorting and tempo changes at the same
+    // tick stay in track order.
+    let mut sources: Vec<std::vec::IntoIter<(u64, Event)>> = Vec::with_capacity(total_tracks * 2);
+    for track_result in &track_results {
+        if track_result.tempo_changes.is_empty() {
+            continue;
+        }
+        let tempo_events: Vec<(u64, Event)> = track_result
+            .tempo_changes
+            .iter()
+            .map(|&(tick, us_per_qn)| (tick, Event::tempo(us_per_qn as u32)))
+            .collect();
+        sources.push(tempo_events.into_iter());
+    }
+    sources.extend(track_results.into_iter().map(|t| t.events.into_iter()));
+
+    let mut heads: Vec<Option<Event>> = vec![None; sources.len()];
+    let mut heap = BinaryHeap::with_capacity(sources.len());
+    for (source, iter) in sources.iter_mut().enumerate() {
+        if let Some((tick, event)) = iter.next() {
+            heap.push(Reverse((tick, event.is_note_on(), source)));
+            heads[source] = Some(event);
+        }
+    }
+
+    let capacity = total_event_count + total_tempo_changes;
+    let mut events = Events::with_capacity(capacity);
+    let mut deltas = Vec::with_capacity(capacity / 10);
+
+    let mut prev_tick = 0u64;
+    let mut first_tick = 0u64;
+    let mut bpm_us_per_qn = 500_000u64;
+
+    // Voices held per channel and key
+    let mut held = vec![0u32; 16 * 128];
+    let mut voices = 0u64;
+    let mut max_polyphony = 0u64;
+    let mut max_polyphony_tick = 0u64;
+    let mut tempo_map: Vec<(u64, u64, Duration)> = Vec::with_capacity(total_tempo_changes + 1);
+
+    while let Some(Reverse((tick, _, source))) = heap.pop() {
+        let Some(event) = heads[source].take() else {
+            continue;
+        };
+        if let Some((next_tick, next)) = sources[source].next() {
+            heap.push(Reverse((next_tick, next.is_note_on(), source)));
+            heads[source] = Some(next);
+        }
+
+        if tick > prev_tick {
+            let delta_tick = tick - prev_tick;
+
+            if events.is_empty() {
+                first_tick = tick;
+            } else {
+                push_gap(&mut deltas, (events.len() - 1) as u32, delta_tick);
+            }
+            prev_tick = tick;
+        }
+
+        if events.is_empty() {
+            tempo_map.push((tick, bpm_us_per_qn, Duration::ZERO));
+        }
+
+        if event.is_tempo() {
+            bpm_us_per_qn = event.data() as u64;
+            match tempo_map.last_mut() {
+                Some(last) if last.0 == tick => last.1 = bpm_us_per_qn,
+                _ => tempo_map.push((tick, bpm_us_per_qn, Duration::ZERO)),
+            }
+        } else {
+            let key = ((event.data() & 0x0F) << 7 | (event.data() >> 8) & 0x7F) as usize;
+            match event_kind(event.data()) {
+                EventKind::NoteOn => {
+                    held[key] += 1;
+                    voices += 1;
+                    if voices > max_polyphony {
+                        max_polyphony = voices;
+                        max_polyphony_tick = tick;
+                    }
+                }
+                EventKind::NoteOff if held[key] > 0 => {
+                    held[key] -= 1;
+                    voices -= 1;
+                }
+                _ => {}
+            }
+        }
+
+        events.push(event);
+    }
+
+    // Integrate the tempo map now that the merge is done, which takes one
+    // step per tempo segment instead of one per gap
+    let division = TimeDivision::from_raw(time_div);
+    let mut total_us_acc = 0u128;
+    let mut segment = (first_tick, 500_000u64);
+    for (tick, us_per_qn, time) in &mut tempo_map {
+        total_us_acc += division.ticks_to_us(*tick - segment.0, segment.1);
+        *time = duration_from_us(total_us_acc);
+        segment = (*tick, *us_per_qn);
+    }
+    total_us_acc += division.ticks_to_us(prev_tick - segment.0, segment.1);
+
+    let total_duration = duration_from_us(total_us_acc);
+    let duration_saturated = total_duration == Duration::MAX;
+    tempo_map.shrink_to_fit();
+
+    events.shrink_to_fit();
+    deltas.shrink_to_fit();
+
+    progress(ParseProgress::Done {
+        events: events.len(),
+        notes: note_count,
+    });
+
+    let mut parsed = ParsedMidi {
+        events,
+        deltas,
+        tick_index: Vec::new(),
+        tick_index_source: (0, 0),
+        first_tick,
+        total_ticks,
+        total_duration,
+        note_count,
+        channel_note_counts,
+        track_note_counts,
+        max_polyphony,
+        max_polyphony_tick,
+        tempo_map,
+        total_us: total_us_acc,
+        duration_saturated,
+        meta_texts,
+        time_signatures,
+        key_signatures,
+        track_ports,
+        smpte_offset,
+        long_data,
+        long_msgs,
+        warnings,
+    };
+    parsed.rebuild_tick_index();
+    parsed
+}
+
+/// Commands that can be sent to a running player.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PlayerControl {
+    Pause,
+    Resume,
+    Stop,
+}
+
+/// What to do with notes that a transpose moves outside 0..=127.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum TransposeRange {
+    #[default]
+    Clamp,
+    Drop,
+}
+
+/// What the player does once it's behind schedule by more than
+/// `PlaybackOptions::catch_up_threshold`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum CatchUpMode {
+    /// Play every event, as fast as possible until caught up. Lag beyond
+    /// `PlaybackOptions::max_drift` is forgotten, so playback stays late.
+    #[default]
+    Absorb,
+    /// Skip pressure, pitch bend and controller messages until caught up,
+    /// except the switch pedals (CC 64-69) and channel mode messages. Their
+    /// next change brings the synth back in line.
+    SkipNonNote,
+    /// Also skip Note Ons until caught up. The Note Offs that match skipped
+    /// notes are skipped too, so no notes are left hanging.
+    SkipAll,
+}
+
+impl FromStr for CatchUpMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "absorb" => Ok(CatchUpMode::Absorb),
+            "skip-non-note" => Ok(CatchUpMode::SkipNonNote),
+            "skip-all" => Ok(CatchUpMode::SkipAll),
+            _ => Err(format!(
+                "Invalid catch-up mode '{}', expected absorb, skip-non-note or skip-all",
+                s.trim()
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CatchUpMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CatchUpMode::Absorb => "absorb",
+            CatchUpMode::SkipNonNote => "skip-non-note",
+            CatchUpMode::SkipAll => "skip-all",
+        })
+    }
+}
+
+/// Whether to skip `data` to catch up, see `CatchUpMode`. `skipped` counts
+/// the skipped Note Ons per channel and key, whose Note Offs are skipped
+/// even once playback is back on time.
+#[inline(always)]
+fn skip_to_catch_up(data: u32, mode: CatchUpMode, behind: bool, skipped: &mut [u32]) -> bool {
+    let key = ((data & 0x0F) << 7 | (data >> 8) & 0x7F) as usize;
+    match event_kind(data) {
+        EventKind::NoteOn if behind && mode == CatchUpMode::SkipAll => {
+            skipped[key] += 1;
+            true
+        }
+        EventKind::NoteOff if skipped[key] > 0 => {
+            skipped[key] -= 1;
+            true
+        }
+        EventKind::PolyPressure | EventKind::ChannelPressure | EventKind::PitchBend => behind,
+        EventKind::ControlChange => behind && !matches!((data >> 8) & 0x7F, 64..=69 | 120..),
+        _ => false,
+    }
+}
+
+/// How closely playback kept to the timing of the file, returned when
+/// `PlaybackOptions::timing_stats` is set.
+///
+/// Measured at each gap between events: the time the events before the gap
+/// were sent is compared to the time they were due, counted from the start
+/// of playback at the current speed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct PlaybackStats {
+    /// Number of gaps measured.
+    pub gaps: u64,
+    /// Sum of how far off the due time each measurement was, early or late.
+    pub total_error: Duration,
+    /// The latest events were sent.
+    pub max_lateness: Duration,
+    /// How often lag beyond `PlaybackOptions::max_drift` was dropped. Each
+    /// time, the rest of the file plays that much later than due.
+    pub drift_clamps: u64,
+}
+
+impl PlaybackStats {
+    /// The average of `total_error` over the gaps measured.
+    pub fn mean_error(&self) -> Duration {
+        match self.gaps {
+            0 => Duration::ZERO,
+            gaps => self.total_error / gaps.min(u32::MAX as u64) as u32,
+        }
+    }
+
+    /// Record a group of events sent `error` 100ns units after they were due.
+    fn record(&mut self, error: i64) {
+        self.gaps += 1;
+        self.total_error += duration_from_100ns(error.saturating_abs());
+        self.max_lateness = self.max_lateness.max(duration_from_100ns(error));
+    }
+}
+
+/// How the player reads the events it plays, see `PlaybackOptions::strategy`.
+/// Every playback option works the same with either.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlaybackStrategy {
+    /// Read each event from `ParsedMidi::events` as it is played.
+    #[default]
+    Direct,
+    /// Unpack the events on a second thread, `batch_size` at a time and up
+    /// to `lookahead` batches ahead of playback.
+    ///
+    /// Every batch is allocated up front, so this takes
+    /// `batch_size * lookahead * size_of::<UnpackedEvent>()` bytes: smaller
+    /// values save memory, larger ones let the unpacking thread stall for
+    /// longer without playback waiting on it. Zero is taken as one. See
+    /// `DEFAULT_BATCH_SIZE` and `DEFAULT_LOOKAHEAD_BATCHES`.
+    Batched { batch_size: usize, lookahead: usize },
+}
+
+/// Callback that receives complete SysEx messages and the track they are
+/// from, like `send_direct_data`.
+pub type SendLongDataFn = Box<dyn FnMut(&[u8], u16) + Send + 'static>;
+
+/// Optional behaviour for `play_parsed_events_with_options`.
+pub struct PlaybackOptions {
+    /// Called with the current tick and the elapsed wall time since playback
+    /// started, at most once per `progress_interval`.
+    pub progress: Option<Box<dyn FnMut(u64, Duration) + Send + 'static>>,
+    pub progress_interval: Duration,
+    /// Called with the new tempo in BPM whenever a tempo change is played,
+    /// and once after seeking if the tempo changed before the seek point.
+    pub on_tempo: Option<Box<dyn FnMut(f64) + Send + 'static>>,
+    /// Playback stops once this is set, after silencing all channels.
+    pub stop: Option<Arc<AtomicBool>>,
+    /// Pause, resume and stop commands, checked between delta blocks.
+    pub control: Option<Receiver<PlayerControl>>,
+    /// Tick to start playback from, see `seek_to_tick`.
+    pub start_tick: u64,
+    /// Wait for `ParsedMidi::smpte_offset` before the first event, so the
+    /// file starts where its time code says. The offset is the time of tick
+    /// 0, so it is only waited for when playing from the start: with a
+    /// `start_tick`, playback begins right away. Loops don't wait again.
+    pub smpte_offset: bool,
+    /// Tick to stop playback at. Events at or after it aren't played, and
+    /// notes still held there are cut off like at the end of the file.
+    ///
+    /// With `play_parsed_events_looped`, every pass seeks back to
+    /// `start_tick`, so this loops the region between the two.
+    pub end_tick: Option<u64>,
+    /// Bit `n` mutes channel `n`. Muted channels don't receive note or
+    /// controller messages, and get All Notes Off when they become muted.
+    pub channel_mute: Option<Arc<AtomicU16>>,
+    /// Note Ons from muted tracks are skipped. Tracks muted mid-playback get
+    /// All Notes Off on the channels they use.
+    pub track_mute: Option<Arc<TrackMask>>,
+    /// Live transpose in semitones for Note On and Note Off messages. All
+    /// notes are released whenever the value changes.
+    pub transpose: Option<Arc<AtomicI8>>,
+    pub transpose_range: TransposeRange,
+    /// Also transpose channel 10 (index 9), which holds GM percussion.
+    pub transpose_drums: bool,
+    /// Key each drum is played with on channel 10 (index 9), e.g. to play a
+    /// file written for one drum kit layout on another. Applied before
+    /// transposing. Unset leaves drums alone.
+    pub drum_map: Option<[u8; 128]>,
+    /// Live multiplier for Note On velocities, clamped to 127. Note Ons with
+    /// velocity 0 are left alone so notes still release.
+    pub velocity_scale: Option<Arc<AtomicF32>>,
+    /// Live playback speed, `2.0` plays twice as fast. Values that aren't
+    /// positive are ignored.
+    pub speed: Option<Arc<AtomicF32>>,
+    /// Channel each channel's messages are sent on, e.g. `map[0] = 3` plays
+    /// channel 0 on channel 3. Applied after muting and transposing, which
+    /// still see the channels of the file. Unset means every channel keeps
+    /// its own.
+    pub channel_map: Option<[u8; 16]>,
+    /// Receives SysEx messages, starting with 0xF0, and the bytes of 0xF7
+    /// escapes that aren't short messages. They are dropped if unset.
+    pub send_long_data: Option<SendLongDataFn>,
+    /// Don't silence all channels when playback reaches the end, so notes
+    /// that were never released keep ringing.
+    pub let_notes_ring: bool,
+    /// Clock used to schedule events, the system clock if unset. A virtual
+    /// clock should be advanced by the `delay_fn`.
+    pub clock: Option<Arc<dyn Clock + Send + Sync>>,
+    /// Play a metronome click on every beat, see `metronome::with_metronome`.
+    pub metronome: bool,
+    /// Send MIDI Clock, Start and Stop, see `clock::with_midi_clock`. After
+    /// seeking to a `start_tick`, Song Position Pointer and Continue are sent
+    /// instead of Start, and Stop is also sent when playback stops early or
+    /// at `end_tick`.
+    pub midi_clock: bool,
+    /// Fade out over this long before the end of the file by ramping
+    /// expression (CC 11) down to 0 on every channel the file uses.
+    ///
+    /// Expression changes in the file are ignored during the fade, and All
+    /// Sound Off is sent at the end even if `let_notes_ring` is set. Each
+    /// pass starts with full expression, so looped playback fades every pass.
+    pub fade_out: Option<Duration>,
+    /// How far behind schedule the player may fall and still catch up, see
+    /// `DEFAULT_MAX_DRIFT`. Only used with `CatchUpMode::Absorb`, the other
+    /// modes always catch up on all of it.
+    pub max_drift: Duration,
+    /// How to catch up once playback is more than `catch_up_threshold`
+    /// behind.
+    pub catch_up: CatchUpMode,
+    /// How far behind schedule playback may fall before `catch_up` starts
+    /// skipping messages, 50ms by default. Not used with
+    /// `CatchUpMode::Absorb`.
+    pub catch_up_threshold: Duration,
+    /// Measure the timing of playback, see `PlaybackStats`.
+    pub timing_stats: bool,
+    /// How events are read while playing.
+    pub strategy: PlaybackStrategy,
+}
+
+/// Default for `PlaybackOptions::max_drift`.
+///
+/// When sending events takes longer than the gaps between them, the player
+/// falls behind and makes up for it by shortening the next waits. Anything
+/// it lags beyond this is dropped instead, so after a stall it plays on at
+/// normal speed rather than rushing through events to get back in time.
+/// More allows for slow systems or busy synths, less keeps timing tight.
+pub const DEFAULT_MAX_DRIFT: Duration = Duration::from_millis(10);
+
+impl Default for PlaybackOptions {
+    fn default() -> Self {
+        Self {
+            progress: None,
+            progress_interval: Duration::from_millis(100),
+            on_tempo: None,
+            stop: None,
+            control: None,
+            start_tick: 0,
+            smpte_offset: false,
+            end_tick: None,
+            channel_mute: None,
+            track_mute: None,
+            transpose: None,
+            transpose_range: TransposeRange::Clamp,
+            transpose_drums: false,
+            drum_map: None,
+            velocity_scale: None,
+            speed: None,
+            channel_map: None,
+            send_long_data: None,
+            let_notes_ring: false,
+            clock: None,
+            metronome: false,
+            midi_clock: false,
+            fade_out: None,
+            max_drift: DEFAULT_MAX_DRIFT,
+            catch_up: CatchUpMode::Absorb,
+            catch_up_threshold: Duration::from_millis(50),
+            timing_stats: false,
+            strategy: PlaybackStrategy::Direct,
+        }
+    }
+}
+
+/// Track of the messages the player sends on its own, like All Notes Off
+/// and the expression of a fade. They aren't from any track of the file, so
+/// outputs that play tracks by port send them everywhere, see
+/// `output::PortRouter`.
+pub const PLAYER_TRACK: u16 = u16::MAX;
+
+/// Send All Sound Off (CC 120) and All Notes Off (CC 123) on all 16 channels.
+pub fn silence_all_channels(send_direct_data: &mut impl FnMut(u32, u16)) {
+    for channel in 0..16u8 {
+        send_direct_data(pack_short_message(0xB0 | channel, 120, 0), PLAYER_TRACK);
+        send_direct_data(pack_short_message(0xB0 | channel, 123, 0), PLAYER_TRACK);
+    }
+}
+
+/// Send All Notes Off (CC 123) on all 16 channels.
+pub fn all_notes_off(send_direct_data: &mut impl FnMut(u32, u16)) {
+    for channel in 0..16u8 {
+        send_direct_data(pack_short_message(0xB0 | channel, 123, 0), PLAYER_TRACK);
+    }
+}
+
+/// Build a channel mute mask that mutes every channel except `channels`.
+pub fn solo_channels(channels: &[u8]) -> u16 {
+    !channels
+        .iter()
+        .fold(0u16, |mask, &channel| mask | (1 << (channel & 0x0F)))
+}
+
+/// Whether `data` is a note or controller message on a muted channel.
+#[inline(always)]
+fn is_channel_muted(data: u32, muted_channels: u16) -> bool {
+    muted_channels != 0
+        && matches!(
+            event_kind(data),
+            EventKind::NoteOn
+                | EventKind::NoteOff
+                | EventKind::PolyPressure
+                | EventKind::ControlChange
+        )
+        && (muted_channels >> (data & 0x0F)) & 1 != 0
+}
+
+/// Whether `track` is set in a bitset of 64 tracks per word.
+#[inline(always)]
+fn track_bit(bits: &[u64], track: usize) -> bool {
+    bits.get(track / 64)
+        .is_some_and(|word| word & (1 << (track % 64)) != 0)
+}
+
+/// Whether `data` is a Note On from a track muted in `muted_tracks`.
+#[inline(always)]
+fn is_track_muted(data: u32, track: u16, muted_tracks: &[u64]) -> bool {
+    !muted_tracks.is_empty()
+        && event_kind(data) == EventKind::NoteOn
+        && track_bit(muted_tracks, track as usize)
+}
+
+/// Shift the key of a Note On/Off message by `semitones`.
+///
+/// Returns `None` if the note falls out of range and `range` is `Drop`.
+#[inline(always)]
+pub fn transpose_message(
+    data: u32,
+    semitones: i8,
+    range: TransposeRange,
+    include_drums: bool,
+) -> Option<u32> {
+    if semitones == 0
+        || !event_kind(data).is_note()
+        || (!include_drums && data & 0x0F == 9)
+    {
+        return Some(data);
+    }
+
+    let key = ((data >> 8) & 0x7F) as i16 + semitones as i16;
+    let key = match range {
+        TransposeRange::Clamp => key.clamp(0, 127),
+        TransposeRange::Drop if !(0..=127).contains(&key) => return None,
+        TransposeRange::Drop => key,
+    };
+    Some((data & !0xFF00) | ((key as u32) << 8))
+}
+
+/// Replace the key of a Note On/Off on channel 10 (index 9) with the one
+/// `map` gives for it. Other messages are returned unchanged.
+#[inline(always)]
+pub fn remap_drum_note(data: u32, map: &[u8; 128]) -> u32 {
+    if data & 0x0F != 9 || !event_kind(data).is_note() {
+        return data;
+    }
+    let key = map[((data >> 8) & 0x7F) as usize] & 0x7F;
+    (data & !0xFF00) | (u32::from(key) << 8)
+}
+
+/// Move a channel message to the channel `map` gives for its channel.
+/// Other messages are returned unchanged.
+#[inline(always)]
+pub fn remap_channel(data: u32, map: &[u8; 16]) -> u32 {
+    if !(0x80..0xF0).contains(&(data & 0xFF)) {
+        return data;
+    }
+    (data & !0x0F) | u32::from(map[(data & 0x0F) as usize] & 0x0F)
+}
+
+/// Scale the velocity of a Note On message by `scale`.
+///
+/// A scaled velocity never drops below 1, which would turn the Note On into
+/// a Note Off, unless `scale` is 0 and silences every note.
+#[inline(always)]
+pub fn scale_velocity(data: u32, scale: f32) -> u32 {
+    if scale == 1.0 || event_kind(data) != EventKind::NoteOn {
+        return data;
+    }
+    let velocity = (data >> 16) & 0xFF;
+
+    let min = if scale == 0.0 { 0.0 } else { 1.0 };
+    let scaled = (velocity as f32 * scale).round().clamp(min, 127.0) as u32;
+    (data & !0xFF_0000) | (scaled << 16)
+}
+
+/// Whether `data` is an expression (CC 11) change.
+#[inline(always)]
+fn is_expression_change(data: u32) -> bool {
+    event_kind(data) == EventKind::ControlChange && (data >> 8) & 0x7F == 11
+}
+
+/// Send expression (CC 11) `value` on every channel in `channels`.
+fn send_expression(channels: u16, value: u8, send_direct_data: &mut impl FnMut(u32, u16)) {
+    for channel in (0..16u8).filter(|ch| channels & (1 << ch) != 0) {
+        send_direct_data(pack_short_message(0xB0 | channel, 11, value), PLAYER_TRACK);
+    }
+}
+
+/// How often the expression is lowered during a fade, in 100ns units.
+const FADE_STEP: i64 = 500_000;
+
+/// Progress of the fade at the end of playback, see
+/// `PlaybackOptions::fade_out`.
+struct Fade {
+    /// Tick the fade starts at.
+    tick: u64,
+    /// Length of the fade and the song time played since it started, in
+    /// 100ns units at normal speed.
+    length: f64,
+    elapsed: f64,
+    /// Channels used by the file.
+    channels: u16,
+    /// The last expression value sent.
+    level: u8,
+}
+
+impl Fade {
+    fn new(parsed: &ParsedMidi, time_div: u16, length: Duration) -> Option<Self> {
+        let start = parsed.total_duration.saturating_sub(length);
+        Some(Self {
+            tick: tick_at_time(parsed, time_div, start)?,
+            length: duration_to_100ns(length).max(1) as f64,
+            elapsed: 0.0,
+            channels: channels_per_track(parsed)
+                .iter()
+                .fold(0, |all, &channels| all | channels),
+            level: 127,
+        })
+    }
+
+    /// Wait `sleep_time` while `units` of song time pass, lowering the
+    /// expression every `FADE_STEP` of the wait.
+    fn delay(
+        &mut self,
+        sleep_time: i64,
+        units: f64,
+        delay_fn: &mut dyn FnMut(i64),
+        send_direct_data: &mut impl FnMut(u32, u16),
+    ) {
+        let steps = (sleep_time / FADE_STEP).max(1);
+        let start = self.elapsed;
+        for step in 0..steps {
+            self.elapsed = start + units * step as f64 / steps as f64;
+            let level = (127.0 * (1.0 - self.elapsed / self.length))
+                .round()
+                .clamp(0.0, 127.0) as u8;
+            if level != self.level {
+                self.level = level;
+                send_expression(self.channels, level, send_direct_data);
+            }
+
+            let part = sleep_time / steps + if step == steps - 1 { sleep_time % steps } else { 0 };
+            if part > 0 {
+                delay_fn(part);
+            }
+        }
+        self.elapsed = start + units;
+    }
+}
+
+/// The set of channels each track sends channel messages on.
+fn channels_per_track(parsed: &ParsedMidi) -> Vec<u16> {
+    let mut channels = Vec::new();
+    for event in parsed.events.iter().filter(|e| !e.is_tempo() && !e.is_long()) {
+        let track = event.track() as usize;
+        if channels.len() <= track {
+            channels.resize(track + 1, 0u16);
+        }
+        channels[track] |= 1 << (event.data() & 0x0F);
+    }
+    channels
+}
+
+/// Entries of `ParsedMidi::deltas` per sampled tick of the index
+/// `seek_to_tick` searches.
+pub const TICK_INDEX_STRIDE: usize = 256;
+
+/// Find where playback should start to begin at `tick`.
+///
+/// Returns the index of the first event at or after `tick`, the index of the
+/// first delta entry that follows it and the tick of that event, or the end
+/// of the file if there is none. A binary search over the tick index narrows
+/// the search down to `TICK_INDEX_STRIDE` entries of the delta table, which
+/// are then walked.
+pub fn seek_to_tick(parsed: &ParsedMidi, tick: u64) -> (usize, usize, u64) {
+    // Start from the last indexed tick before `tick`. An index built for
+    // another delta table is left out
+    let indexed = if parsed.tick_index_source == (parsed.first_tick, parsed.deltas.len()) {
+        parsed.tick_index.len()
+    } else {
+        0
+    };
+    let checkpoint = parsed.tick_index[..indexed].partition_point(|&t| t < tick);
+    let (mut current, first_delta) = match checkpoint {
+        0 => (parsed.first_tick, 0),
+        n => (parsed.tick_index[n - 1], n * TICK_INDEX_STRIDE),
+    };
+    let mut start = match first_delta {
+        0 => 0usize,
+        n => parsed.deltas[n - 1].0 as usize + 1,
+    };
+
+    for (delta_idx, &(idx, delta_ticks)) in parsed.deltas.iter().enumerate().skip(first_delta) {
+        // Entries continuing a split gap belong to the event before `start`
+        if current >= tick && idx as usize >= start {
+            return (start, delta_idx, current);
+        }
+        current += delta_ticks as u64;
+        start = idx as usize + 1;
+    }
+
+    if current >= tick {
+        (start, parsed.deltas.len(), current)
+    } else {
+        (parsed.events.len(), parsed.deltas.len(), current)
+    }
 }
 
-pub fn play_parsed_events_batched(
+/// Handle pending player controls. Blocks while paused.
+///
+/// Returns how long playback was paused in 100ns units, or `None` if playback
+/// should stop.
+fn poll_controls(
+    control: &Receiver<PlayerControl>,
+    clock: &dyn Clock,
+    send_direct_data: &mut impl FnMut(u32, u16),
+) -> Option<i64> {
+    let mut paused_at: Option<i64> = None;
+
+    loop {
+        let command = match paused_at {
+            // A dropped sender while paused can never resume us, so stop.
+            Some(_) => control.recv().unwrap_or(PlayerControl::Stop),
+            None => match control.try_recv() {
+                Ok(command) => command,
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => return Some(0),
+            },
+        };
+
+        match command {
+            PlayerControl::Pause if paused_at.is_none() => {
+                silence_all_channels(send_direct_data);
+                paused_at = Some(clock.now_100ns());
+            }
+            PlayerControl::Resume => {
+                if let Some(start) = paused_at {
+                    return Some(clock.now_100ns() - start);
+                }
+            }
+            PlayerControl::Stop => {
+                silence_all_channels(send_direct_data);
+                return None;
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn play_parsed_events(
     parsed: &ParsedMidi,
     time_div: u16,
-    mut send_direct_data: impl FnMut(u32, u16) + Send + 'static,
+    send_direct_data: impl FnMut(u32, u16) + Send + 'static,
     delay_fn: Option<Box<dyn FnMut(i64) + Send + 'static>>,
 ) {
+    play_parsed_events_with_options(
+        parsed,
+        time_div,
+        send_direct_data,
+        delay_fn,
+        PlaybackOptions::default(),
+    );
+}
+
+/// Play the events once.
+///
+/// When the end is reached, All Sound Off and All Notes Off are sent on all
+/// channels so notes without a Note Off don't ring forever, unless
+/// `PlaybackOptions::let_notes_ring` is set. Returns the timing measured if
+/// `PlaybackOptions::timing_stats` is set.
+pub fn play_parsed_events_with_options(
+    parsed: &ParsedMidi,
+    time_div: u16,
+    mut send_direct_data: impl FnMut(u32, u16) + Send + 'static,
+    delay_fn: Option<Box<dyn FnMut(i64) + Send + 'static>>,
+    mut options: PlaybackOptions,
+) -> PlaybackStats {
+    let with_clicks;
+    let parsed = if options.metronome {
+        with_clicks = with_metronome(parsed, time_div);
+        &with_clicks
+    } else {
+        parsed
+    };
+    let with_clock;
+    let parsed = if options.midi_clock {
+        with_clock = with_midi_clock(parsed, time_div);
+        &with_clock
+    } else {
+        parsed
+    };
+    let mut stats = PlaybackStats::default();
     if parsed.events.is_empty() {
-        return;
+        return stats;
     }
 
     let default_delay = |ns: i64| delay_execution_100ns(ns);
     let mut delay_fn = match delay_fn {
         Some(f) => f,
-        None => Box::new(move |ns| default_delay(ns)),
+        None => Box::new(default_delay),
     };
 
-    let batch_size = 65536;
-    let lookahead_batches = 2048;
+    if !wait_for_smpte_offset(parsed, &mut *delay_fn, &options) {
+        return stats;
+    }
 
-    let (batch_tx, batch_rx): (Sender<Vec<UnpackedEvent>>, Receiver<Vec<UnpackedEvent>>) =
-        bounded(lookahead_batches);
+    let finished = play_pass(
+        PassSource::Parsed(parsed),
+        time_div,
+        &mut send_direct_data,
+        &mut *delay_fn,
+        &mut options,
+        &mut stats,
+    );
+    if finished && (!options.let_notes_ring || options.fade_out.is_some()) {
+        silence_all_channels(&mut send_direct_data);
+    }
+    stats
+}
 
-    let (pool_tx, pool_rx): (Sender<Vec<UnpackedEvent>>, Receiver<Vec<UnpackedEvent>>) =
-        bounded(lookahead_batches);
+/// Play tracks merged while they are read, see `stream::play_tracks_streaming`.
+pub(crate) fn play_merged(
+    merge: TrackMerge,
+    time_div: u16,
+    mut send_direct_data: impl FnMut(u32, u16) + Send + 'static,
+    delay_fn: Option<Box<dyn FnMut(i64) + Send + 'static>>,
+    mut options: PlaybackOptions,
+) -> PlaybackStats {
+    let mut stats = PlaybackStats::default();
+    if merge.peek_tick().is_none() {
+        return stats;
+    }
+
+    let default_delay = |ns: i64| delay_execution_100ns(ns);
+    let mut delay_fn = match delay_fn {
+        Some(f) => f,
+        None => Box::new(default_delay),
+    };
 
-    // Pre-fill the pool with empty Vecs (reusable buffers)
-    for _ in 0..lookahead_batches {
-        pool_tx
-            .send(Vec::with_capacity(batch_size))
-            .expect("pool prefill should succeed");
+    let finished = play_pass(
+        PassSource::Streamed(merge),
+        time_div,
+        &mut send_direct_data,
+        &mut *delay_fn,
+        &mut options,
+        &mut stats,
+    );
+    if finished && !options.let_notes_ring {
+        silence_all_channels(&mut send_direct_data);
     }
+    stats
+}
 
-    thread::scope(|scope| {
-        let parser_tx = batch_tx.clone();
-        let parser_pool_rx = pool_rx.clone();
+/// Play the events `loop_count` times, or forever if it is `None`.
+///
+/// All notes are released between passes so held notes don't bleed into the
+/// next one. Stopping through the options ends the whole loop.
+///
+/// Like `play_parsed_events_with_options`, all channels are silenced after
+/// the last pass unless `PlaybackOptions::let_notes_ring` is set. The timing
+/// stats cover all passes.
+pub fn play_parsed_events_looped(
+    parsed: &ParsedMidi,
+    time_div: u16,
+    mut send_direct_data: impl FnMut(u32, u16) + Send + 'static,
+    delay_fn: Option<Box<dyn FnMut(i64) + Send + 'static>>,
+    loop_count: Option<u32>,
+    mut options: PlaybackOptions,
+) -> PlaybackStats {
+    let with_clicks;
+    let parsed = if options.metronome {
+        with_clicks = with_metronome(parsed, time_div);
+        &with_clicks
+    } else {
+        parsed
+    };
+    let with_clock;
+    let parsed = if options.midi_clock {
+        with_clock = with_midi_clock(parsed, time_div);
+        &with_clock
+    } else {
+        parsed
+    };
+    let mut stats = PlaybackStats::default();
+    if parsed.events.is_empty() {
+        return stats;
+    }
 
-        scope.spawn(move || {
-            let mut iter = parsed.events.iter().enumerate();
-            loop {
-                let mut buf = match parser_pool_rx.recv() {
-                    Ok(v) => v,
-                    Err(_) => return,
-                };
+    let default_delay = |ns: i64| delay_execution_100ns(ns);
+    let mut delay_fn = match delay_fn {
+        Some(f) => f,
+        None => Box::new(default_delay),
+    };
 
-                buf.clear();
-                for (idx, &packed) in (&mut iter).by_ref().take(batch_size) {
-                    let data = packed.data;
-                    let is_tempo = packed.is_tempo;
-
-                    buf.push(UnpackedEvent {
-                        idx: idx as u32,
-                        track: packed.track,
-                        data,
-                        is_tempo,
-                    });
+    if !wait_for_smpte_offset(parsed, &mut *delay_fn, &options) {
+        return stats;
+    }
+
+    let mut pass = 0u32;
+    while loop_count.is_none_or(|count| pass < count) {
+        if pass > 0 {
+            all_notes_off(&mut send_direct_data);
+        }
+
+        let finished = play_pass(
+            PassSource::Parsed(parsed),
+            time_div,
+            &mut send_direct_data,
+            &mut *delay_fn,
+            &mut options,
+            &mut stats,
+        );
+        if !finished {
+            return stats;
+        }
+        pass += 1;
+    }
+
+    if !options.let_notes_ring || options.fade_out.is_some() {
+        silence_all_channels(&mut send_direct_data);
+    }
+    stats
+}
+
+/// Longest single wait for an SMPTE offset or, with a stop flag, between
+/// events, in 100ns units, so a stop is noticed within 100ms.
+const OFFSET_STEP: i64 = 1_000_000;
+
+/// Wait `time` in steps of at most `OFFSET_STEP`, cutting the wait short
+/// once `stop` is set.
+fn delay_until_stopped(mut time: i64, delay_fn: &mut dyn FnMut(i64), stop: &AtomicBool) {
+    while time > 0 && !stop.load(Ordering::Relaxed) {
+        let step = time.min(OFFSET_STEP);
+        delay_fn(step);
+        time -= step;
+    }
+}
+
+/// Wait out the SMPTE offset of `parsed` if `PlaybackOptions::smpte_offset`
+/// asks for it. Returns `false` if playback was stopped while waiting.
+fn wait_for_smpte_offset(
+    parsed: &ParsedMidi,
+    delay_fn: &mut dyn FnMut(i64),
+    options: &PlaybackOptions,
+) -> bool {
+    let Some(offset) = parsed
+        .smpte_offset
+        .filter(|_| options.smpte_offset && options.start_tick == 0)
+    else {
+        return true;
+    };
+
+    let mut remaining = duration_to_100ns(offset.duration());
+    while remaining > 0 {
+        if options
+            .stop
+            .as_ref()
+            .is_some_and(|stop| stop.load(Ordering::Relaxed))
+        {
+            return false;
+        }
+        let step = remaining.min(OFFSET_STEP);
+        delay_fn(step);
+        remaining -= step;
+    }
+    true
+}
+
+/// Send Stop to gear following the MIDI Clock on `track`, if it's running.
+fn stop_clock(track: Option<u16>, send_direct_data: &mut impl FnMut(u32, u16)) {
+    if let Some(track) = track {
+        send_direct_data(STOP, track);
+    }
+}
+
+/// The events a pass plays.
+enum PassSource<'a> {
+    Parsed(&'a ParsedMidi),
+    /// Tracks merged while they are read. Features that need the whole file,
+    /// like SysEx and fading out, are left out.
+    Streamed(TrackMerge),
+}
+
+/// Play through all events once, starting from a fresh timing state, and
+/// add its timing to `stats`. Returns `false` if playback was stopped early.
+fn play_pass(
+    mut source: PassSource<'_>,
+    time_div: u16,
+    send_direct_data: &mut impl FnMut(u32, u16),
+    delay_fn: &mut dyn FnMut(i64),
+    options: &mut PlaybackOptions,
+    stats: &mut PlaybackStats,
+) -> bool {
+    let stop = options.stop.clone();
+    let mut stepped_delay;
+    let delay_fn: &mut dyn FnMut(i64) = match stop.as_deref() {
+        Some(stop) => {
+            stepped_delay = |time| delay_until_stopped(time, delay_fn, stop);
+            &mut stepped_delay
+        }
+        None => delay_fn,
+    };
+    let channel_map = options.channel_map;
+    let send_direct_data = &mut |data, track| match channel_map.as_ref() {
+        Some(map) => send_direct_data(remap_channel(data, map), track),
+        None => send_direct_data(data, track),
+    };
+    let parsed = match &source {
+        PassSource::Parsed(parsed) => Some(*parsed),
+        PassSource::Streamed(_) => None,
+    };
+    let division = TimeDivision::from_raw(time_div);
+    let mut tick: u64 = match &source {
+        PassSource::Parsed(parsed) => parsed.first_tick,
+        PassSource::Streamed(merge) => merge.peek_tick().unwrap_or(0),
+    };
+    let mut multiplier = division.multiplier(500_000);
+    let max_drift = duration_to_100ns(options.max_drift);
+    let catch_up = options.catch_up;
+    let catch_up_threshold = duration_to_100ns(options.catch_up_threshold);
+    let mut behind = false;
+    let mut skipped = match catch_up {
+        CatchUpMode::Absorb => Vec::new(),
+        _ => vec![0u32; 16 * 128],
+    };
+    let mut old: i64 = 0;
+    // Fraction of a 100ns unit dropped when `old` was truncated
+    let mut carry = 0.0f64;
+    let mut delta: i64 = 0;
+    let clock = options.clock.clone();
+    let clock: &dyn Clock = match clock.as_deref() {
+        Some(clock) => clock,
+        None => &SystemClock,
+    };
+    let mut last_time = clock.now_100ns();
+
+    let mut start_time = last_time;
+    // When the next events are due after `start_time`, for `stats`
+    let mut due = 0.0f64;
+    let progress_interval = duration_to_100ns(options.progress_interval);
+    let mut last_progress = i64::MIN;
+    let end_tick = options.end_tick.unwrap_or(u64::MAX);
+
+    let mut i = 0;
+    let mut delta_idx = 0;
+    let mut muted_channels = options
+        .channel_mute
+        .as_ref()
+        .map_or(0, |mask| mask.load(Ordering::Relaxed));
+    let mut transpose = options
+        .transpose
+        .as_ref()
+        .map_or(0, |semitones| semitones.load(Ordering::Relaxed));
+    let mut velocity_scale = options
+        .velocity_scale
+        .as_ref()
+        .map_or(1.0, |scale| scale.load());
+    let mut speed = 1.0f64;
+    let drum_map = options.drum_map;
+    let mut track_generation = 0;
+    let mut muted_tracks = Vec::new();
+    let mut track_channels = Vec::new();
+    if let Some(mask) = options.track_mute.as_ref() {
+        track_generation = mask.generation();
+        muted_tracks = mask.snapshot();
+        track_channels = match &source {
+            PassSource::Parsed(parsed) => channels_per_track(parsed),
+            // Which channels a track uses isn't known before it's read
+            PassSource::Streamed(merge) => vec![u16::MAX; merge.track_count()],
+        };
+    }
+    let mut fade = options
+        .fade_out
+        .zip(parsed)
+        .and_then(|(length, parsed)| Fade::new(parsed, time_div, length));
+    if let Some(fade) = fade.as_ref() {
+        // Undo the fade of a previous pass
+        send_expression(fade.channels, 127, send_direct_data);
+    }
+
+    // Track of the MIDI Clock while it runs, between Start or Continue and
+    // Stop
+    let midi_clock = options.midi_clock;
+    let mut clock_track = None;
+    if options.start_tick > 0 {
+        // Silently fast-forward: restore controllers, programs, pitch bend,
+        // SysEx and tempo from before the seek point without playing any notes.
+        let mut restored_tempo = None;
+        let mut restore = |data: u32, is_tempo: bool, track: u16| {
+            if is_tempo {
+                restored_tempo = Some(data as u64);
+            } else if midi_clock && data == START {
+                clock_track = Some(track);
+            } else if (data & 0xFF) == LONG_STATUS {
+                if let Some(send_long_data) = options.send_long_data.as_mut()
+                    && let Some(message) = parsed.and_then(|parsed| parsed.long_message(data))
+                {
+                    send_long_data(message, track);
+                }
+            } else if matches!(
+                event_kind(data),
+                EventKind::ControlChange | EventKind::ProgramChange | EventKind::PitchBend
+            ) {
+                send_direct_data(data, track);
+            }
+        };
+        match &mut source {
+            PassSource::Parsed(parsed) => {
+                (i, delta_idx, tick) = seek_to_tick(parsed, options.start_tick);
+                for event in parsed.events.iter().take(i) {
+                    restore(event.data(), event.is_tempo(), event.track());
+                }
+            }
+            PassSource::Streamed(merge) => {
+                tick = merge.skip_to(options.start_tick, |event| {
+                    restore(event.data, event.is_tempo, event.track)
+                });
+            }
+        }
+        if let Some(us_per_qn) = restored_tempo {
+            multiplier = division.multiplier(us_per_qn);
+            if let Some(on_tempo) = options.on_tempo.as_mut() {
+                on_tempo(us_per_qn_to_bpm(us_per_qn));
+            }
+        }
+    }
+    if tick >= end_tick {
+        return true;
+    }
+    if let Some(track) = clock_track {
+        // The clock was started before the seek point, carry on from here
+        send_direct_data(song_position(tick, time_div), track);
+        send_direct_data(CONTINUE, track);
+    }
+    let mut fading = fade.as_ref().is_some_and(|fade| tick >= fade.tick);
+
+    let strategy = options.strategy;
+    thread::scope(|scope| {
+        let mut events = match source {
+            PassSource::Parsed(parsed) => EventReader::new(scope, parsed, i, delta_idx, strategy),
+            PassSource::Streamed(merge) => EventReader::Streamed(merge),
+        };
+        while let Some(UnpackedEvent {
+            data,
+            track,
+            is_tempo,
+            gap: mut delta_tick,
+        }) = events.next()
+        {
+            if is_tempo {
+                let bpm_us_per_qn = data as u64;
+                multiplier = division.multiplier(bpm_us_per_qn);
+                if let Some(on_tempo) = options.on_tempo.as_mut() {
+                    on_tempo(us_per_qn_to_bpm(bpm_us_per_qn));
                 }
+            } else if (data & 0xFF) == LONG_STATUS {
+                if let Some(send_long_data) = options.send_long_data.as_mut()
+                    && let Some(message) = parsed.and_then(|parsed| parsed.long_message(data))
+                {
+                    send_long_data(message, track);
+                }
+            } else if !is_channel_muted(data, muted_channels)
+                && !is_track_muted(data, track, &muted_tracks)
+                && !(fading && is_expression_change(data))
+                && !(catch_up != CatchUpMode::Absorb
+                    && skip_to_catch_up(data, catch_up, behind, &mut skipped))
+                && let Some(data) = transpose_message(
+                    drum_map
+                        .as_ref()
+                        .map_or(data, |map| remap_drum_note(data, map)),
+                    transpose,
+                    options.transpose_range,
+                    options.transpose_drums,
+                )
+            {
+                send_direct_data(scale_velocity(data, velocity_scale), track);
+            }
+            if midi_clock {
+                match data {
+                    START => clock_track = Some(track),
+                    STOP => clock_track = None,
+                    _ => {}
+                }
+            }
 
-                if buf.is_empty() {
-                    let _ = parser_tx.send(buf);
-                    break;
+            if delta_tick == 0 {
+                continue;
+            }
+            let gap_in_fade = fading;
+            tick = tick.wrapping_add(delta_tick);
+            fading = fade.as_ref().is_some_and(|fade| tick >= fade.tick);
+            // Only wait until the end tick, then stop
+            let reached_end = tick >= end_tick;
+            if reached_end {
+                delta_tick -= tick - end_tick;
+            }
+
+            if let Some(mask) = options.channel_mute.as_ref() {
+                let current = mask.load(Ordering::Relaxed);
+                let newly_muted = current & !muted_channels;
+                for channel in (0..16u8).filter(|ch| newly_muted & (1 << ch) != 0) {
+                    send_direct_data(pack_short_message(0xB0 | channel, 123, 0), PLAYER_TRACK);
                 }
+                muted_channels = current;
+            }
 
-                if parser_tx.send(buf).is_err() {
-                    break;
+            if let Some(mask) = options.track_mute.as_ref()
+                && mask.generation() != track_generation
+            {
+                track_generation = mask.generation();
+                let current = mask.snapshot();
+                let mut release = 0u16;
+                for (track, channels) in track_channels.iter().enumerate() {
+                    if track_bit(&current, track) && !track_bit(&muted_tracks, track) {
+                        release |= channels;
+                    }
+                }
+                for channel in (0..16u8).filter(|ch| release & (1 << ch) != 0) {
+                    send_direct_data(pack_short_message(0xB0 | channel, 123, 0), PLAYER_TRACK);
                 }
+                muted_tracks = current;
             }
-        });
 
-        let mut bpm_us_per_qn: u64;
-        let mut tick: u64 = 0;
-        let mut multiplier: f64 = 0.0;
-        let max_drift: i64 = 100_000;
-        let mut old: i64 = 0;
-        let mut delta: i64 = 0;
-        let mut last_time = get_time_100ns();
-
-        let mut delta_idx = 0;
-        let n_deltas = parsed.deltas.len();
-
-        // Receive entire batches (blocks when empty). Processing is a tight per-batch loop.
-        while let Ok(mut batch) = batch_rx.recv() {
-            if batch.is_empty() {
-                break;
-            }
-
-            for ev in &batch {
-                if ev.is_tempo {
-                    bpm_us_per_qn = ev.data as u64;
-                    multiplier = (bpm_us_per_qn as f64) / (time_div as f64) * 10.0;
-                } else {
-                    send_direct_data(ev.data, ev.track);
+            if let Some(semitones) = options.transpose.as_ref() {
+                let current = semitones.load(Ordering::Relaxed);
+                if current != transpose {
+                    // Release notes that were started at the old pitch.
+                    all_notes_off(send_direct_data);
+                    transpose = current;
                 }
+            }
+
+            if let Some(scale) = options.velocity_scale.as_ref() {
+                velocity_scale = scale.load();
+            }
+
+            if let Some(current) = options.speed.as_ref().map(|s| s.load())
+                && current > 0.0
+            {
+                speed = current as f64;
+            }
 
-                while delta_idx < n_deltas {
-                    let (didx, delta_ticks) = unsafe { *parsed.deltas.get_unchecked(delta_idx) };
-                    if didx != ev.idx {
-                        break;
+            if let Some(control) = options.control.as_ref() {
+                match poll_controls(control, clock, send_direct_data) {
+                    // Shift the clock so the pause doesn't count as lag.
+                    Some(paused) => {
+                        last_time += paused;
+                        start_time += paused;
+                    }
+                    None => {
+                        stop_clock(clock_track, send_direct_data);
+                        return false;
                     }
+                }
+            }
 
-                    let delta_tick = delta_ticks as u64;
-                    tick = tick.wrapping_add(delta_tick);
+            let now = clock.now_100ns();
+            // The clock is monotonic, but pause handling moves last_time
+            let elapsed = now.saturating_sub(last_time).max(0);
+            last_time = now;
 
-                    let now = get_time_100ns();
-                    let elapsed = (now - last_time) as i64;
-                    last_time = now;
+            if let Some(progress) = options.progress.as_mut()
+                && now.saturating_sub(last_progress) >= progress_interval
+            {
+                last_progress = now;
+                progress(tick, duration_from_100ns(now - start_time));
+            }
 
-                    let work_time = elapsed - old;
-                    old = (delta_tick as f64 * multiplier) as i64;
-                    delta = delta.wrapping_add(work_time);
+            let work_time = elapsed - old;
+            // Carry the truncated fraction so it doesn't add up over
+            // millions of events
+            let gap = delta_tick as f64 * multiplier / speed;
+            if options.timing_stats {
+                stats.record(now - start_time - due as i64);
+                due += gap;
+            }
+            let exact = gap + carry;
+            old = exact as i64;
+            carry = exact - old as f64;
+            delta = delta.saturating_add(work_time);
+
+            let sleep_time = if delta > 0 { old - delta } else { old };
+
+            // `delta` is how far behind schedule playback is, and is
+            // taken off the waits. When a wait is skipped entirely, at
+            // most `max_drift` of it is kept for the next ones, unless
+            // events are skipped to catch up on all of it
+            if sleep_time <= 0 && catch_up == CatchUpMode::Absorb && delta > max_drift {
+                delta = max_drift;
+                stats.drift_clamps += 1;
+            }
+            // Lag left after this wait, which is skipped if there is any
+            behind =
+                catch_up != CatchUpMode::Absorb && delta.saturating_sub(old) > catch_up_threshold;
+            match fade.as_mut() {
+                Some(fade) if gap_in_fade => fade.delay(
+                    sleep_time.max(0),
+                    delta_tick as f64 * multiplier,
+                    delay_fn,
+                    send_direct_data,
+                ),
+                _ if sleep_time > 0 => delay_fn(sleep_time),
+                _ => {}
+            }
+            if stop
+                .as_ref()
+                .is_some_and(|stop| stop.load(Ordering::Relaxed))
+            {
+                silence_all_channels(send_direct_data);
+                stop_clock(clock_track, send_direct_data);
+                return false;
+            }
+            if reached_end {
+                stop_clock(clock_track, send_direct_data);
+                return true;
+            }
+        }
 
-                    let sleep_time = if delta > 0 { old - delta } else { old };
+        stop_clock(clock_track, send_direct_data);
+        true
+    })
+}
 
-                    if sleep_time <= 0 {
-                        delta = delta.min(max_drift);
-                    } else {
-                        delay_fn(sleep_time);
-                    }
+/// Events per batch of `PlaybackStrategy::Batched`.
+pub const DEFAULT_BATCH_SIZE: usize = 65536;
+/// Batches `PlaybackStrategy::Batched` reads ahead, about 24 MiB of events
+/// with `DEFAULT_BATCH_SIZE`.
+pub const DEFAULT_LOOKAHEAD_BATCHES: usize = 16;
+
+/// An event as the player reads it, with the gap that follows it.
+#[derive(Copy, Clone, Debug)]
+pub struct UnpackedEvent {
+    pub data: u32,
+    pub track: u16,
+    pub is_tempo: bool,
+    /// Ticks to wait after the event, 0 if the next one is at the same tick.
+    pub gap: u64,
+}
+
+/// Ticks to wait after event `idx`, moving `delta_idx` past its entries of
+/// `deltas`.
+#[inline(always)]
+fn gap_after(deltas: &[(u32, u32)], delta_idx: &mut usize, idx: usize) -> u64 {
+    let mut gap = 0;
+    while let Some(&(next_idx, ticks)) = deltas.get(*delta_idx)
+        && next_idx as usize == idx
+    {
+        gap += ticks as u64;
+        *delta_idx += 1;
+    }
+    gap
+}
+
+/// Reads the events of a pass in order, straight from `Events`, from the
+/// batches of a second thread, see `PlaybackStrategy`, or from tracks merged
+/// as they are read.
+enum EventReader<'env> {
+    Direct {
+        events: &'env Events,
+        deltas: &'env [(u32, u32)],
+        idx: usize,
+        delta_idx: usize,
+    },
+    Batched {
+        batches: Receiver<Vec<UnpackedEvent>>,
+        pool: Sender<Vec<UnpackedEvent>>,
+        batch: Vec<UnpackedEvent>,
+        pos: usize,
+    },
+    Streamed(TrackMerge),
+}
+
+impl<'env> EventReader<'env> {
+    /// Read the events of `parsed` from event `start` and delta entry
+    /// `delta_start` on, see `seek_to_tick`. A batched reader unpacks them
+    /// on a thread of `scope`, which ends once the reader is dropped.
+    fn new<'scope>(
+        scope: &'scope thread::Scope<'scope, 'env>,
+        parsed: &'env ParsedMidi,
+        start: usize,
+        delta_start: usize,
+        strategy: PlaybackStrategy,
+    ) -> Self {
+        let PlaybackStrategy::Batched {
+            batch_size,
+            lookahead,
+        } = strategy
+        else {
+            return EventReader::Direct {
+                events: &parsed.events,
+                deltas: &parsed.deltas,
+                idx: start,
+                delta_idx: delta_start,
+            };
+        };
+        let batch_size = batch_size.max(1);
+        let lookahead = lookahead.max(1);
+
+        let (batch_tx, batch_rx) = bounded(lookahead);
+        let (pool_tx, pool_rx) = bounded::<Vec<UnpackedEvent>>(lookahead);
 
-                    delta_idx += 1;
+        // Pre-fill the pool with empty Vecs (reusable buffers)
+        for _ in 0..lookahead {
+            pool_tx
+                .send(Vec::with_capacity(batch_size))
+                .expect("pool prefill should succeed");
+        }
+
+        scope.spawn(move || {
+            let mut iter = parsed.events.iter().enumerate().skip(start);
+            let mut delta_idx = delta_start;
+            // Stops when the pool or the player hangs up
+            while let Ok(mut buf) = pool_rx.recv() {
+                buf.clear();
+                buf.extend(
+                    iter.by_ref()
+                        .take(batch_size)
+                        .map(|(idx, event)| UnpackedEvent {
+                            data: event.data(),
+                            track: event.track(),
+                            is_tempo: event.is_tempo(),
+                            gap: gap_after(&parsed.deltas, &mut delta_idx, idx),
+                        }),
+                );
+                if buf.is_empty() || batch_tx.send(buf).is_err() {
+                    break;
                 }
             }
-            batch.clear();
-            let _ = pool_tx.try_send(batch);
+        });
+
+        EventReader::Batched {
+            batches: batch_rx,
+            pool: pool_tx,
+            batch: Vec::new(),
+            pos: 0,
         }
+    }
 
-        drop(batch_rx);
-    })
-}
\ No newline at end of file
+    /// The next event, or `None` after the last one.
+    #[inline(always)]
+    fn next(&mut self) -> Option<UnpackedEvent> {
+        match self {
+            EventReader::Direct {
+                events,
+                deltas,
+                idx,
+                delta_idx,
+            } => {
+                if *idx >= events.len() {
+                    return None;
+                }
+                let (data, is_tempo) = unsafe { events.data_unchecked(*idx) };
+                let event = UnpackedEvent {
+                    data,
+                    track: unsafe { events.track_unchecked(*idx) },
+                    is_tempo,
+                    gap: gap_after(deltas, delta_idx, *idx),
+                };
+                *idx += 1;
+                Some(event)
+            }
+            EventReader::Batched {
+                batches,
+                pool,
+                batch,
+                pos,
+            } => {
+                if *pos == batch.len() {
+                    // The batch thread hangs up after the last event
+                    let next = batches.recv().ok()?;
+                    let _ = pool.try_send(std::mem::replace(batch, next));
+                    *pos = 0;
+                }
+                let event = batch[*pos];
+                *pos += 1;
+                Some(event)
+            }
+            EventReader::Streamed(merge) => merge.next(),
+        }
+    }
+}
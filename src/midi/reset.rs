@@ -0,0 +1,92 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::midi::player::PLAYER_TRACK;
+use crate::midi::utils::pack_short_message;
+
+/// GM System On.
+const GM_RESET: &[u8] = &[0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7];
+/// Roland GS Reset.
+const GS_RESET: &[u8] = &[
+    0xF0, 0x41, 0x10, 0x42, 0x12, 0x40, 0x00, 0x7F, 0x00, 0x41, 0xF7,
+];
+/// Yamaha XG System On.
+const XG_RESET: &[u8] = &[0xF0, 0x43, 0x10, 0x4C, 0x00, 0x00, 0x7E, 0x00, 0xF7];
+
+/// Default channel volume (CC 7) after a reset.
+const DEFAULT_VOLUME: u8 = 100;
+/// Default pan (CC 10) after a reset, centered.
+const DEFAULT_PAN: u8 = 64;
+
+/// Which reset to send to the synth before playback.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SynthReset {
+    #[default]
+    None,
+    Gm,
+    Gs,
+    Xg,
+}
+
+impl SynthReset {
+    /// The SysEx message that performs the reset, if any.
+    pub fn sysex(self) -> Option<&'static [u8]> {
+        match self {
+            SynthReset::None => None,
+            SynthReset::Gm => Some(GM_RESET),
+            SynthReset::Gs => Some(GS_RESET),
+            SynthReset::Xg => Some(XG_RESET),
+        }
+    }
+
+    /// Send the reset SysEx, then the default volume and pan on all 16
+    /// channels. Does nothing for `SynthReset::None`.
+    pub fn send(
+        self,
+        send_direct_data: &mut impl FnMut(u32, u16),
+        send_long_data: &mut impl FnMut(&[u8]),
+    ) {
+        let Some(sysex) = self.sysex() else {
+            return;
+        };
+        send_long_data(sysex);
+        for channel in 0..16u8 {
+            send_direct_data(
+                pack_short_message(0xB0 | channel, 7, DEFAULT_VOLUME),
+                PLAYER_TRACK,
+            );
+            send_direct_data(
+                pack_short_message(0xB0 | channel, 10, DEFAULT_PAN),
+                PLAYER_TRACK,
+            );
+        }
+    }
+}
+
+impl FromStr for SynthReset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "none" => Ok(SynthReset::None),
+            "gm" => Ok(SynthReset::Gm),
+            "gs" => Ok(SynthReset::Gs),
+            "xg" => Ok(SynthReset::Xg),
+            _ => Err(format!(
+                "Invalid reset '{}', expected none, gm, gs or xg",
+                s.trim()
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SynthReset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SynthReset::None => "none",
+            SynthReset::Gm => "gm",
+            SynthReset::Gs => "gs",
+            SynthReset::Xg => "xg",
+        })
+    }
+}
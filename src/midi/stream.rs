@@ -0,0 +1,213 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::midi::player::{
+    EventKind, PlaybackOptions, PlaybackStats, UnpackedEvent, event_kind, play_merged,
+};
+use crate::midi::track_data::TrackData;
+
+/// Order of events at the same tick, like the merge in the parser: tempo
+/// changes first, Note Ons last so they don't cut off Note Offs.
+const TEMPO: u8 = 0;
+const OTHER: u8 = 1;
+const NOTE_ON: u8 = 2;
+
+/// The next event of a track: its tick, its place at that tick, the track
+/// and the packed message, or the tempo for a tempo change.
+type Head = (u64, u8, usize, u32);
+
+/// Merges tracks in tick order as they are read, holding only the next
+/// event of each track.
+pub(crate) struct TrackMerge {
+    tracks: Vec<TrackData>,
+    queue: BinaryHeap<Reverse<Head>>,
+    time_div: u16,
+    /// Format 2: play one track after another instead of all at once.
+    patterns: bool,
+    /// The pattern being read and the tick it starts at.
+    pattern: usize,
+    offset: u64,
+    /// The last tick read in the current pattern.
+    pattern_end: u64,
+    /// Tick of the last event returned.
+    tick: u64,
+}
+
+impl TrackMerge {
+    pub(crate) fn new(mut tracks: Vec<TrackData>, time_div: u16, patterns: bool) -> Self {
+        let mut queue = BinaryHeap::with_capacity(tracks.len());
+        if !patterns {
+            for (idx, track) in tracks.iter_mut().enumerate() {
+                queue_next(&mut queue, track, idx, 0, &mut 0, time_div);
+            }
+        }
+        let mut merge = Self {
+            tracks,
+            queue,
+            time_div,
+            patterns,
+            pattern: 0,
+            offset: 0,
+            pattern_end: 0,
+            tick: 0,
+        };
+        if patterns {
+            merge.start_patterns();
+        }
+        merge
+    }
+
+    /// Number of tracks, including ones that are already finished.
+    pub(crate) fn track_count(&self) -> usize {
+        self.tracks.len()
+    }
+
+    /// Tick of the next event, if there is one.
+    pub(crate) fn peek_tick(&self) -> Option<u64> {
+        self.queue.peek().map(|Reverse((tick, ..))| *tick)
+    }
+
+    /// Queue the next event, or go on to the next pattern until one has an
+    /// event. Each pattern starts where the previous one ends, like
+    /// `parse_midi_patterns`.
+    fn start_patterns(&mut self) {
+        while self.queue.is_empty() && self.pattern < self.tracks.len() {
+            let idx = self.pattern;
+            queue_next(
+                &mut self.queue,
+                &mut self.tracks[idx],
+                idx,
+                self.offset,
+                &mut self.pattern_end,
+                self.time_div,
+            );
+            if self.queue.is_empty() {
+                self.offset = self.pattern_end;
+                self.pattern += 1;
+            }
+        }
+    }
+
+    /// The next event in tick order, with the ticks until the one after it.
+    pub(crate) fn next(&mut self) -> Option<UnpackedEvent> {
+        let Reverse((tick, rank, idx, data)) = self.queue.pop()?;
+        if self.patterns {
+            self.start_patterns();
+        } else {
+            queue_next(
+                &mut self.queue,
+                &mut self.tracks[idx],
+                idx,
+                0,
+                &mut 0,
+                self.time_div,
+            );
+        }
+        self.tick = tick;
+        Some(UnpackedEvent {
+            data,
+            track: idx as u16,
+            is_tempo: rank == TEMPO,
+            gap: self.peek_tick().map_or(0, |next| next - tick),
+        })
+    }
+
+    /// Pass every event before `tick` to `skipped` and return the tick
+    /// playback goes on from: that of the next event, or of the last one if
+    /// none is left.
+    pub(crate) fn skip_to(&mut self, tick: u64, mut skipped: impl FnMut(UnpackedEvent)) -> u64 {
+        while self.peek_tick().is_some_and(|next| next < tick) {
+            if let Some(event) = self.next() {
+                skipped(event);
+            }
+        }
+        self.peek_tick().unwrap_or(self.tick)
+    }
+}
+
+/// Read the next event of track `idx` that is played and queue it at
+/// `offset` past its tick, raising `end` to the ticks read. SysEx, 0xF7
+/// escapes and meta events other than tempo changes are skipped.
+fn queue_next(
+    queue: &mut BinaryHeap<Reverse<Head>>,
+    track: &mut TrackData,
+    idx: usize,
+    offset: u64,
+    end: &mut u64,
+    time_div: u16,
+) {
+    while !track.is_finished() {
+        let tick = track.tick.saturating_add(offset);
+        *end = (*end).max(tick);
+        track.update_command();
+        track.update_message();
+        if track.length == 0 {
+            // The data ended mid-message
+            return;
+        }
+
+        let message = track.message;
+        let head = match (message & 0xFF) as u8 {
+            status if status < 0xF0 => {
+                let rank = match event_kind(message) {
+                    EventKind::NoteOn => NOTE_ON,
+                    _ => OTHER,
+                };
+                Some((tick, rank, idx, message))
+            }
+            0xFF => {
+                let mut multiplier = 0.0f64;
+                let mut us_per_qn = 0u64;
+                track.process_meta_event(&mut multiplier, &mut us_per_qn, time_div);
+                let is_tempo = (message >> 8) & 0xFF == 0x51 && track.long_msg.len() >= 3;
+                is_tempo.then_some((tick, TEMPO, idx, us_per_qn as u32))
+            }
+            _ => None,
+        };
+
+        track.update_tick();
+        if let Some(head) = head {
+            queue.push(Reverse(head));
+            return;
+        }
+    }
+}
+
+/// Play `tracks` straight from their data, merging them as they are read
+/// instead of parsing the whole file up front.
+///
+/// Playback starts as soon as the first event of every track has been read
+/// and only one event per track is held at a time, at the cost of decoding
+/// while playing. Tracks are numbered by their index in `tracks`, so a
+/// subset loaded with `load_midi_file_selected` keeps the numbers of the
+/// file. Events play in the same order and at the same times as with
+/// `play_parsed_events_with_options`, through the same timing loop.
+///
+/// The options work the same, except for the ones that need the whole file:
+/// `fade_out`, `metronome`, `midi_clock` and `smpte_offset` are ignored,
+/// SysEx and 0xF7 escapes are not sent, and `strategy` doesn't apply. A
+/// track muted mid-playback releases all channels, as the channels it uses
+/// aren't known yet.
+pub fn play_tracks_streaming(
+    tracks: Vec<TrackData>,
+    time_div: u16,
+    send_direct_data: impl FnMut(u32, u16) + Send + 'static,
+    delay_fn: Option<Box<dyn FnMut(i64) + Send + 'static>>,
+    options: PlaybackOptions,
+) -> PlaybackStats {
+    let merge = TrackMerge::new(tracks, time_div, false);
+    play_merged(merge, time_div, send_direct_data, delay_fn, options)
+}
+
+/// `play_tracks_streaming` for the patterns of a Format 2 file, playing
+/// each one after the end of the previous one like `parse_midi_patterns`.
+pub fn play_patterns_streaming(
+    tracks: Vec<TrackData>,
+    time_div: u16,
+    send_direct_data: impl FnMut(u32, u16) + Send + 'static,
+    delay_fn: Option<Box<dyn FnMut(i64) + Send + 'static>>,
+    options: PlaybackOptions,
+) -> PlaybackStats {
+    let merge = TrackMerge::new(tracks, time_div, true);
+    play_merged(merge, time_div, send_direct_data, delay_fn, options)
+}
@@ -0,0 +1,99 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::midi::analysis::{SendBudget, min_send_budget};
+use crate::midi::player::ParsedMidi;
+use crate::midi::time_division::TimeDivision;
+
+/// The numbers printed after parsing a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Summary {
+    pub file: String,
+    pub tracks: usize,
+    pub time_division: TimeDivision,
+    pub events: usize,
+    pub note_count: u64,
+    pub total_ticks: u64,
+    pub total_duration: Duration,
+    pub parse_time: Duration,
+    /// Smallest nonzero interval between events.
+    pub min_send_budget: Option<SendBudget>,
+    pub duration_saturated: bool,
+    /// Problems in corrupt tracks, see `ParsedMidi::warnings`.
+    pub warnings: Vec<String>,
+}
+
+impl Summary {
+    pub fn new(
+        file: &str,
+        tracks: usize,
+        parsed: &ParsedMidi,
+        time_div: u16,
+        parse_time: Duration,
+    ) -> Self {
+        Summary {
+            file: file.to_string(),
+            tracks,
+            time_division: TimeDivision::from_raw(time_div),
+            events: parsed.events.len(),
+            note_count: parsed.note_count,
+            total_ticks: parsed.total_ticks,
+            total_duration: parsed.total_duration,
+            parse_time,
+            min_send_budget: min_send_budget(parsed, time_div),
+            duration_saturated: parsed.duration_saturated,
+            warnings: parsed.warnings.iter().map(|w| w.to_string()).collect(),
+        }
+    }
+
+    /// Format the summary as a single-line JSON object.
+    pub fn to_json(&self) -> String {
+        let (min_interval, min_interval_tick) = match self.min_send_budget {
+            Some(budget) => (
+                format!("{:.3}", budget.interval.as_secs_f64() * 1_000.0),
+                budget.tick.to_string(),
+            ),
+            None => ("null".to_string(), "null".to_string()),
+        };
+        let warnings: Vec<String> = self.warnings.iter().map(|w| json_string(w)).collect();
+        format!(
+            "{{\"file\":{},\"tracks\":{},\"time_division\":{},\"events\":{},\
+             \"note_count\":{},\"total_ticks\":{},\"total_duration_ms\":{},\
+             \"parse_time_ms\":{:.3},\"min_event_interval_ms\":{},\"min_event_interval_tick\":{},\
+             \"duration_saturated\":{},\"warnings\":[{}]}}",
+            json_string(&self.file),
+            self.tracks,
+            json_string(&self.time_division.to_string()),
+            self.events,
+            self.note_count,
+            self.total_ticks,
+            self.total_duration.as_millis(),
+            self.parse_time.as_secs_f64() * 1_000.0,
+            min_interval,
+            min_interval_tick,
+            self.duration_saturated,
+            warnings.join(",")
+        )
+    }
+}
+
+/// Quote and escape a string for JSON.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
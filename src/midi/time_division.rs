@@ -0,0 +1,75 @@
+use std::fmt;
+
+/// The time division from the MIDI header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TimeDivision {
+    /// Ticks per quarter note.
+    Metrical(u16),
+    /// Frame-based timing. Tempo events have no effect on these files.
+    Smpte { fps: u8, ticks_per_frame: u8 },
+}
+
+impl TimeDivision {
+    /// Decode the raw division word from the header.
+    pub fn from_raw(raw: u16) -> Self {
+        if (raw & 0x8000) != 0 {
+            // Upper byte is the negative frame rate in two's complement.
+            let fps = ((raw >> 8) as u8 as i8).unsigned_abs();
+            TimeDivision::Smpte {
+                fps,
+                ticks_per_frame: (raw & 0xFF) as u8,
+            }
+        } else {
+            TimeDivision::Metrical(raw)
+        }
+    }
+
+    /// Microseconds per tick as a `(numerator, denominator)` pair.
+    fn us_per_tick(self, us_per_qn: u64) -> (u128, u128) {
+        match self {
+            TimeDivision::Metrical(tpq) => (us_per_qn as u128, tpq as u128),
+            TimeDivision::Smpte {
+                fps,
+                ticks_per_frame,
+            } => {
+                // 29 means 30 drop-frame, which runs at 29.97 frames per second.
+                let fps_x100 = if fps == 29 { 2997 } else { fps as u128 * 100 };
+                (100_000_000, fps_x100 * ticks_per_frame as u128)
+            }
+        }
+    }
+
+    /// Convert a tick count to microseconds at the given tempo. A division
+    /// without ticks, which the loaders reject, gives 0.
+    pub fn ticks_to_us(self, ticks: u64, us_per_qn: u64) -> u128 {
+        let (num, den) = self.us_per_tick(us_per_qn);
+        ((ticks as u128) * num).checked_div(den).unwrap_or(0)
+    }
+
+    /// Convert microseconds to whole ticks at the given tempo, saturating at
+    /// `u64::MAX`.
+    pub fn us_to_ticks(self, us: u128, us_per_qn: u64) -> u64 {
+        let (num, den) = self.us_per_tick(us_per_qn);
+        us.saturating_mul(den)
+            .checked_div(num)
+            .map_or(u64::MAX, |ticks| u64::try_from(ticks).unwrap_or(u64::MAX))
+    }
+
+    /// Length of one tick in 100ns units at the given tempo.
+    pub fn multiplier(self, us_per_qn: u64) -> f64 {
+        let (num, den) = self.us_per_tick(us_per_qn);
+        (num as f64) / (den as f64) * 10.0
+    }
+}
+
+impl fmt::Display for TimeDivision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeDivision::Metrical(tpq) => write!(f, "{} ticks/quarter note", tpq),
+            TimeDivision::Smpte {
+                fps,
+                ticks_per_frame,
+            } => write!(f, "SMPTE {} fps, {} ticks/frame", fps, ticks_per_frame),
+        }
+    }
+}
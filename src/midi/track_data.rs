@@ -1,40 +1,145 @@
+use std::fmt;
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+
+use crate::midi::time_division::TimeDivision;
+
+/// A buffer that track data can be borrowed from, e.g. a `Vec<u8>` holding
+/// the whole file or a memory map of it.
+pub type SharedBuffer = Arc<dyn AsRef<[u8]> + Send + Sync>;
+
+/// The bytes of one track chunk.
+///
+/// Keeps the buffer the file was loaded into alive instead of copying the
+/// chunk, so a file's data is only held in memory once.
+#[derive(Clone)]
+pub struct TrackBytes {
+    buffer: Option<SharedBuffer>,
+    range: Range<usize>,
+}
+
+impl TrackBytes {
+    /// Borrow `range` of `buffer`. Panics if the range is out of bounds.
+    pub fn new(buffer: SharedBuffer, range: Range<usize>) -> Self {
+        // Checked once here so slicing in `deref` can't fail
+        let _ = &(*buffer).as_ref()[range.clone()];
+        TrackBytes {
+            buffer: Some(buffer),
+            range,
+        }
+    }
+
+    pub fn empty() -> Self {
+        TrackBytes {
+            buffer: None,
+            range: 0..0,
+        }
+    }
+}
+
+impl Default for TrackBytes {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl From<Vec<u8>> for TrackBytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        let len = bytes.len();
+        TrackBytes::new(Arc::new(bytes), 0..len)
+    }
+}
+
+impl Deref for TrackBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.buffer {
+            Some(buffer) => &(**buffer).as_ref()[self.range.clone()],
+            None => &[],
+        }
+    }
+}
+
+impl fmt::Debug for TrackBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl PartialEq<[u8]> for TrackBytes {
+    fn eq(&self, other: &[u8]) -> bool {
+        **self == *other
+    }
+}
+
+impl PartialEq<Vec<u8>> for TrackBytes {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        **self == **other
+    }
+}
+
+#[derive(Debug)]
 pub struct TrackData {
-    pub data: Vec<u8>,
+    pub data: TrackBytes,
     pub long_msg: Vec<u8>,
+    /// Tick of the next event to read. The loaders read the leading delta of
+    /// every track, so this starts at the tick of its first event, and
+    /// `update_tick` adds the delta after each event once it has been read.
     pub tick: u64,
     pub offset: usize,
     pub length: usize,
     pub message: u32,
     pub temp: u32,
     pub last_status: Option<u8>,
+    /// Why reading stopped early, if the track is corrupt. The events read
+    /// before it are kept.
+    pub problem: Option<&'static str>,
 }
 
 impl TrackData {
-    /// Create a new TrackData with a given capacity.
-    pub fn new(capacity: usize) -> Self {
+    /// Create a new TrackData reading from `data`.
+    pub fn new(data: TrackBytes) -> Self {
         TrackData {
-            data: Vec::with_capacity(capacity),
+            length: data.len(),
+            data,
             long_msg: Vec::with_capacity(256),
             tick: 0,
             offset: 0,
-            length: capacity,
             message: 0,
             temp: 0,
             last_status: None,
+            problem: None,
         }
     }
 
+    /// Read the next byte, or `None` at the end of the track.
+    fn next_byte(&mut self) -> Option<u8> {
+        if self.offset >= self.length {
+            return None;
+        }
+        let byte = *self.data.get(self.offset)?;
+        self.offset += 1;
+        Some(byte)
+    }
+
     /// Decode a variable-length MIDI value from the data stream.
+    /// Values are at most 4 bytes long; anything longer ends the track.
     pub fn decode_variable_length(&mut self) -> u32 {
         let mut result = 0u32;
-        while self.offset < self.length {
-            let byte = self.data[self.offset];
-            self.offset += 1;
+        for i in 0..4 {
+            let Some(byte) = self.next_byte() else {
+                if i > 0 {
+                    self.fail("the data ended in a variable-length value");
+                }
+                return result;
+            };
             result = (result << 7) | u32::from(byte & 0x7F);
             if (byte & 0x80) == 0 {
-                break;
+                return result;
             }
         }
+        self.fail("variable-length value longer than 4 bytes");
         result
     }
 
@@ -48,30 +153,52 @@ impl TrackData {
     /// Read the next status byte if present and update `message`.
     /// Also handles running status
     pub fn update_command(&mut self) {
-        if self.offset >= self.length {
+        let Some(&byte) = self.data.get(self.offset).filter(|_| self.offset < self.length) else {
             return;
-        }
+        };
 
-        let byte = self.data[self.offset];
         if byte >= 0x80 {
-            // new status byte
+            // new status byte. System messages, including SysEx and meta
+            // events, cancel running status.
             self.offset += 1;
             self.message = u32::from(byte);
-            self.last_status = Some(byte);
+            self.last_status = (byte < 0xF0).then_some(byte);
         } else {
             // running status: reuse last_status if available
             if let Some(status) = self.last_status {
                 self.message = u32::from(status);
             } else {
-                // invalid MIDI: data byte without any running status
-                self.message = 0; // or bail
+                // invalid MIDI: data byte without any running status, the rest
+                // of the track can't be trusted
+                self.message = 0;
+                self.fail("data byte without a running status");
             }
         }
     }
 
+    /// Whether the track has been fully read, either by reaching the end of
+    /// its data or by an End of Track event.
+    pub fn is_finished(&self) -> bool {
+        self.offset >= self.length
+    }
+
+    /// Stop reading the track, discarding any remaining data.
+    pub fn end(&mut self) {
+        self.data = TrackBytes::empty();
+        self.length = 0;
+    }
+
+    /// Stop reading a corrupt track and remember why, see `problem`.
+    pub fn fail(&mut self, problem: &'static str) {
+        self.problem.get_or_insert(problem);
+        self.end();
+    }
+
     /// Read message params or long meta/sysex events.
+    /// If the data ends mid-message the track is ended instead.
     pub fn update_message(&mut self) {
         if self.offset >= self.length {
+            self.fail("the data ended after a status byte");
             return;
         }
 
@@ -80,41 +207,46 @@ impl TrackData {
         let msg_type = (self.message & 0xFF) as u8;
         match msg_type {
             0x00..=0xBF | 0xE0..=0xEF => {
-                if self.offset + 2 <= self.length {
-                    self.temp = u32::from(self.data[self.offset]) << 8;
-                    self.temp |= u32::from(self.data[self.offset + 1]) << 16;
-                    self.offset += 2;
-                }
+                let (Some(d1), Some(d2)) = (self.next_byte(), self.next_byte()) else {
+                    self.fail("the data ended in a message");
+                    return;
+                };
+                self.temp = (u32::from(d1) << 8) | (u32::from(d2) << 16);
             }
             0xC0..=0xDF => {
                 // 1-byte messages (program change & channel pressure)
-                if self.offset < self.length {
-                    self.temp = u32::from(self.data[self.offset]) << 8;
-                    self.offset += 1;
-                }
+                let Some(d1) = self.next_byte() else {
+                    self.fail("the data ended in a message");
+                    return;
+                };
+                self.temp = u32::from(d1) << 8;
             }
-            0xF0 | 0xFF => {
-                // Sysex or Meta events
+            0xF0 | 0xF7 | 0xFF => {
+                // Sysex, sysex continuation/escape or Meta events
                 if msg_type == 0xFF {
                     // Meta event: first data byte is the meta type
-                    if self.offset < self.length {
-                        self.temp = u32::from(self.data[self.offset]) << 8;
-                        self.offset += 1;
-                    }
-                } else {
-                    self.temp = 0;
+                    let Some(meta_type) = self.next_byte() else {
+                        self.fail("the data ended in a meta event");
+                        return;
+                    };
+                    self.temp = u32::from(meta_type) << 8;
                 }
 
                 let len = self.decode_variable_length() as usize;
-                if self.long_msg.capacity() < len {
-                    self.long_msg.reserve(len - self.long_msg.capacity());
-                }
+                let end = self.offset.saturating_add(len);
+                let Some(bytes) = self.data.get(self.offset..end).filter(|_| end <= self.length)
+                else {
+                    self.fail(if msg_type == 0xFF {
+                        "meta event longer than the rest of the track"
+                    } else {
+                        "SysEx message longer than the rest of the track"
+                    });
+                    return;
+                };
 
                 // Copy the data
-                let end = (self.offset + len).min(self.length);
                 self.long_msg.clear();
-                self.long_msg
-                    .extend_from_slice(&self.data[self.offset..end]);
+                self.long_msg.extend_from_slice(bytes);
                 self.offset = end;
             }
             _ => {}
@@ -137,8 +269,7 @@ impl TrackData {
                     | (self.long_msg[2] as u64);
                 *bpm = t;
 
-                // 1 microsecond = 10 * 100ns, so (t * 10)/time_div = 100ns units per tick
-                let mut m = (t as f64 * 10.0) / (time_div as f64);
+                let mut m = TimeDivision::from_raw(time_div).multiplier(t);
                 if m < 1.0 {
                     m = 1.0;
                 }
@@ -146,10 +277,7 @@ impl TrackData {
             }
 
             // End of track
-            0x2F => {
-                self.data.clear();
-                self.length = 0;
-            }
+            0x2F => self.end(),
             _ => {}
         }
     }
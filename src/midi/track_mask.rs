@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A live-adjustable set of muted tracks, shared with a running player.
+pub struct TrackMask {
+    bits: Vec<AtomicU64>,
+    generation: AtomicU64,
+}
+
+impl TrackMask {
+    /// Create a mask for `num_tracks` tracks with nothing muted.
+    pub fn new(num_tracks: usize) -> Self {
+        Self {
+            bits: (0..num_tracks.div_ceil(64))
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    /// Mute or unmute a single track. Out of range tracks are ignored.
+    pub fn set_muted(&self, track: u16, muted: bool) {
+        let Some(word) = self.bits.get(track as usize / 64) else {
+            return;
+        };
+        let bit = 1u64 << (track % 64);
+        if muted {
+            word.fetch_or(bit, Ordering::Relaxed);
+        } else {
+            word.fetch_and(!bit, Ordering::Relaxed);
+        }
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// Mute every track except `tracks`.
+    pub fn solo(&self, tracks: &[u16]) {
+        for word in &self.bits {
+            word.store(u64::MAX, Ordering::Relaxed);
+        }
+        for &track in tracks {
+            if let Some(word) = self.bits.get(track as usize / 64) {
+                word.fetch_and(!(1u64 << (track % 64)), Ordering::Relaxed);
+            }
+        }
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// Unmute all tracks.
+    pub fn clear(&self) {
+        for word in &self.bits {
+            word.store(0, Ordering::Relaxed);
+        }
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn is_muted(&self, track: u16) -> bool {
+        self.bits
+            .get(track as usize / 64)
+            .is_some_and(|word| word.load(Ordering::Relaxed) & (1 << (track % 64)) != 0)
+    }
+
+    /// Counter that changes every time the mask is modified.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Copy the current muted bits, 64 tracks per word.
+    pub fn snapshot(&self) -> Vec<u64> {
+        self.bits
+            .iter()
+            .map(|word| word.load(Ordering::Relaxed))
+            .collect()
+    }
+}
@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use crate::midi::player::{Event, EventKind, Events, ParsedMidi, event_kind, push_gap};
+use crate::midi::utils::pack_short_message;
+
+/// Cut `parsed` down to its first `max_events` events.
+///
+/// All Notes Off is sent on every channel at the tick of the first event
+/// that was cut, so notes still held at that point don't hang, and the
+/// events that are kept play at the same times as before. Returns whether
+/// anything was cut.
+pub fn truncate_events(parsed: &mut ParsedMidi, time_div: u16, max_events: usize) -> bool {
+    let Some(cut_tick) = parsed.iter_with_ticks().nth(max_events).map(|(tick, _)| tick) else {
+        return false;
+    };
+    truncate(parsed, time_div, max_events, cut_tick);
+    true
+}
+
+/// Cut the events of `parsed` from the tick played at `max_duration` after
+/// the start on, ending it with All Notes Off on every channel at that tick.
+/// Returns whether anything was cut.
+pub fn truncate_duration(parsed: &mut ParsedMidi, time_div: u16, max_duration: Duration) -> bool {
+    let cut_tick = parsed.duration_to_tick(time_div, max_duration);
+    let keep = parsed
+        .iter_with_ticks()
+        .take_while(|&(tick, _)| tick < cut_tick)
+        .count();
+    if keep == parsed.events.len() {
+        return false;
+    }
+    truncate(parsed, time_div, keep, cut_tick);
+    true
+}
+
+/// Keep the first `keep` events, followed by All Notes Off at `cut_tick`,
+/// and recount the notes and polyphony of what is left.
+fn truncate(parsed: &mut ParsedMidi, time_div: u16, keep: usize, cut_tick: u64) {
+    // Like the metronome, one past the last track so muting never drops it
+    let track = parsed.track_note_counts.len() as u16;
+
+    let mut events = Events::with_capacity(keep + 16);
+    let mut deltas = Vec::new();
+    let mut note_count = 0u64;
+    let mut channel_note_counts = [0u64; 16];
+    let mut track_note_counts = vec![0u64; parsed.track_note_counts.len()];
+    let mut held = vec![0u32; 16 * 128];
+    let mut voices = 0u64;
+    let mut max_polyphony = 0u64;
+    let mut max_polyphony_tick = 0u64;
+    let mut prev_tick = parsed.first_tick;
+
+    for (tick, event) in parsed.iter_with_ticks().take(keep) {
+        if tick > prev_tick {
+            push_gap(&mut deltas, (events.len() - 1) as u32, tick - prev_tick);
+            prev_tick = tick;
+        }
+        events.push(event);
+
+        if event.is_tempo() || event.is_long() {
+            continue;
+        }
+        let data = event.data();
+        let key = ((data & 0x0F) << 7 | (data >> 8) & 0x7F) as usize;
+        match event_kind(data) {
+            EventKind::NoteOn => {
+                note_count += 1;
+                channel_note_counts[(data & 0x0F) as usize] += 1;
+                if let Some(count) = track_note_counts.get_mut(event.track() as usize) {
+                    *count += 1;
+                }
+                held[key] += 1;
+                voices += 1;
+                if voices > max_polyphony {
+                    max_polyphony = voices;
+                    max_polyphony_tick = tick;
+                }
+            }
+            EventKind::NoteOff if held[key] > 0 => {
+                held[key] -= 1;
+                voices -= 1;
+            }
+            _ => {}
+        }
+    }
+
+    if !events.is_empty() && cut_tick > prev_tick {
+        push_gap(&mut deltas, (events.len() - 1) as u32, cut_tick - prev_tick);
+    }
+    for channel in 0..16u8 {
+        events.push(Event::new(
+            pack_short_message(0xB0 | channel, 123, 0),
+            track,
+        ));
+    }
+
+    parsed.events = events;
+    parsed.deltas = deltas;
+    parsed.rebuild_tick_index();
+    parsed.total_ticks = cut_tick;
+    parsed.note_count = note_count;
+    parsed.channel_note_counts = channel_note_counts;
+    parsed.track_note_counts = track_note_counts;
+    parsed.max_polyphony = max_polyphony;
+    parsed.max_polyphony_tick = max_polyphony_tick;
+
+    // The first tempo segment stays even if the cut is at the first tick
+    let segments = parsed
+        .tempo_map
+        .partition_point(|&(tick, _, _)| tick < cut_tick);
+    parsed.tempo_map.truncate(segments.max(1));
+    parsed.total_duration = parsed.tick_to_duration(time_div, cut_tick);
+    parsed.total_us = parsed.total_duration.as_micros();
+    parsed.duration_saturated = parsed.total_duration == Duration::MAX;
+
+    let texts = parsed
+        .meta_texts
+        .partition_point(|&(tick, _)| tick < cut_tick);
+    parsed.meta_texts.truncate(texts);
+    let signatures = parsed
+        .time_signatures
+        .partition_point(|&(tick, _)| tick < cut_tick);
+    parsed.time_signatures.truncate(signatures);
+    let keys = parsed
+        .key_signatures
+        .partition_point(|&(tick, _)| tick < cut_tick);
+    parsed.key_signatures.truncate(keys);
+}
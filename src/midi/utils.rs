@@ -1,47 +1,261 @@
 use std::sync::LazyLock;
-use std::thread::sleep;
+use std::sync::atomic::{AtomicI64, AtomicU32, Ordering};
 use std::time::Duration;
 use std::time::Instant;
 
-static START: LazyLock<Instant> = LazyLock::new(|| Instant::now());
+use crate::output::MidiOutput;
+
+static START: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// Get the monotonic time in nanoseconds since the first call.
+///
+/// `Instant` never goes backwards, so differences between two readings are
+/// never negative. Saturates after ~584 years.
+pub fn get_time_ns() -> u64 {
+    START.elapsed().as_nanos().min(u64::MAX as u128) as u64
+}
 
 /// Get the time in 100ns units
 pub fn get_time_100ns() -> i64 {
-    let duration = START.elapsed();
+    (get_time_ns() / 100) as i64
+}
+
+/// A source of the current time in 100ns units, used by the player to
+/// schedule events. Tests can pass a virtual clock that only moves when they
+/// advance it.
+pub trait Clock {
+    fn now_100ns(&self) -> i64;
+}
+
+/// The monotonic clock of `get_time_100ns`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_100ns(&self) -> i64 {
+        get_time_100ns()
+    }
+}
+
+/// Convert a duration to 100ns units, saturating at `i64::MAX`.
+pub fn duration_to_100ns(duration: Duration) -> i64 {
+    (duration.as_nanos() / 100).min(i64::MAX as u128) as i64
+}
+
+/// Convert 100ns units to a duration. Negative values become zero.
+pub fn duration_from_100ns(units: i64) -> Duration {
+    let units = units.max(0) as u64;
+    Duration::new(units / 10_000_000, ((units % 10_000_000) * 100) as u32)
+}
+
+/// Convert a tempo in microseconds per quarter note to beats per minute.
+pub fn us_per_qn_to_bpm(us_per_qn: u64) -> f64 {
+    60_000_000.0 / us_per_qn as f64
+}
+
+/// How much of each delay is spent spinning instead of sleeping, in 100ns
+/// units, see `set_spin_margin`.
+static SPIN_MARGIN_100NS: AtomicI64 = AtomicI64::new(20_000);
 
-    let seconds = duration.as_secs() as i64;
-    let nanos = duration.subsec_nanos() as i64;
+/// Set how much of each delay is busy-waited instead of slept, 2ms by
+/// default. Sleeping tends to overshoot by up to a timer tick, so the last
+/// stretch is spun to hit the target time precisely. A larger margin is more
+/// accurate on coarse timers but uses more CPU. Clamped to under a second.
+pub fn set_spin_margin(margin: Duration) {
+    let margin = duration_to_100ns(margin).min(9_999_999);
+    SPIN_MARGIN_100NS.store(margin, Ordering::Relaxed);
+}
 
-    (seconds * 10_000_000) + (nanos / 100)
+/// The margin set by `set_spin_margin`.
+pub fn spin_margin() -> Duration {
+    duration_from_100ns(SPIN_MARGIN_100NS.load(Ordering::Relaxed))
 }
 
 /// Delay the thread execution using 100ns units
+///
+/// Sleeps for the bulk of the delay, then spins for the last `spin_margin`.
+/// Short delays are spun entirely. This is the `spin_sleep` feature's
+/// version, which sleeps on a high resolution timer on Windows.
+#[cfg(feature = "spin_sleep")]
 pub fn delay_execution_100ns(delay_in_100ns: i64) {
     if delay_in_100ns <= 0 {
         return;
     }
 
-    let secs = delay_in_100ns / 10_000_000;
-    let nanos = (delay_in_100ns % 10_000_000) * 100;
+    let margin = SPIN_MARGIN_100NS.load(Ordering::Relaxed) as u32 * 100;
+    spin_sleep::SpinSleeper::new(margin)
+        .with_spin_strategy(spin_sleep::SpinStrategy::SpinLoopHint)
+        .sleep(duration_from_100ns(delay_in_100ns));
+}
+
+/// Delay the thread execution using 100ns units
+///
+/// Sleeps for the bulk of the delay, then spins on `get_time_100ns` for the
+/// last `spin_margin`. Short delays are spun entirely.
+#[cfg(not(feature = "spin_sleep"))]
+pub fn delay_execution_100ns(delay_in_100ns: i64) {
+    if delay_in_100ns <= 0 {
+        return;
+    }
+
+    let target = get_time_100ns() + delay_in_100ns;
+
+    let sleep_time = delay_in_100ns - SPIN_MARGIN_100NS.load(Ordering::Relaxed);
+    if sleep_time > 0 {
+        std::thread::sleep(duration_from_100ns(sleep_time));
+    }
+
+    while get_time_100ns() < target {
+        std::hint::spin_loop();
+    }
+}
+
+#[cfg(target_os = "windows")]
+#[link(name = "winmm")]
+unsafe extern "system" {
+    fn timeBeginPeriod(period: u32) -> u32;
+    fn timeEndPeriod(period: u32) -> u32;
+}
+
+/// Raises the Windows timer resolution to 1ms while alive, so sleeps in
+/// `delay_execution_100ns` don't overshoot by a whole ~15.6ms tick.
+/// Does nothing on other platforms.
+#[must_use = "the timer resolution is restored when the guard is dropped"]
+pub struct TimerResolutionGuard {
+    #[cfg(target_os = "windows")]
+    raised: bool,
+}
+
+impl TimerResolutionGuard {
+    pub fn raise() -> Self {
+        #[cfg(target_os = "windows")]
+        {
+            // TIMERR_NOERROR is 0
+            let raised = unsafe { timeBeginPeriod(1) } == 0;
+            Self { raised }
+        }
+        #[cfg(not(target_os = "windows"))]
+        Self {}
+    }
+}
+
+impl Drop for TimerResolutionGuard {
+    fn drop(&mut self) {
+        #[cfg(target_os = "windows")]
+        if self.raised {
+            unsafe {
+                timeEndPeriod(1);
+            }
+        }
+    }
+}
+
+/// An `f32` that can be shared and updated live, stored as its bits.
+#[derive(Debug, Default)]
+pub struct AtomicF32(AtomicU32);
+
+impl AtomicF32 {
+    pub fn new(value: f32) -> Self {
+        Self(AtomicU32::new(value.to_bits()))
+    }
+
+    pub fn load(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn store(&self, value: f32) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
 
-    let duration = Duration::new(secs as u64, nanos as u32);
-    sleep(duration);
+/// Pack a short MIDI message into the `u32` layout used by `SendDirectData`.
+pub fn pack_short_message(status: u8, data1: u8, data2: u8) -> u32 {
+    u32::from(status) | (u32::from(data1 & 0x7F) << 8) | (u32::from(data2 & 0x7F) << 16)
 }
 
-// // Funny stuff that allows us to keep the memory usage so low
-// const KIND_BIT: u32 = 1 << 31;
-// const DATA_MASK: u32 = 0x7FFFFFFF;
+/// Unpack a `SendDirectData` message into its raw bytes.
+/// Returns the bytes and how many of them belong to the message.
+pub fn unpack_short_message(data: u32) -> ([u8; 3], usize) {
+    let bytes = [data as u8, (data >> 8) as u8, (data >> 16) as u8];
+    let len = match bytes[0] {
+        0xC0..=0xDF | 0xF1 | 0xF3 => 2,
+        0x80..=0xEF | 0xF2 => 3,
+        _ => 1,
+    };
+    (bytes, len)
+}
 
-// #[inline(always)]
-// pub fn pack_event(data: u32, is_tempo: bool) -> u32 {
-//     if is_tempo {
-//         data | KIND_BIT
-//     } else {
-//         data & DATA_MASK
-//     }
-// }
+/// Split raw bytes, like the contents of an 0xF7 escape event, into packed
+/// short messages. Returns `None` unless the bytes are all complete messages
+/// that start with a status byte other than SysEx.
+pub fn split_short_messages(bytes: &[u8]) -> Option<Vec<u32>> {
+    let mut messages = Vec::new();
+    let mut rest = bytes;
+    while let Some(&status) = rest.first() {
+        if status < 0x80 || status == 0xF0 || status == 0xF7 {
+            return None;
+        }
+        let len = unpack_short_message(u32::from(status)).1;
+        let message = rest.get(..len)?;
+        if message[1..].iter().any(|&byte| byte >= 0x80) {
+            return None;
+        }
+        messages.push(
+            message
+                .iter()
+                .rev()
+                .fold(0u32, |data, &byte| data << 8 | u32::from(byte)),
+        );
+        rest = &rest[len..];
+    }
+    Some(messages)
+}
 
-// #[inline(always)]
-// pub fn unpack_event(packed: u32) -> (u32, bool) {
-//     ((packed & DATA_MASK), (packed & KIND_BIT) != 0)
-// }
+/// Send a note on to `backend`, wait for what is left of `duration` by
+/// `clock` after sending it and send the matching note off.
+///
+/// The wait goes to `delay_fn`, in 100ns units, or `delay_execution_100ns`
+/// if it is `None`. A virtual clock should be advanced by the `delay_fn`,
+/// like with `PlaybackOptions::clock`.
+pub fn play_note(
+    backend: &dyn MidiOutput,
+    clock: &dyn Clock,
+    delay_fn: Option<&mut dyn FnMut(i64)>,
+    channel: u8,
+    key: u8,
+    velocity: u8,
+    duration: Duration,
+) {
+    let channel = channel & 0x0F;
+    let end = clock.now_100ns().saturating_add(duration_to_100ns(duration));
+    backend.send_direct_data(pack_short_message(0x90 | channel, key, velocity));
+    let left = end - clock.now_100ns();
+    if left > 0 {
+        match delay_fn {
+            Some(delay_fn) => delay_fn(left),
+            None => delay_execution_100ns(left),
+        }
+    }
+    backend.send_direct_data(pack_short_message(0x80 | channel, key, 0));
+}
+
+// Funny stuff that allows us to keep the memory usage so low
+const KIND_BIT: u32 = 1 << 31;
+const DATA_MASK: u32 = 0x7FFFFFFF;
+
+/// Pack event data and its tempo flag into one word. The data keeps its low
+/// 31 bits.
+#[inline(always)]
+pub fn pack_event(data: u32, is_tempo: bool) -> u32 {
+    if is_tempo {
+        data | KIND_BIT
+    } else {
+        data & DATA_MASK
+    }
+}
+
+/// Split a word from `pack_event` into the data and the tempo flag.
+#[inline(always)]
+pub fn unpack_event(packed: u32) -> (u32, bool) {
+    ((packed & DATA_MASK), (packed & KIND_BIT) != 0)
+}
@@ -0,0 +1,116 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::midi::player::ParsedMidi;
+use crate::midi::utils::unpack_short_message;
+
+/// Encode a value as a MIDI variable-length quantity, the inverse of
+/// `TrackData::decode_variable_length`.
+pub fn encode_variable_length(mut value: u32, out: &mut Vec<u8>) {
+    let mut buf = [0u8; 5];
+    let mut pos = buf.len() - 1;
+    buf[pos] = (value & 0x7F) as u8;
+    value >>= 7;
+    while value > 0 {
+        pos -= 1;
+        buf[pos] = ((value & 0x7F) as u8) | 0x80;
+        value >>= 7;
+    }
+    out.extend_from_slice(&buf[pos..]);
+}
+
+/// Largest delta time a variable-length quantity can hold.
+const MAX_DELTA: u64 = 0x0FFF_FFFF;
+
+/// Encode a delta time. Gaps longer than a variable-length quantity can hold
+/// are bridged with empty text events, which players ignore.
+fn encode_delta(mut delta: u64, out: &mut Vec<u8>) {
+    while delta > MAX_DELTA {
+        encode_variable_length(MAX_DELTA as u32, out);
+        out.extend_from_slice(&[0xFF, 0x01, 0x00]);
+        delta -= MAX_DELTA;
+    }
+    encode_variable_length(delta as u32, out);
+}
+
+/// Write the merged event stream as a Format 0 MIDI file.
+pub fn write_midi_file<P: AsRef<Path>>(
+    parsed: &ParsedMidi,
+    time_div: u16,
+    filename: P,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(filename)?);
+    write_midi(parsed, time_div, &mut writer)?;
+    writer.flush()
+}
+
+/// Write the merged event stream as a Format 0 MIDI file to any writer.
+///
+/// Delta times are rebuilt from `first_tick` and the `deltas` table, so
+/// silence before the first event is kept. Tempo events become tempo meta
+/// events and SysEx events are written as single F0 packets.
+pub fn write_midi<W: Write>(parsed: &ParsedMidi, time_div: u16, writer: &mut W) -> io::Result<()> {
+    let track = encode_track(parsed);
+    let track_len = u32::try_from(track.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Track is too large"))?;
+
+    // Header: Format 0 with a single track
+    writer.write_all(b"MThd")?;
+    writer.write_all(&6u32.to_be_bytes())?;
+    writer.write_all(&0u16.to_be_bytes())?;
+    writer.write_all(&1u16.to_be_bytes())?;
+    writer.write_all(&time_div.to_be_bytes())?;
+
+    writer.write_all(b"MTrk")?;
+    writer.write_all(&track_len.to_be_bytes())?;
+    writer.write_all(&track)
+}
+
+/// Encode all events into the body of a single MTrk chunk.
+fn encode_track(parsed: &ParsedMidi) -> Vec<u8> {
+    let mut out = Vec::with_capacity(parsed.events.len() * 4 + 4);
+    let mut gaps = parsed.gaps().peekable();
+    // Silence before the first event is kept
+    let mut pending_delta = parsed.first_tick;
+
+    for (idx, event) in parsed.events.iter().enumerate() {
+        encode_delta(pending_delta, &mut out);
+
+        if event.is_tempo() {
+            out.extend_from_slice(&[0xFF, 0x51, 0x03]);
+            out.extend_from_slice(&event.data().to_be_bytes()[1..]);
+        } else if event.is_long() {
+            let message = parsed.long_message(event.data()).unwrap_or(&[0xF0, 0xF7]);
+            let (status, body) = match message.strip_prefix(&[0xF0]) {
+                // Lengths don't include the leading 0xF0
+                Some(body) => (0xF0, body),
+                // Raw bytes of an escape event
+                None => (0xF7, message),
+            };
+            out.push(status);
+            encode_variable_length(body.len() as u32, &mut out);
+            out.extend_from_slice(body);
+        } else {
+            let (bytes, len) = unpack_short_message(event.data());
+            // System messages aren't events of their own in a file, they
+            // are written as 0xF7 escapes
+            if bytes[0] >= 0xF0 {
+                out.push(0xF7);
+                encode_variable_length(len as u32, &mut out);
+            }
+            out.extend_from_slice(&bytes[..len]);
+        }
+
+        // Delta to the next event, if any
+        pending_delta = match gaps.next_if(|&(i, _)| i as usize == idx) {
+            Some((_, delta)) => delta,
+            None => 0,
+        };
+    }
+
+    // End of Track
+    encode_delta(pending_delta, &mut out);
+    out.extend_from_slice(&[0xFF, 0x2F, 0x00]);
+    out
+}
@@ -0,0 +1,426 @@
+// Output backends the player can send events to.
+
+use std::sync::Arc;
+
+use crate::kdmapi::KDMAPIStream;
+use crate::midi::player::PLAYER_TRACK;
+
+/// Whether `wanted`, a device index or part of a device name, picks the
+/// device at `index` named `name`.
+pub fn matches_device(wanted: &str, index: usize, name: &str) -> bool {
+    match wanted.parse::<usize>() {
+        Ok(wanted) => wanted == index,
+        Err(_) => name.contains(wanted),
+    }
+}
+
+/// A MIDI output the player can send events to.
+pub trait MidiOutput {
+    /// Send a short message in the packed `SendDirectData` layout.
+    fn send_direct_data(&self, data: u32);
+
+    /// Send a complete SysEx message, including the `F0`/`F7` framing.
+    fn send_long_data(&self, data: &[u8]);
+
+    /// Reset the output to its initial state, e.g. between songs.
+    fn reset(&self) {}
+
+    /// Number of active voices, if the output renders audio itself.
+    fn voice_count(&self) -> Option<u64> {
+        None
+    }
+
+    /// Rendering time reported by the synth, if the output renders audio itself.
+    fn rendering_time(&self) -> Option<f32> {
+        None
+    }
+}
+
+/// Sends each event to the output of its track's port, for files that
+/// address several synths or devices, see `ParsedMidi::track_ports`.
+///
+/// Tracks without a port, ports without an output of their own and tracks
+/// the player adds, like the metronome's, go to the first output. Messages
+/// the player sends on its own, on `PLAYER_TRACK`, go to every output so
+/// silencing, resets and fades reach every synth.
+pub struct PortRouter {
+    outputs: Vec<Arc<dyn MidiOutput + Send + Sync>>,
+    /// Index into `outputs` of each track
+    track_outputs: Vec<usize>,
+}
+
+impl PortRouter {
+    /// Route the tracks of a file whose ports are `track_ports`, sending
+    /// the tracks of each port in `port_outputs` to the output at that
+    /// index. Panics if `outputs` is empty.
+    pub fn new(
+        outputs: Vec<Arc<dyn MidiOutput + Send + Sync>>,
+        track_ports: &[Option<u8>],
+        port_outputs: &[(u8, usize)],
+    ) -> Self {
+        assert!(!outputs.is_empty(), "a router needs at least one output");
+        let track_outputs = track_ports
+            .iter()
+            .map(|&port| {
+                port.and_then(|port| port_outputs.iter().find(|&&(p, _)| p == port))
+                    .map_or(0, |&(_, output)| output)
+                    .min(outputs.len() - 1)
+            })
+            .collect();
+        PortRouter {
+            outputs,
+            track_outputs,
+        }
+    }
+
+    /// The output the events of `track` are sent to.
+    pub fn output(&self, track: u16) -> &(dyn MidiOutput + Send + Sync) {
+        let index = self.track_outputs.get(track as usize).copied().unwrap_or(0);
+        &*self.outputs[index]
+    }
+
+    pub fn outputs(&self) -> &[Arc<dyn MidiOutput + Send + Sync>] {
+        &self.outputs
+    }
+
+    pub fn send_direct_data(&self, data: u32, track: u16) {
+        if track == PLAYER_TRACK {
+            for output in &self.outputs {
+                output.send_direct_data(data);
+            }
+        } else {
+            self.output(track).send_direct_data(data);
+        }
+    }
+
+    pub fn send_long_data(&self, data: &[u8], track: u16) {
+        self.output(track).send_long_data(data);
+    }
+}
+
+impl MidiOutput for KDMAPIStream {
+    fn send_direct_data(&self, data: u32) {
+        KDMAPIStream::send_direct_data(self, data);
+    }
+
+    fn send_long_data(&self, data: &[u8]) {
+        self.send_direct_long_data(data);
+    }
+
+    fn reset(&self) {
+        KDMAPIStream::reset(self);
+    }
+
+    fn voice_count(&self) -> Option<u64> {
+        Some(self.get_voice_count())
+    }
+
+    fn rendering_time(&self) -> Option<f32> {
+        Some(self.get_rendering_time())
+    }
+}
+
+#[cfg(feature = "midir")]
+pub use self::midir_backend::MidirBackend;
+
+#[cfg(feature = "midir")]
+mod midir_backend {
+    use std::sync::Mutex;
+
+    use midir::{MidiOutput as MidirOutput, MidiOutputConnection};
+
+    use super::{MidiOutput, matches_device};
+    use crate::midi::utils::unpack_short_message;
+
+    const CLIENT_NAME: &str = "midiplayer_rs";
+
+    /// Sends to a hardware or virtual MIDI port through midir.
+    pub struct MidirBackend {
+        conn: Mutex<MidiOutputConnection>,
+        port_name: String,
+    }
+
+    impl MidirBackend {
+        /// Names of the available output ports.
+        pub fn port_names() -> Result<Vec<String>, String> {
+            let output = MidirOutput::new(CLIENT_NAME).map_err(|e| e.to_string())?;
+            Ok(output
+                .ports()
+                .iter()
+                .filter_map(|p| output.port_name(p).ok())
+                .collect())
+        }
+
+        /// Connect to the port at index `wanted` in `port_names`, or to the
+        /// first port whose name contains it, or to the first available port
+        /// if `wanted` is `None`.
+        pub fn connect(wanted: Option<&str>) -> Result<Self, String> {
+            let output = MidirOutput::new(CLIENT_NAME).map_err(|e| e.to_string())?;
+            let ports = output.ports();
+            let (port, port_name) = ports
+                .iter()
+                .filter_map(|p| output.port_name(p).ok().map(|name| (p, name)))
+                .enumerate()
+                .find(|(index, (_, name))| {
+                    wanted.is_none_or(|wanted| matches_device(wanted, *index, name))
+                })
+                .map(|(_, port)| port)
+                .ok_or_else(|| match wanted {
+                    Some(wanted) => format!("No MIDI output port matching '{}'", wanted),
+                    None => "No MIDI output ports available".to_string(),
+                })?;
+
+            let conn = output
+                .connect(port, CLIENT_NAME)
+                .map_err(|e| e.to_string())?;
+            Ok(MidirBackend {
+                conn: Mutex::new(conn),
+                port_name,
+            })
+        }
+
+        /// Name of the connected port.
+        pub fn port_name(&self) -> &str {
+            &self.port_name
+        }
+    }
+
+    impl MidiOutput for MidirBackend {
+        fn send_direct_data(&self, data: u32) {
+            let (bytes, len) = unpack_short_message(data);
+            if let Ok(mut conn) = self.conn.lock() {
+                let _ = conn.send(&bytes[..len]);
+            }
+        }
+
+        fn send_long_data(&self, data: &[u8]) {
+            if let Ok(mut conn) = self.conn.lock() {
+                let _ = conn.send(data);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+pub use self::winmm_backend::WinmmBackend;
+
+#[cfg(target_os = "windows")]
+mod winmm_backend {
+    use std::ffi::c_void;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    use libloading::Library;
+
+    use super::{MidiOutput, matches_device};
+
+    /// Device id of the MIDI mapper, which plays through the default synth.
+    const MIDI_MAPPER: u32 = u32::MAX;
+    const CALLBACK_NULL: u32 = 0;
+    const MMSYSERR_NOERROR: u32 = 0;
+    const MIDIERR_STILLPLAYING: u32 = 65;
+
+    type HMidiOut = *mut c_void;
+
+    /// `MIDIOUTCAPSW`
+    #[repr(C)]
+    struct MidiOutCaps {
+        mid: u16,
+        pid: u16,
+        driver_version: u32,
+        name: [u16; 32],
+        technology: u16,
+        voices: u16,
+        notes: u16,
+        channel_mask: u16,
+        support: u32,
+    }
+
+    /// `MIDIHDR`
+    #[repr(C)]
+    struct MidiHdr {
+        data: *mut u8,
+        buffer_length: u32,
+        bytes_recorded: u32,
+        user: usize,
+        flags: u32,
+        next: *mut MidiHdr,
+        reserved: usize,
+        offset: u32,
+        reserved_2: [usize; 8],
+    }
+
+    /// The midiOut functions of winmm.dll.
+    struct Api {
+        get_num_devs: unsafe extern "system" fn() -> u32,
+        get_dev_caps: unsafe extern "system" fn(usize, *mut MidiOutCaps, u32) -> u32,
+        open: unsafe extern "system" fn(*mut HMidiOut, u32, usize, usize, u32) -> u32,
+        close: unsafe extern "system" fn(HMidiOut) -> u32,
+        reset: unsafe extern "system" fn(HMidiOut) -> u32,
+        short_msg: unsafe extern "system" fn(HMidiOut, u32) -> u32,
+        long_msg: unsafe extern "system" fn(HMidiOut, *mut MidiHdr, u32) -> u32,
+        prepare_header: unsafe extern "system" fn(HMidiOut, *mut MidiHdr, u32) -> u32,
+        unprepare_header: unsafe extern "system" fn(HMidiOut, *mut MidiHdr, u32) -> u32,
+        // Keeps the functions above loaded
+        _lib: Library,
+    }
+
+    /// Copy a function pointer out of `lib`.
+    ///
+    /// # Safety
+    ///
+    /// `T` must be the type of the exported function.
+    unsafe fn symbol<T: Copy>(lib: &Library, name: &str) -> Result<T, String> {
+        unsafe { lib.get::<T>(name.as_bytes()) }
+            .map(|f| *f)
+            .map_err(|e| format!("Failed to load {} from WinMM: {}", name, e))
+    }
+
+    impl Api {
+        fn load() -> Result<Self, String> {
+            unsafe {
+                let lib = Library::new("winmm.dll")
+                    .map_err(|e| format!("Failed to load WinMM: {}", e))?;
+                Ok(Api {
+                    get_num_devs: symbol(&lib, "midiOutGetNumDevs")?,
+                    get_dev_caps: symbol(&lib, "midiOutGetDevCapsW")?,
+                    open: symbol(&lib, "midiOutOpen")?,
+                    close: symbol(&lib, "midiOutClose")?,
+                    reset: symbol(&lib, "midiOutReset")?,
+                    short_msg: symbol(&lib, "midiOutShortMsg")?,
+                    long_msg: symbol(&lib, "midiOutLongMsg")?,
+                    prepare_header: symbol(&lib, "midiOutPrepareHeader")?,
+                    unprepare_header: symbol(&lib, "midiOutUnprepareHeader")?,
+                    _lib: lib,
+                })
+            }
+        }
+
+        /// Name of the device with the given id.
+        fn device_name(&self, id: u32) -> Option<String> {
+            let mut caps: MidiOutCaps = unsafe { std::mem::zeroed() };
+            let result = unsafe {
+                (self.get_dev_caps)(id as usize, &mut caps, size_of::<MidiOutCaps>() as u32)
+            };
+            if result != MMSYSERR_NOERROR {
+                return None;
+            }
+            let len = caps
+                .name
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(caps.name.len());
+            Some(String::from_utf16_lossy(&caps.name[..len]))
+        }
+
+        fn device_names(&self) -> Vec<String> {
+            let count = unsafe { (self.get_num_devs)() };
+            (0..count).filter_map(|id| self.device_name(id)).collect()
+        }
+    }
+
+    /// Sends to a Windows MIDI output device through WinMM, by default the
+    /// MIDI mapper, so files play through the built-in synth when no other
+    /// output is installed.
+    pub struct WinmmBackend {
+        api: Api,
+        // HMIDIOUT. WinMM calls on it are thread-safe, so it isn't locked
+        handle: usize,
+        // Held while sending SysEx so buffers don't interleave
+        long_lock: Mutex<()>,
+        device_name: String,
+    }
+
+    impl WinmmBackend {
+        /// Names of the available output devices, indexed by device id.
+        pub fn device_names() -> Result<Vec<String>, String> {
+            Ok(Api::load()?.device_names())
+        }
+
+        /// Open the device at index `wanted` in `device_names`, or the first
+        /// device whose name contains it, or the MIDI mapper if `wanted` is
+        /// `None`.
+        pub fn open(wanted: Option<&str>) -> Result<Self, String> {
+            let api = Api::load()?;
+            let (id, device_name) = match wanted {
+                None => (
+                    MIDI_MAPPER,
+                    api.device_name(MIDI_MAPPER)
+                        .unwrap_or_else(|| "MIDI Mapper".to_string()),
+                ),
+                Some(wanted) => api
+                    .device_names()
+                    .into_iter()
+                    .enumerate()
+                    .find(|(index, name)| matches_device(wanted, *index, name))
+                    .map(|(id, name)| (id as u32, name))
+                    .ok_or_else(|| format!("No MIDI output device matching '{}'", wanted))?,
+            };
+
+            let mut handle: HMidiOut = std::ptr::null_mut();
+            let result = unsafe { (api.open)(&mut handle, id, 0, 0, CALLBACK_NULL) };
+            if result != MMSYSERR_NOERROR {
+                return Err(format!(
+                    "Failed to open MIDI output device '{}' (error {})",
+                    device_name, result
+                ));
+            }
+            Ok(WinmmBackend {
+                api,
+                handle: handle as usize,
+                long_lock: Mutex::new(()),
+                device_name,
+            })
+        }
+
+        /// Name of the open device.
+        pub fn device_name(&self) -> &str {
+            &self.device_name
+        }
+    }
+
+    impl MidiOutput for WinmmBackend {
+        fn send_direct_data(&self, data: u32) {
+            unsafe { (self.api.short_msg)(self.handle as HMidiOut, data) };
+        }
+
+        fn send_long_data(&self, data: &[u8]) {
+            let Ok(_guard) = self.long_lock.lock() else {
+                return;
+            };
+            let handle = self.handle as HMidiOut;
+            let mut buffer = data.to_vec();
+            let mut header: MidiHdr = unsafe { std::mem::zeroed() };
+            header.data = buffer.as_mut_ptr();
+            header.buffer_length = buffer.len() as u32;
+            header.bytes_recorded = buffer.len() as u32;
+            let size = size_of::<MidiHdr>() as u32;
+
+            unsafe {
+                if (self.api.prepare_header)(handle, &mut header, size) != MMSYSERR_NOERROR {
+                    return;
+                }
+                (self.api.long_msg)(handle, &mut header, size);
+                // The buffer has to stay alive until the device is done with it
+                while (self.api.unprepare_header)(handle, &mut header, size) == MIDIERR_STILLPLAYING
+                {
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+        }
+
+        fn reset(&self) {
+            unsafe { (self.api.reset)(self.handle as HMidiOut) };
+        }
+    }
+
+    impl Drop for WinmmBackend {
+        fn drop(&mut self) {
+            unsafe {
+                (self.api.reset)(self.handle as HMidiOut);
+                (self.api.close)(self.handle as HMidiOut);
+            }
+        }
+    }
+}
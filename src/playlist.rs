@@ -0,0 +1,58 @@
+// Building the list of files to play.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Whether `path` has a `.mid`, `.midi` or `.rmi` extension, optionally
+/// followed by `.gz`.
+pub fn is_midi_file(path: &Path) -> bool {
+    let path = match path.extension() {
+        Some(ext) if ext.eq_ignore_ascii_case("gz") => Path::new(path.file_stem().unwrap()),
+        _ => path,
+    };
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            ["mid", "midi", "rmi"]
+                .iter()
+                .any(|midi| ext.eq_ignore_ascii_case(midi))
+        })
+}
+
+/// Expand the given paths into a playlist.
+///
+/// Files are kept in the given order. Directories are replaced by the MIDI
+/// files directly inside them, sorted by name.
+pub fn expand_playlist<P: AsRef<Path>>(paths: &[P]) -> io::Result<Vec<PathBuf>> {
+    let mut playlist = Vec::new();
+    for path in paths {
+        let path = path.as_ref();
+        if path.is_dir() {
+            let mut files = Vec::new();
+            for entry in fs::read_dir(path)? {
+                let entry_path = entry?.path();
+                if entry_path.is_file() && is_midi_file(&entry_path) {
+                    files.push(entry_path);
+                }
+            }
+            files.sort();
+            playlist.extend(files);
+        } else {
+            playlist.push(path.to_path_buf());
+        }
+    }
+    Ok(playlist)
+}
+
+/// Shuffle `items` in place with a Fisher-Yates shuffle seeded by `seed`.
+pub fn shuffle<T>(items: &mut [T], seed: u64) {
+    // xorshift64, which never leaves the zero state
+    let mut state = seed | 1;
+    for i in (1..items.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        items.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+}
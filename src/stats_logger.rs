@@ -1,37 +1,325 @@
-use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
+use crate::output::MidiOutput;
+
+/// Counts events in a ring of frames covering a sliding window of time.
 pub struct StatsLogger {
-    fps: usize,
     buffer_size: usize,
+    frame_length: Duration,
     history: Vec<AtomicU32>,
+    /// When each frame started, in nanoseconds since `created`
+    frame_starts: Vec<AtomicU64>,
     current_frame: AtomicUsize,
+    created: Instant,
+    peak_eps: AtomicU32,
 }
 
 impl StatsLogger {
-    pub fn new(fps: usize) -> Self {
+    /// A logger over `window`, split into `buckets` frames of equal length.
+    /// The frames are only advanced by `next_frame`, ideally once every
+    /// `frame_length`, but the rate is worked out from the times the frames
+    /// actually started.
+    pub fn new(window: Duration, buckets: usize) -> Self {
+        let buckets = buckets.max(1);
         Self {
-            fps,
-            buffer_size: fps,
-            history: (0..fps).map(|_| AtomicU32::new(0)).collect(),
+            buffer_size: buckets,
+            frame_length: window / buckets as u32,
+            history: (0..buckets).map(|_| AtomicU32::new(0)).collect(),
+            frame_starts: (0..buckets).map(|_| AtomicU64::new(0)).collect(),
             current_frame: AtomicUsize::new(0),
+            created: Instant::now(),
+            peak_eps: AtomicU32::new(0),
         }
     }
 
+    /// How often `next_frame` should be called to cover the window.
+    pub fn frame_length(&self) -> Duration {
+        self.frame_length
+    }
+
     pub fn increment(&self, by: u32) {
-        let idx = self.current_frame.load(Ordering::Relaxed);
+        let idx = self.current_frame.load(Ordering::Acquire);
         self.history[idx].fetch_add(by, Ordering::Relaxed);
     }
 
+    /// Start a new frame, dropping the oldest one. Meant to be called from
+    /// one thread, but `increment` may run on others at the same time.
     pub fn next_frame(&self) {
+        self.next_frame_at(Instant::now());
+    }
+
+    /// `next_frame`, with the frame ending at `now`.
+    pub fn next_frame_at(&self, now: Instant) {
+        self.peak_eps.fetch_max(self.get_eps_at(now), Ordering::Relaxed);
+
+        // Clear the bucket before moving to it, so an increment that already
+        // sees the new frame can't be wiped out. Increments that read the
+        // old index still land in the frame just finished.
         let next = (self.current_frame.load(Ordering::Relaxed) + 1) % self.buffer_size;
-        self.current_frame.store(next, Ordering::Relaxed);
-        self.history[next].store(0, Ordering::Relaxed);
+        self.history[next].store(0, Ordering::Release);
+        self.frame_starts[next].store(self.nanos_since_created(now), Ordering::Relaxed);
+        self.current_frame.store(next, Ordering::Release);
+    }
+
+    fn nanos_since_created(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.created).as_nanos() as u64
     }
 
+    /// Events counted in the frame being filled.
+    pub fn get_frame_events(&self) -> u32 {
+        self.history[self.current_frame.load(Ordering::Relaxed)].load(Ordering::Relaxed)
+    }
+
+    /// Events per second over the frames in the ring, including the one
+    /// being filled.
     pub fn get_eps(&self) -> u32 {
+        self.get_eps_at(Instant::now())
+    }
+
+    /// `get_eps`, measured up to `now`.
+    pub fn get_eps_at(&self, now: Instant) -> u32 {
+        // The frame after the current one is the oldest, or starts at zero
+        // like every frame until the ring has gone round once
+        let oldest = (self.current_frame.load(Ordering::Acquire) + 1) % self.buffer_size;
+        let start = self.frame_starts[oldest].load(Ordering::Relaxed);
+        let elapsed = self.nanos_since_created(now).saturating_sub(start);
+        if elapsed == 0 {
+            return 0;
+        }
+        let events = self.get_window_events() as f64;
+        (events * 1e9 / elapsed as f64).round() as u32
+    }
+
+    /// Events in all the frames of the ring, including the one being filled.
+    pub fn get_window_events(&self) -> u32 {
         self.history
             .iter()
             .map(|x| x.load(Ordering::Relaxed))
             .sum()
     }
+
+    /// Highest `get_eps` value seen at the end of any frame so far.
+    pub fn get_peak_eps(&self) -> u32 {
+        self.peak_eps.load(Ordering::Relaxed)
+    }
+
+    /// Events in the quietest completed frame of the history.
+    pub fn get_min(&self) -> u32 {
+        self.snapshot().into_iter().min().unwrap_or(0)
+    }
+
+    /// Events in the busiest completed frame of the history.
+    pub fn get_max(&self) -> u32 {
+        self.snapshot().into_iter().max().unwrap_or(0)
+    }
+
+    /// The `p`th percentile (0-100) of events per completed frame, using the
+    /// nearest-rank method.
+    pub fn get_percentile(&self, p: f64) -> u32 {
+        let mut frames = self.snapshot();
+        if frames.is_empty() {
+            return 0;
+        }
+        frames.sort_unstable();
+        let rank = (p.clamp(0.0, 100.0) / 100.0 * frames.len() as f64).ceil() as usize;
+        frames[rank.saturating_sub(1)]
+    }
+
+    /// Per-frame counts of every frame except the one being filled.
+    fn snapshot(&self) -> Vec<u32> {
+        let current = self.current_frame.load(Ordering::Relaxed);
+        self.history
+            .iter()
+            .enumerate()
+            .filter(|&(idx, _)| idx != current)
+            .map(|(_, x)| x.load(Ordering::Relaxed))
+            .collect()
+    }
+}
+
+/// The stats of one frame, handed to the callback of `StatsMonitor`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameStats {
+    /// Time since the monitor started.
+    pub timestamp: Duration,
+    pub events_this_frame: u32,
+    /// Events per second over the window, see `StatsLogger::get_eps`.
+    pub events_per_sec: u32,
+    /// Synth stats, if the output provides them.
+    pub voice_count: Option<u64>,
+    pub rendering_time: Option<f32>,
+}
+
+/// Counts events on a thread of its own and hands the stats of every frame
+/// to a callback, e.g. to print them or draw a meter.
+///
+/// Playback only adds to an atomic counter, see `counter`, so sending stays
+/// cheap. Players that don't need stats can leave the monitor out and skip
+/// the thread.
+pub struct StatsMonitor {
+    logger: Arc<StatsLogger>,
+    counter: Arc<AtomicU32>,
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StatsMonitor {
+    /// Start counting over `window`, in `buckets` frames, calling `on_frame`
+    /// at the end of each one. The synth stats are read from `output`, if
+    /// any.
+    pub fn start(
+        window: Duration,
+        buckets: usize,
+        output: Option<Arc<dyn MidiOutput + Send + Sync>>,
+        mut on_frame: impl FnMut(FrameStats) + Send + 'static,
+    ) -> Self {
+        let logger = Arc::new(StatsLogger::new(window, buckets));
+        let frame = logger.frame_length();
+        let counter = Arc::new(AtomicU32::new(0));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let thread_logger = Arc::clone(&logger);
+        let thread_counter = Arc::clone(&counter);
+        let thread_done = Arc::clone(&done);
+        let thread = thread::spawn(move || {
+            let started = Instant::now();
+            let mut last_frame = started;
+            while !thread_done.load(Ordering::Relaxed) {
+                // drain the atomic into the current frame
+                let count = thread_counter.swap(0, Ordering::Relaxed);
+                if count > 0 {
+                    thread_logger.increment(count);
+                }
+
+                if last_frame.elapsed() >= frame {
+                    on_frame(FrameStats {
+                        timestamp: started.elapsed(),
+                        events_this_frame: thread_logger.get_frame_events(),
+                        events_per_sec: thread_logger.get_eps(),
+                        voice_count: output.as_ref().and_then(|output| output.voice_count()),
+                        rendering_time: output.as_ref().and_then(|output| output.rendering_time()),
+                    });
+                    thread_logger.next_frame();
+                    last_frame = Instant::now();
+                }
+
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+
+        StatsMonitor {
+            logger,
+            counter,
+            done,
+            thread: Some(thread),
+        }
+    }
+
+    /// Counter to add every event sent to.
+    pub fn counter(&self) -> Arc<AtomicU32> {
+        Arc::clone(&self.counter)
+    }
+
+    /// The history of the frames so far.
+    pub fn logger(&self) -> &StatsLogger {
+        &self.logger
+    }
+
+    /// Stop the thread, returning the history for a final summary.
+    pub fn stop(mut self) -> Arc<StatsLogger> {
+        self.join();
+        Arc::clone(&self.logger)
+    }
+
+    fn join(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for StatsMonitor {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+/// Number of rows buffered before the CSV file is flushed.
+const CSV_FLUSH_ROWS: usize = 60;
+
+/// Writes one row of stats per frame to a CSV file.
+pub struct StatsCsv {
+    writer: BufWriter<File>,
+    pending_rows: usize,
+}
+
+impl StatsCsv {
+    /// Create the file, replacing any existing one, and write the header.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "timestamp,events_this_frame,events_per_sec,voice_count,rendering_time"
+        )?;
+        Ok(StatsCsv {
+            writer,
+            pending_rows: 0,
+        })
+    }
+
+    /// Append the row of a `StatsMonitor` frame.
+    pub fn write_frame(&mut self, stats: &FrameStats) -> io::Result<()> {
+        self.write_row(
+            stats.timestamp,
+            stats.events_this_frame,
+            stats.events_per_sec,
+            stats.voice_count,
+            stats.rendering_time,
+        )
+    }
+
+    /// Append a row. Stats the output doesn't provide are left empty.
+    /// The file is flushed every `CSV_FLUSH_ROWS` rows.
+    pub fn write_row(
+        &mut self,
+        timestamp: Duration,
+        events_this_frame: u32,
+        events_per_sec: u32,
+        voice_count: Option<u64>,
+        rendering_time: Option<f32>,
+    ) -> io::Result<()> {
+        write!(
+            self.writer,
+            "{:.3},{},{},",
+            timestamp.as_secs_f64(),
+            events_this_frame,
+            events_per_sec
+        )?;
+        if let Some(voices) = voice_count {
+            write!(self.writer, "{}", voices)?;
+        }
+        write!(self.writer, ",")?;
+        if let Some(time) = rendering_time {
+            write!(self.writer, "{}", time)?;
+        }
+        writeln!(self.writer)?;
+
+        self.pending_rows += 1;
+        if self.pending_rows >= CSV_FLUSH_ROWS {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.pending_rows = 0;
+        self.writer.flush()
+    }
 }
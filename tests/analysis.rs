@@ -0,0 +1,112 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{TrackBuilder, parse, smf, two_track_song, write_temp};
+use midiplayer_rs::midi::analysis::{min_send_budget, render_timeline, tick_at_time};
+use midiplayer_rs::midi::loader::load_midi_file;
+use midiplayer_rs::midi::player::parse_midi_events;
+
+#[test]
+fn finds_tightest_interval_with_tempo_applied() {
+    let tracks = vec![
+        TrackBuilder::new()
+            .tempo(0, 500_000)
+            .tempo(96, 250_000)
+            .end(0),
+        TrackBuilder::new()
+            .note_on(0, 0, 60, 100)
+            .note_off(10, 0, 60)
+            .note_on(86, 0, 62, 100)
+            .note_off(12, 0, 62)
+            .end(0),
+    ];
+    let path = write_temp(&smf(1, 96, &tracks));
+    let (loaded, time_div, _) = load_midi_file(&path).unwrap();
+    let parsed = parse_midi_events(loaded, time_div);
+
+    let budget = min_send_budget(&parsed, time_div).unwrap();
+    // 10 ticks at 500ms/qn is ~52ms, 12 ticks at 250ms/qn is 31.25ms.
+    assert_eq!(budget.interval, Duration::from_micros(31_250));
+    assert_eq!(budget.tick, 96);
+}
+
+#[test]
+fn returns_none_without_gaps() {
+    let track = TrackBuilder::new()
+        .note_on(0, 0, 60, 100)
+        .note_on(0, 0, 64, 100)
+        .end(0);
+    let path = write_temp(&smf(1, 96, &[track]));
+    let (loaded, time_div, _) = load_midi_file(&path).unwrap();
+    let parsed = parse_midi_events(loaded, time_div);
+
+    assert_eq!(min_send_budget(&parsed, time_div), None);
+}
+
+#[test]
+fn finds_tick_at_time() {
+    // 600ms per quarter note for the first 192 ticks, then 300ms
+    let parsed = parse(96, &two_track_song());
+
+    assert_eq!(tick_at_time(&parsed, 96, Duration::ZERO), Some(0));
+    assert_eq!(
+        tick_at_time(&parsed, 96, Duration::from_millis(600)),
+        Some(96)
+    );
+    assert_eq!(
+        tick_at_time(&parsed, 96, Duration::from_millis(601)),
+        Some(192)
+    );
+    assert_eq!(
+        tick_at_time(&parsed, 96, Duration::from_millis(1500)),
+        Some(288)
+    );
+    assert_eq!(tick_at_time(&parsed, 96, Duration::from_secs(2)), None);
+}
+
+#[test]
+fn renders_timestamps_with_tempo_changes() {
+    let parsed = parse(96, &two_track_song());
+
+    let timeline: Vec<(Duration, u32)> = render_timeline(&parsed, 96)
+        .into_iter()
+        .map(|(time, event)| (time, event.data()))
+        .collect();
+    assert_eq!(
+        timeline,
+        vec![
+            (Duration::ZERO, 600_000),
+            (Duration::ZERO, parsed.events.get(1).unwrap().data()),
+            (
+                Duration::from_millis(600),
+                parsed.events.get(2).unwrap().data()
+            ),
+            (Duration::from_millis(1_200), 300_000),
+            (
+                Duration::from_millis(1_200),
+                parsed.events.get(4).unwrap().data()
+            ),
+            // The second tempo halves the length of the last beat
+            (
+                Duration::from_millis(1_500),
+                parsed.events.get(5).unwrap().data()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn carries_rounding_between_timestamps() {
+    // 1/3 of a 100ns unit per tick
+    let mut track = TrackBuilder::new().tempo(0, 1);
+    for _ in 0..30 {
+        track = track.note_on(1, 0, 60, 100);
+    }
+    let parsed = parse(30, &[track.end(0)]);
+
+    let timeline = render_timeline(&parsed, 30);
+    let last = timeline.last().unwrap().0;
+    // Truncating each gap separately would end at 0.
+    assert!(last >= Duration::from_nanos(900), "{:?}", last);
+}
@@ -0,0 +1,208 @@
+mod common;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::{TrackBuilder, note, parse, two_track_song};
+use midiplayer_rs::midi::analysis::render_timeline;
+use midiplayer_rs::midi::clock::{
+    CLOCK, CONTINUE, SONG_POSITION, START, STOP, clock_ticks, song_position, with_midi_clock,
+};
+use midiplayer_rs::midi::player::{PlaybackOptions, play_parsed_events_with_options};
+
+#[test]
+fn ticks_24_times_per_quarter_note() {
+    let parsed = parse(96, &two_track_song());
+    let ticks = clock_ticks(&parsed, 96);
+    assert_eq!(ticks.len(), 72);
+    assert!(
+        ticks
+            .iter()
+            .enumerate()
+            .all(|(n, &tick)| tick == n as u64 * 4)
+    );
+
+    // A division that isn't a multiple of 24 rounds down
+    let parsed = parse(
+        100,
+        &[TrackBuilder::new()
+            .note_on(0, 0, 60, 100)
+            .note_off(25, 0, 60)
+            .end(0)],
+    );
+    assert_eq!(clock_ticks(&parsed, 100), vec![0, 4, 8, 12, 16, 20]);
+
+    // SMPTE files have no quarter notes
+    assert!(clock_ticks(&parsed, 0xE728).is_empty());
+}
+
+#[test]
+fn clock_rate_follows_tempo_changes() {
+    let parsed = parse(96, &two_track_song());
+    let with_clock = with_midi_clock(&parsed, 96);
+    assert_eq!(with_clock.note_count, parsed.note_count);
+
+    let sync: Vec<(Duration, u32)> = render_timeline(&with_clock, 96)
+        .into_iter()
+        .filter(|(_, event)| !event.is_tempo() && matches!(event.data(), CLOCK | START | STOP))
+        .map(|(time, event)| (time, event.data()))
+        .collect();
+    assert_eq!(sync.first(), Some(&(Duration::ZERO, START)));
+    assert_eq!(sync.last(), Some(&(Duration::from_millis(1500), STOP)));
+
+    // 25ms apart at 600ms/qn, 12.5ms from tick 192 at 300ms/qn
+    let clocks: Vec<Duration> = sync
+        .iter()
+        .filter(|&&(_, data)| data == CLOCK)
+        .map(|&(time, _)| time)
+        .collect();
+    assert_eq!(clocks[1], Duration::from_millis(25));
+    assert_eq!(clocks[48], Duration::from_millis(1200));
+    assert_eq!(clocks[49], Duration::from_micros(1_212_500));
+    assert_eq!(clocks[71], Duration::from_micros(1_487_500));
+}
+
+#[test]
+fn starts_at_the_beginning_of_the_file() {
+    let tracks = vec![
+        TrackBuilder::new()
+            .note_on(48, 0, 60, 100)
+            .note_off(48, 0, 60)
+            .end(0),
+    ];
+    let parsed = parse(96, &tracks);
+    assert_eq!(parsed.first_tick, 48);
+    let with_clock = with_midi_clock(&parsed, 96);
+
+    // The note keeps its place, after the 12 clocks of its first eighth note
+    let timeline = render_timeline(&with_clock, 96);
+    assert_eq!(timeline[0].1.data(), START);
+    let note_on = timeline
+        .iter()
+        .position(|(_, event)| event.data() == note(0x90, 60, 100))
+        .unwrap();
+    assert_eq!(
+        timeline[..note_on]
+            .iter()
+            .filter(|(_, event)| event.data() == CLOCK)
+            .count(),
+        12
+    );
+}
+
+#[test]
+fn sends_clock_when_enabled() {
+    let parsed = parse(96, &two_track_song());
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent_clone = Arc::clone(&sent);
+    let options = PlaybackOptions {
+        midi_clock: true,
+        let_notes_ring: true,
+        ..Default::default()
+    };
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |data, track| sent_clone.lock().unwrap().push((data, track)),
+        Some(Box::new(|_| {})),
+        options,
+    );
+
+    // Clock comes after the file's events at the same tick, on a track of
+    // its own
+    let sent = sent.lock().unwrap();
+    assert_eq!(
+        sent[..4],
+        [(note(0x90, 60, 100), 1), (START, 2), (CLOCK, 2), (CLOCK, 2),]
+    );
+    assert_eq!(sent.iter().filter(|&&(data, _)| data == CLOCK).count(), 72);
+    assert_eq!(sent[sent.len() - 2..], [(note(0x80, 62, 0), 1), (STOP, 2)]);
+}
+
+/// The messages sent while playing `two_track_song` with MIDI Clock.
+fn play_with_clock(options: PlaybackOptions) -> Vec<(u32, u16)> {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent_clone = Arc::clone(&sent);
+    play_parsed_events_with_options(
+        &parse(96, &two_track_song()),
+        96,
+        move |data, track| sent_clone.lock().unwrap().push((data, track)),
+        Some(Box::new(|_| {})),
+        PlaybackOptions {
+            midi_clock: true,
+            let_notes_ring: true,
+            ..options
+        },
+    );
+    Arc::try_unwrap(sent).unwrap().into_inner().unwrap()
+}
+
+#[test]
+fn song_position_counts_sixteenth_notes() {
+    assert_eq!(song_position(0, 96), SONG_POSITION);
+    // 200 beats, rounded down from the tick
+    assert_eq!(
+        song_position(4823, 96),
+        SONG_POSITION | 0x48 << 8 | 0x01 << 16
+    );
+    assert_eq!(song_position(u64::MAX / 8, 96), SONG_POSITION | 0x7F7F00);
+    assert_eq!(song_position(4823, 0xE728), SONG_POSITION);
+}
+
+#[test]
+fn continues_from_the_seek_point() {
+    let sent = play_with_clock(PlaybackOptions {
+        start_tick: 100,
+        ..Default::default()
+    });
+
+    assert_eq!(
+        sent[..3],
+        [(song_position(100, 96), 2), (CONTINUE, 2), (CLOCK, 2)]
+    );
+    assert!(!sent.contains(&(START, 2)));
+    assert_eq!(sent.last(), Some(&(STOP, 2)));
+    assert_eq!(sent.iter().filter(|&&(data, _)| data == STOP).count(), 1);
+}
+
+#[test]
+fn stops_the_clock_at_the_end_tick() {
+    let sent = play_with_clock(PlaybackOptions {
+        end_tick: Some(96),
+        ..Default::default()
+    });
+
+    assert_eq!(sent[1], (START, 2));
+    assert_eq!(sent.last(), Some(&(STOP, 2)));
+    assert_eq!(sent.iter().filter(|&&(data, _)| data == STOP).count(), 1);
+}
+
+#[test]
+fn stops_the_clock_when_playback_is_stopped() {
+    let stop = Arc::new(AtomicBool::new(false));
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let stop_clone = Arc::clone(&stop);
+    let sent_clone = Arc::clone(&sent);
+    play_parsed_events_with_options(
+        &parse(96, &two_track_song()),
+        96,
+        move |data, track| {
+            sent_clone.lock().unwrap().push((data, track));
+            if data == note(0x90, 60, 100) {
+                stop_clone.store(true, Ordering::Relaxed);
+            }
+        },
+        Some(Box::new(|_| {})),
+        PlaybackOptions {
+            midi_clock: true,
+            stop: Some(stop),
+            ..Default::default()
+        },
+    );
+
+    let sent = sent.lock().unwrap();
+    assert_eq!(sent.last(), Some(&(STOP, 2)));
+    assert_eq!(sent.iter().filter(|&&(data, _)| data == CLOCK).count(), 1);
+}
@@ -0,0 +1,134 @@
+// Helpers for building tiny MIDI files in-code.
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use midiplayer_rs::midi::loader::load_midi_file;
+use midiplayer_rs::midi::player::{ParsedMidi, parse_midi_events};
+
+/// Encode a value as a MIDI variable-length quantity.
+pub fn varlen(mut value: u32) -> Vec<u8> {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    value >>= 7;
+    while value > 0 {
+        bytes.push(((value & 0x7F) as u8) | 0x80);
+        value >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
+/// Builds the body of a single MTrk chunk.
+#[derive(Default)]
+pub struct TrackBuilder {
+    bytes: Vec<u8>,
+}
+
+impl TrackBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a raw event (status + data bytes) after `delta` ticks.
+    pub fn event(mut self, delta: u32, bytes: &[u8]) -> Self {
+        self.bytes.extend(varlen(delta));
+        self.bytes.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn note_on(self, delta: u32, channel: u8, key: u8, velocity: u8) -> Self {
+        self.event(delta, &[0x90 | channel, key, velocity])
+    }
+
+    pub fn note_off(self, delta: u32, channel: u8, key: u8) -> Self {
+        self.event(delta, &[0x80 | channel, key, 0])
+    }
+
+    pub fn meta(mut self, delta: u32, kind: u8, data: &[u8]) -> Self {
+        self.bytes.extend(varlen(delta));
+        self.bytes.extend_from_slice(&[0xFF, kind]);
+        self.bytes.extend(varlen(data.len() as u32));
+        self.bytes.extend_from_slice(data);
+        self
+    }
+
+    pub fn tempo(self, delta: u32, us_per_qn: u32) -> Self {
+        let t = us_per_qn.to_be_bytes();
+        self.meta(delta, 0x51, &t[1..])
+    }
+
+    /// Append End of Track and return the track body.
+    pub fn end(self, delta: u32) -> Vec<u8> {
+        self.meta(delta, 0x2F, &[]).bytes
+    }
+
+    /// Return the track body as-is, without End of Track.
+    pub fn build(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Wrap a raw chunk body with its 4-byte id and length.
+pub fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut bytes = id.to_vec();
+    bytes.extend((body.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+/// Build a complete SMF with a header and one MTrk per track body.
+pub fn smf(format: u16, time_div: u16, tracks: &[Vec<u8>]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(6);
+    header.extend(format.to_be_bytes());
+    header.extend((tracks.len() as u16).to_be_bytes());
+    header.extend(time_div.to_be_bytes());
+
+    let mut bytes = chunk(b"MThd", &header);
+    for track in tracks {
+        bytes.extend(chunk(b"MTrk", track));
+    }
+    bytes
+}
+
+/// Write `bytes` to a unique file in the temp dir and return its path.
+pub fn write_temp(bytes: &[u8]) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!(
+        "midiplayer_rs_test_{}_{}.mid",
+        std::process::id(),
+        n
+    ));
+    std::fs::write(&path, bytes).expect("write temp midi file");
+    path
+}
+
+/// Pack a short message the way the parser stores it.
+pub fn note(status: u8, key: u8, velocity: u8) -> u32 {
+    u32::from(status) | (u32::from(key) << 8) | (u32::from(velocity) << 16)
+}
+
+/// Write `tracks` as a Format 1 file, load it and parse it.
+pub fn parse(time_div: u16, tracks: &[Vec<u8>]) -> ParsedMidi {
+    let path = write_temp(&smf(1, time_div, tracks));
+    let (loaded, time_div, _) = load_midi_file(&path).unwrap();
+    parse_midi_events(loaded, time_div)
+}
+
+/// Two tracks: a conductor track with two tempo changes and a melody track
+/// with two notes, each a quarter note long.
+pub fn two_track_song() -> Vec<Vec<u8>> {
+    vec![
+        TrackBuilder::new()
+            .tempo(0, 600_000)
+            .tempo(192, 300_000)
+            .end(0),
+        TrackBuilder::new()
+            .note_on(0, 0, 60, 100)
+            .note_off(96, 0, 60)
+            .note_on(96, 0, 62, 100)
+            .note_off(96, 0, 62)
+            .end(0),
+    ]
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use common::{TrackBuilder, note, parse};
+use midiplayer_rs::midi::dedupe::dedupe_note_ons;
+use midiplayer_rs::midi::player::ParsedMidi;
+
+/// The tick and message of every short event, in playback order.
+fn timeline(parsed: &ParsedMidi) -> Vec<(u64, u32)> {
+    parsed
+        .iter_with_ticks()
+        .filter(|(_, event)| !event.is_tempo() && !event.is_long())
+        .map(|(tick, event)| (tick, event.data()))
+        .collect()
+}
+
+#[test]
+fn collapses_stacked_note_ons() {
+    let tracks = vec![
+        TrackBuilder::new()
+            .note_on(0, 0, 60, 100)
+            .note_on(0, 0, 60, 100)
+            .note_off(48, 0, 60)
+            .note_off(48, 0, 60)
+            .end(0),
+        TrackBuilder::new()
+            .note_on(0, 0, 60, 90)
+            // Other keys and channels are left alone
+            .note_on(0, 0, 62, 90)
+            .note_on(0, 1, 60, 90)
+            .note_off(96, 0, 60)
+            .note_off(0, 0, 62)
+            .note_off(0, 1, 60)
+            .end(0),
+    ];
+    let mut parsed = parse(96, &tracks);
+    assert_eq!(parsed.note_count, 5);
+
+    assert_eq!(dedupe_note_ons(&mut parsed), 2);
+    assert_eq!(parsed.note_count, 3);
+    assert_eq!(parsed.channel_note_counts[0], 2);
+    assert_eq!(parsed.channel_note_counts[1], 1);
+    assert_eq!(parsed.max_polyphony, 3);
+    assert_eq!(
+        timeline(&parsed),
+        vec![
+            (0, note(0x90, 60, 100)),
+            (0, note(0x90, 62, 90)),
+            (0, note(0x91, 60, 90)),
+            // The last Note Off of the stack releases the kept note
+            (96, note(0x80, 60, 0)),
+            (96, note(0x80, 62, 0)),
+            (96, note(0x81, 60, 0)),
+        ]
+    );
+}
+
+#[test]
+fn keeps_notes_on_different_ticks() {
+    let tracks = vec![
+        TrackBuilder::new()
+            .note_on(0, 0, 60, 100)
+            .note_on(24, 0, 60, 100)
+            .note_off(24, 0, 60)
+            .note_off(24, 0, 60)
+            .end(0),
+    ];
+    let mut parsed = parse(96, &tracks);
+    let before = timeline(&parsed);
+
+    assert_eq!(dedupe_note_ons(&mut parsed), 0);
+    assert_eq!(parsed.note_count, 2);
+    assert_eq!(timeline(&parsed), before);
+}
@@ -0,0 +1,419 @@
+mod common;
+
+use common::{TrackBuilder, chunk, smf, write_temp};
+use std::io::Cursor;
+use std::sync::Arc;
+
+use midiplayer_rs::midi::loader::{
+    LoadOptions, LoadWarning, MidiFormat, MidiLoadError, TrackSelection, load_midi_buffer_selected,
+    load_midi_bytes, load_midi_file, load_midi_file_selected, load_midi_reader,
+};
+use midiplayer_rs::midi::player::{load_and_parse, play_parsed_events};
+use midiplayer_rs::midi::track_data::{SharedBuffer, TrackBytes};
+
+#[test]
+fn loads_tracks_and_time_division() {
+    let tracks = vec![
+        TrackBuilder::new().tempo(0, 600_000).end(0),
+        TrackBuilder::new()
+            .note_on(200, 0, 60, 100)
+            .note_off(96, 0, 60)
+            .end(0),
+    ];
+    let path = write_temp(&smf(1, 480, &tracks));
+
+    let (loaded, time_div, _) = load_midi_file(&path).unwrap();
+    assert_eq!(time_div, 480);
+    assert_eq!(loaded.len(), 2);
+    assert_eq!(loaded[0].length, tracks[0].len());
+    assert_eq!(loaded[1].data, tracks[1]);
+
+    // The loader pre-reads the first delta of every track.
+    assert_eq!(loaded[0].tick, 0);
+    assert_eq!(loaded[1].tick, 200);
+}
+
+#[test]
+fn rejects_non_midi_data() {
+    let path = write_temp(b"RIFF\x00\x00\x00\x00");
+    let err = load_midi_file(&path).unwrap_err();
+    assert!(matches!(err, MidiLoadError::NotMidi), "{:?}", err);
+    assert_eq!(err.to_string(), "Not a MIDI file");
+}
+
+#[test]
+fn rejects_bad_header_length() {
+    let mut bytes = smf(1, 96, &[TrackBuilder::new().end(0)]);
+    bytes[7] = 7;
+    let path = write_temp(&bytes);
+    let err = load_midi_file(&path).unwrap_err();
+    assert!(
+        matches!(err, MidiLoadError::BadHeaderLength(7)),
+        "{:?}",
+        err
+    );
+}
+
+#[test]
+fn keeps_partial_data_of_truncated_track() {
+    let body = TrackBuilder::new().note_on(0, 0, 60, 100).end(0);
+    let mut bytes = smf(1, 96, std::slice::from_ref(&body));
+    bytes.truncate(bytes.len() - 2);
+    let path = write_temp(&bytes);
+
+    let (tracks, _, _) = load_midi_file(&path).unwrap();
+    assert_eq!(tracks.len(), 1);
+    assert_eq!(tracks[0].length, body.len() - 2);
+    assert_eq!(tracks[0].data, body[..body.len() - 2]);
+}
+
+#[test]
+fn stops_at_missing_tracks() {
+    let tracks = vec![
+        TrackBuilder::new().note_on(0, 0, 60, 100).end(0),
+        TrackBuilder::new().note_on(0, 0, 62, 100).end(0),
+    ];
+    let mut bytes = smf(1, 96, &tracks);
+    bytes.truncate(bytes.len() - tracks[1].len() - 8);
+    let path = write_temp(&bytes);
+
+    let (loaded, _, _) = load_midi_file(&path).unwrap();
+    assert_eq!(loaded.len(), 1);
+}
+
+/// Load `bytes` with `options`, returning the warnings.
+fn load_warnings(bytes: Vec<u8>, options: &LoadOptions) -> Vec<LoadWarning> {
+    load_midi_buffer_selected(Arc::new(bytes), None, options)
+        .unwrap()
+        .warnings
+}
+
+#[test]
+fn returns_load_warnings() {
+    let tracks = vec![
+        TrackBuilder::new().note_on(0, 0, 60, 100).end(0),
+        TrackBuilder::new().note_on(0, 0, 62, 100).end(0),
+    ];
+    let options = LoadOptions::default();
+    assert!(load_warnings(smf(1, 96, &tracks), &options).is_empty());
+
+    assert_eq!(
+        load_warnings(smf(7, 96, &tracks), &options),
+        vec![LoadWarning::UnknownFormat(7)]
+    );
+
+    let mut bytes = smf(1, 96, &tracks);
+    bytes.truncate(bytes.len() - tracks[1].len() - 8);
+    assert_eq!(
+        load_warnings(bytes, &options),
+        vec![LoadWarning::MissingTracks {
+            found: 1,
+            declared: 2
+        }]
+    );
+
+    let mut bytes = smf(1, 96, &tracks);
+    bytes.truncate(bytes.len() - 2);
+    let warnings = load_warnings(bytes, &options);
+    assert_eq!(
+        warnings,
+        vec![LoadWarning::TruncatedTrack {
+            index: 1,
+            read: tracks[1].len() - 2,
+            declared: tracks[1].len()
+        }]
+    );
+    assert_eq!(
+        warnings[0].to_string(),
+        "track 1 is truncated (6 of 8 bytes), playing what was read"
+    );
+
+    // The first track claims to end after its Note On
+    let mut bytes = smf(1, 96, &tracks);
+    bytes[18..22].copy_from_slice(&4u32.to_be_bytes());
+    assert_eq!(
+        load_warnings(bytes, &LoadOptions { repair: true }),
+        vec![LoadWarning::RepairedTrack {
+            index: 0,
+            length: tracks[0].len(),
+            declared: 4
+        }]
+    );
+}
+
+#[test]
+fn parses_track_selection() {
+    let sel: TrackSelection = "0-2, 5".parse().unwrap();
+    assert!((0..=2).all(|i| sel.contains(i)));
+    assert!(!sel.contains(3));
+    assert!(sel.contains(5));
+
+    assert!("".parse::<TrackSelection>().is_err());
+    assert!("3-1".parse::<TrackSelection>().is_err());
+    assert!("a".parse::<TrackSelection>().is_err());
+}
+
+#[test]
+fn skips_unselected_tracks() {
+    let tracks: Vec<Vec<u8>> = (0..4)
+        .map(|i| TrackBuilder::new().note_on(i, 0, 60, 100).end(0))
+        .collect();
+    let path = write_temp(&smf(1, 96, &tracks));
+    let sel: TrackSelection = "1,3".parse().unwrap();
+
+    let (loaded, _, _) = load_midi_file_selected(&path, Some(&sel), &LoadOptions::default())
+        .unwrap()
+        .into_parts();
+    assert_eq!(loaded.len(), 4);
+    assert_eq!(loaded[0].length, 0);
+    assert_eq!(loaded[1].data, tracks[1]);
+    assert_eq!(loaded[2].length, 0);
+    assert_eq!(loaded[3].tick, 3);
+}
+
+#[test]
+fn ignores_truncated_tracks_that_are_not_selected() {
+    let tracks: Vec<Vec<u8>> = (0..3)
+        .map(|i| TrackBuilder::new().note_on(i, 0, 60, 100).end(0))
+        .collect();
+    let mut bytes = smf(1, 96, &tracks);
+    bytes.truncate(bytes.len() - 2);
+    let sel: TrackSelection = "0-1".parse().unwrap();
+
+    let loaded =
+        load_midi_buffer_selected(Arc::new(bytes), Some(&sel), &LoadOptions::default()).unwrap();
+    assert_eq!(loaded.tracks.len(), 3);
+    assert_eq!(loaded.tracks[1].data, tracks[1]);
+    assert_eq!(loaded.tracks[2].length, 0);
+    assert!(loaded.warnings.is_empty());
+}
+
+#[test]
+fn loads_from_reader() {
+    let tracks = vec![TrackBuilder::new().note_on(7, 0, 60, 100).end(0)];
+    let (loaded, time_div, _) = load_midi_reader(Cursor::new(smf(1, 240, &tracks))).unwrap();
+    assert_eq!(time_div, 240);
+    assert_eq!(loaded.len(), 1);
+    assert_eq!(loaded[0].tick, 7);
+}
+
+#[test]
+fn skips_unknown_chunks() {
+    let tracks = [
+        TrackBuilder::new().note_on(1, 0, 60, 100).end(0),
+        TrackBuilder::new().note_on(2, 0, 62, 100).end(0),
+    ];
+    let mut bytes = smf(1, 96, &tracks);
+    // Wedge a proprietary chunk between the two tracks.
+    let second_track = 14 + 8 + tracks[0].len();
+    bytes.splice(
+        second_track..second_track,
+        chunk(b"XYZ ", b"proprietary data"),
+    );
+
+    let (loaded, _, _) = load_midi_reader(Cursor::new(bytes)).unwrap();
+    assert_eq!(loaded.len(), 2);
+    assert_eq!(loaded[0].data, tracks[0]);
+    assert_eq!(loaded[1].data, tracks[1]);
+}
+
+#[test]
+fn keeps_track_order_for_many_tracks() {
+    let tracks: Vec<Vec<u8>> = (0..500u32)
+        .map(|i| {
+            TrackBuilder::new()
+                .note_on(i, (i % 16) as u8, (i % 128) as u8, 100)
+                .end(0)
+        })
+        .collect();
+
+    let (loaded, _, _) = load_midi_bytes(&smf(1, 96, &tracks)).unwrap();
+    assert_eq!(loaded.len(), tracks.len());
+    for (i, (track, body)) in loaded.iter().zip(&tracks).enumerate() {
+        assert_eq!(track.data, *body, "track {}", i);
+        assert_eq!(track.tick, i as u64);
+    }
+}
+
+#[test]
+fn tracks_share_the_file_buffer() {
+    let tracks = vec![
+        TrackBuilder::new().note_on(0, 0, 60, 100).end(0),
+        TrackBuilder::new().note_on(0, 0, 62, 100).end(0),
+    ];
+    let (loaded, _, _) = load_midi_bytes(&smf(1, 96, &tracks)).unwrap();
+
+    // The second track starts right after the first one and its chunk header
+    let first = loaded[0].data.as_ptr();
+    let second = loaded[1].data.as_ptr();
+    assert_eq!(second as usize - first as usize, tracks[0].len() + 8);
+}
+
+#[test]
+fn borrows_track_bytes_from_a_buffer() {
+    let buffer: SharedBuffer = Arc::new(vec![1u8, 2, 3, 4, 5]);
+    let bytes = TrackBytes::new(Arc::clone(&buffer), 1..4);
+    assert_eq!(bytes, vec![2, 3, 4]);
+    assert_eq!(bytes.clone(), vec![2, 3, 4]);
+    assert!(TrackBytes::empty().is_empty());
+}
+
+#[test]
+fn returns_the_file_format() {
+    let track = TrackBuilder::new().note_on(0, 0, 60, 100).end(0);
+    for (raw, format) in [
+        (0, MidiFormat::SingleTrack),
+        (1, MidiFormat::Simultaneous),
+        (2, MidiFormat::Sequential),
+        // Unknown formats are read like Format 1
+        (7, MidiFormat::Simultaneous),
+    ] {
+        let bytes = smf(raw, 96, std::slice::from_ref(&track));
+        let (tracks, _, loaded_format) = load_midi_bytes(&bytes).unwrap();
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(loaded_format, format);
+    }
+    assert_eq!(
+        MidiFormat::Sequential.to_string(),
+        "2 (sequential patterns)"
+    );
+}
+
+#[test]
+fn reports_truncated_skipped_chunks_and_io_errors() {
+    let mut bytes = smf(1, 96, &[TrackBuilder::new().end(0)]);
+    bytes.extend(chunk(b"XFIH", &[0; 16]));
+    bytes[11] = 2;
+    bytes.truncate(bytes.len() - 4);
+    let err = load_midi_bytes(&bytes).unwrap_err();
+    assert!(
+        matches!(err, MidiLoadError::TruncatedTrack { index: 1 }),
+        "{:?}",
+        err
+    );
+
+    let err = load_midi_file("/nonexistent/song.mid").unwrap_err();
+    match err {
+        MidiLoadError::Io(err) => assert_eq!(err.kind(), std::io::ErrorKind::NotFound),
+        err => panic!("unexpected error {:?}", err),
+    }
+}
+
+/// Wrap an SMF in a RIFF RMID container, after an odd-sized chunk.
+fn rmid(smf: &[u8]) -> Vec<u8> {
+    let mut body = b"RMID".to_vec();
+    body.extend(b"DISP");
+    body.extend(3u32.to_le_bytes());
+    body.extend([1, 2, 3, 0]);
+    body.extend(b"data");
+    body.extend((smf.len() as u32).to_le_bytes());
+    body.extend_from_slice(smf);
+    body.extend(b"LIST");
+    body.extend(0u32.to_le_bytes());
+
+    let mut bytes = b"RIFF".to_vec();
+    bytes.extend((body.len() as u32).to_le_bytes());
+    bytes.extend(body);
+    bytes
+}
+
+#[test]
+fn loads_smf_from_rmid_container() {
+    let tracks = vec![
+        TrackBuilder::new().tempo(0, 400_000).end(0),
+        TrackBuilder::new()
+            .note_on(10, 0, 60, 100)
+            .note_off(96, 0, 60)
+            .end(0),
+    ];
+    let bytes = rmid(&smf(1, 240, &tracks));
+
+    let (loaded, time_div, _) = load_midi_bytes(&bytes).unwrap();
+    assert_eq!(time_div, 240);
+    assert_eq!(loaded.len(), 2);
+    assert_eq!(loaded[1].data, tracks[1]);
+    assert_eq!(loaded[1].tick, 10);
+
+    let path = write_temp(&bytes);
+    let (parsed, _) = load_and_parse(&path).unwrap();
+    assert_eq!(parsed.note_count, 1);
+}
+
+#[test]
+fn rejects_riff_files_without_midi_data() {
+    let mut wave = b"RIFF\x04\x00\x00\x00WAVE".to_vec();
+    wave.extend(b"data\x00\x00\x00\x00");
+    let err = load_midi_bytes(&wave).unwrap_err();
+    assert!(matches!(err, MidiLoadError::NotMidi), "{:?}", err);
+
+    let empty = b"RIFF\x04\x00\x00\x00RMID";
+    let err = load_midi_bytes(empty).unwrap_err();
+    assert!(matches!(err, MidiLoadError::NotMidi), "{:?}", err);
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn loads_gzip_compressed_files() {
+    use std::io::Write;
+
+    let tracks = vec![
+        TrackBuilder::new().tempo(0, 400_000).end(0),
+        TrackBuilder::new()
+            .note_on(10, 0, 60, 100)
+            .note_off(96, 0, 60)
+            .end(0),
+    ];
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&smf(1, 96, &tracks)).unwrap();
+    let bytes = encoder.finish().unwrap();
+
+    let (loaded, time_div, _) = load_midi_bytes(&bytes).unwrap();
+    assert_eq!(time_div, 96);
+    assert_eq!(loaded[1].data, tracks[1]);
+
+    let path = write_temp(&bytes);
+    let (parsed, _) = load_and_parse(&path).unwrap();
+    assert_eq!(parsed.note_count, 1);
+}
+
+#[cfg(not(feature = "gzip"))]
+#[test]
+fn reports_gzip_files_without_the_feature() {
+    let bytes = [0x1F, 0x8B, 0x08, 0x00];
+    for err in [
+        load_midi_bytes(&bytes).unwrap_err(),
+        load_and_parse(write_temp(&bytes)).unwrap_err(),
+    ] {
+        match err {
+            MidiLoadError::Io(err) => assert_eq!(err.kind(), std::io::ErrorKind::Unsupported),
+            err => panic!("unexpected error {:?}", err),
+        }
+    }
+}
+
+#[test]
+fn rejects_files_too_short_for_a_header() {
+    for bytes in [&b""[..], b"MTh", b"MThd\x00\x00\x00\x06\x00\x01"] {
+        let path = write_temp(bytes);
+        let expected = if bytes.len() < 4 {
+            "Not a MIDI file"
+        } else {
+            "The file ends in the middle of its header"
+        };
+        assert_eq!(load_midi_file(&path).unwrap_err().to_string(), expected);
+        assert_eq!(load_and_parse(&path).unwrap_err().to_string(), expected);
+        assert_eq!(load_midi_bytes(bytes).unwrap_err().to_string(), expected);
+    }
+}
+
+#[test]
+fn loads_files_without_tracks() {
+    let path = write_temp(&smf(1, 96, &[]));
+    let (tracks, _, _) = load_midi_file(&path).unwrap();
+    assert!(tracks.is_empty());
+
+    let (parsed, _) = load_and_parse(&path).unwrap();
+    assert_eq!(parsed.note_count, 0);
+    assert_eq!(parsed.total_ticks, 0);
+    play_parsed_events(&parsed, 96, |_, _| {}, Some(Box::new(|_| {})));
+}
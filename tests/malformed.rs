@@ -0,0 +1,219 @@
+mod common;
+
+use std::io::Cursor;
+use std::sync::Arc;
+
+use common::{TrackBuilder, chunk, parse, smf, two_track_song, write_temp};
+use midiplayer_rs::midi::loader::{
+    LoadOptions, MidiLoadError, load_midi_buffer_selected, load_midi_reader,
+};
+use midiplayer_rs::midi::player::{
+    self, ParseWarning, ParsedMidi, parse_midi_events, parse_midi_events_single_threaded,
+};
+use midiplayer_rs::midi::time_division::TimeDivision;
+use midiplayer_rs::midi::track_data::TrackData;
+
+/// Small xorshift generator so the byte streams are reproducible.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+/// Load and parse a file, panicking only if the parser itself does.
+fn load_and_parse(bytes: &[u8]) {
+    if let Ok((tracks, time_div, _)) = load_midi_reader(Cursor::new(bytes)) {
+        parse_midi_events_single_threaded(tracks, time_div);
+    }
+}
+
+/// A track body made of random bytes in a valid header.
+fn random_track_file(body: &[u8]) -> Vec<u8> {
+    let mut bytes = smf(1, 96, &[]);
+    // Patch the track count to 1
+    bytes[11] = 1;
+    bytes.extend(chunk(b"MTrk", body));
+    bytes
+}
+
+#[test]
+fn survives_every_truncation() {
+    let mut tracks = two_track_song();
+    tracks.push(
+        TrackBuilder::new()
+            .event(0, &[0xF0, 0x03, 0x7E, 0x01, 0xF7])
+            .event(10, &[0xC0, 5])
+            .event(0, &[0x90, 60, 100])
+            .event(0, &[64, 100])
+            .end(0),
+    );
+    let bytes = smf(1, 96, &tracks);
+
+    for len in 0..bytes.len() {
+        load_and_parse(&bytes[..len]);
+    }
+}
+
+#[test]
+fn survives_random_track_bodies() {
+    let mut rng = XorShift(0x9E37_79B9_7F4A_7C15);
+    for _ in 0..2000 {
+        let len = (rng.next() % 64) as usize;
+        let body = rng.bytes(len);
+        load_and_parse(&random_track_file(&body));
+    }
+}
+
+#[test]
+fn survives_random_mutations() {
+    let mut rng = XorShift(0xDEAD_BEEF_CAFE_F00D);
+    let bytes = smf(1, 96, &two_track_song());
+    for _ in 0..2000 {
+        let mut mutated = bytes.clone();
+        for _ in 0..1 + rng.next() % 4 {
+            let idx = (rng.next() as usize) % mutated.len();
+            mutated[idx] = rng.next() as u8;
+        }
+        load_and_parse(&mutated);
+    }
+}
+
+#[test]
+fn tolerates_length_past_data() {
+    // The declared length is larger than the data actually held
+    let mut track = TrackData::new(vec![0x00, 0x90, 60].into());
+    track.length = 16;
+    track.update_tick();
+    track.update_command();
+    track.update_message();
+    assert_eq!(track.length, 0);
+}
+
+#[test]
+fn reports_corrupt_tracks() {
+    let mut tracks = two_track_song();
+    // A meta event cancels running status
+    tracks.push(
+        TrackBuilder::new()
+            .note_on(0, 0, 60, 100)
+            .meta(0, 0x01, b"text")
+            .event(10, &[60, 0])
+            .end(0),
+    );
+    tracks.push(
+        TrackBuilder::new()
+            .note_on(0, 1, 60, 100)
+            .event(5, &[0x80, 60])
+            .build(),
+    );
+    tracks.push(
+        TrackBuilder::new()
+            .event(3, &[0xF0, 0x02, 0x7E, 0x01])
+            .end(0),
+    );
+    let parsed = parse(96, &tracks);
+
+    let warning = |track, tick, message| ParseWarning {
+        track,
+        tick,
+        message,
+    };
+    assert_eq!(
+        parsed.warnings,
+        vec![
+            warning(2, 10, "data byte without a running status"),
+            warning(3, 5, "the data ended in a message"),
+            warning(4, 3, "SysEx message without an end"),
+        ]
+    );
+    assert_eq!(
+        parsed.warnings[0].to_string(),
+        "track 2, tick 10: data byte without a running status"
+    );
+    // The events before the problems are kept
+    assert_eq!(parsed.note_count, 4);
+
+    assert!(parse(96, &two_track_song()).warnings.is_empty());
+}
+
+/// Load a file from memory with repairs on or off, and parse it.
+fn load_repaired(bytes: Vec<u8>, repair: bool) -> ParsedMidi {
+    let (tracks, time_div, _) =
+        load_midi_buffer_selected(Arc::new(bytes), None, &LoadOptions { repair })
+            .unwrap()
+            .into_parts();
+    parse_midi_events(tracks, time_div)
+}
+
+#[test]
+fn repairs_short_track_lengths() {
+    let tracks = vec![
+        TrackBuilder::new()
+            .note_on(0, 0, 60, 100)
+            .note_off(96, 0, 60)
+            .end(0),
+        TrackBuilder::new()
+            .note_on(48, 1, 64, 100)
+            .note_off(48, 1, 64)
+            .end(0),
+    ];
+    let expected = parse(96, &tracks);
+
+    // The first track claims to end after its Note On
+    let mut bytes = smf(1, 96, &tracks);
+    bytes[18..22].copy_from_slice(&4u32.to_be_bytes());
+
+    // The rest of it is then read as a chunk running past the end of the file
+    assert!(
+        load_midi_buffer_selected(Arc::new(bytes.clone()), None, &LoadOptions::default()).is_err()
+    );
+
+    let repaired = load_repaired(bytes, true);
+    assert_eq!(repaired.events, expected.events);
+    assert_eq!(repaired.deltas, expected.deltas);
+
+    // Tracks without an End of Track that end at the next track are left as
+    // they are
+    let tracks = vec![
+        TrackBuilder::new().note_on(0, 0, 60, 100).build(),
+        TrackBuilder::new().note_off(96, 0, 60).end(0),
+    ];
+    let bytes = smf(1, 96, &tracks);
+    assert_eq!(
+        load_repaired(bytes.clone(), true),
+        load_repaired(bytes, false)
+    );
+}
+
+#[test]
+fn rejects_time_divisions_without_ticks() {
+    let tracks = two_track_song();
+    // 0 ticks per quarter note, and 25 fps SMPTE with 0 ticks per frame
+    for raw in [0x0000, 0xE700] {
+        let bytes = smf(1, raw, &tracks);
+        let err = load_midi_reader(Cursor::new(bytes.clone())).unwrap_err();
+        assert!(
+            matches!(err, MidiLoadError::BadTimeDivision(div) if div == raw),
+            "{:?}",
+            err
+        );
+
+        let path = write_temp(&bytes);
+        assert!(matches!(
+            player::load_and_parse(&path),
+            Err(MidiLoadError::BadTimeDivision(_))
+        ));
+    }
+
+    // Converting with such a division doesn't panic either
+    assert_eq!(TimeDivision::Metrical(0).ticks_to_us(96, 500_000), 0);
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::{TrackBuilder, note, parse, two_track_song};
+use midiplayer_rs::midi::analysis::render_timeline;
+use midiplayer_rs::midi::metronome::{beat_ticks, with_metronome};
+use midiplayer_rs::midi::player::{PlaybackOptions, play_parsed_events_with_options};
+
+#[test]
+fn follows_time_signature_changes() {
+    let tracks = vec![
+        TrackBuilder::new()
+            // 3/4, then 6/8 from tick 288
+            .meta(0, 0x58, &[3, 2, 24, 8])
+            .meta(288, 0x58, &[6, 3, 24, 8])
+            .end(0),
+        TrackBuilder::new()
+            .note_on(0, 0, 60, 100)
+            .note_off(576, 0, 60)
+            .end(0),
+    ];
+    let parsed = parse(96, &tracks);
+
+    let beats = beat_ticks(&parsed, 96);
+    let mut expected = vec![(0, true), (96, false), (192, false)];
+    expected.extend((0..6).map(|n| (288 + n * 48, n == 0)));
+    assert_eq!(beats, expected);
+
+    // Without a time signature the file is counted in 4/4
+    let parsed = parse(96, &two_track_song());
+    assert_eq!(
+        beat_ticks(&parsed, 96),
+        vec![(0, true), (96, false), (192, false)]
+    );
+
+    // SMPTE files have no beats
+    assert!(beat_ticks(&parsed, 0xE728).is_empty());
+}
+
+#[test]
+fn clicks_stay_in_sync_through_tempo_changes() {
+    let parsed = parse(96, &two_track_song());
+    let with_clicks = with_metronome(&parsed, 96);
+    assert_eq!(with_clicks.note_count, parsed.note_count);
+
+    let clicks: Vec<(Duration, u32)> = render_timeline(&with_clicks, 96)
+        .into_iter()
+        .filter(|(_, event)| !event.is_tempo() && event.data() & 0x0F == 9)
+        .map(|(time, event)| (time, event.data()))
+        .collect();
+    assert_eq!(
+        clicks,
+        vec![
+            (Duration::ZERO, note(0x99, 76, 100)),
+            // 96 ticks at 600ms/qn
+            (Duration::from_millis(600), note(0x99, 76, 0)),
+            (Duration::from_millis(600), note(0x99, 77, 100)),
+            // then 300ms/qn from tick 192
+            (Duration::from_millis(1200), note(0x99, 77, 0)),
+            (Duration::from_millis(1200), note(0x99, 77, 100)),
+            (Duration::from_millis(1500), note(0x99, 77, 0)),
+        ]
+    );
+}
+
+#[test]
+fn counts_beats_from_the_start_of_the_file() {
+    let tracks = vec![
+        TrackBuilder::new()
+            .note_on(48, 0, 60, 100)
+            .note_off(48, 0, 60)
+            .end(0),
+    ];
+    let parsed = parse(96, &tracks);
+    assert_eq!(parsed.first_tick, 48);
+    let with_clicks = with_metronome(&parsed, 96);
+
+    // The file's notes keep their place between the clicks
+    let timeline = render_timeline(&with_clicks, 96);
+    let note_on = timeline
+        .iter()
+        .find(|(_, event)| event.data() == note(0x90, 60, 100))
+        .unwrap();
+    assert_eq!(note_on.0, Duration::from_millis(250));
+    assert_eq!(with_clicks.first_tick, 0);
+}
+
+#[test]
+fn plays_clicks_when_enabled() {
+    let parsed = parse(96, &two_track_song());
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent_clone = Arc::clone(&sent);
+    let options = PlaybackOptions {
+        metronome: true,
+        let_notes_ring: true,
+        ..Default::default()
+    };
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |data, track| sent_clone.lock().unwrap().push((data, track)),
+        Some(Box::new(|_| {})),
+        options,
+    );
+
+    // Clicks come after the file's events at the same tick, on a track of
+    // their own
+    assert_eq!(
+        *sent.lock().unwrap(),
+        vec![
+            (note(0x90, 60, 100), 1),
+            (note(0x99, 76, 100), 2),
+            (note(0x80, 60, 0), 1),
+            (note(0x99, 76, 0), 2),
+            (note(0x99, 77, 100), 2),
+            (note(0x90, 62, 100), 1),
+            (note(0x99, 77, 0), 2),
+            (note(0x99, 77, 100), 2),
+            (note(0x80, 62, 0), 1),
+            (note(0x99, 77, 0), 2),
+        ]
+    );
+}
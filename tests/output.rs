@@ -0,0 +1,65 @@
+use std::sync::{Arc, Mutex};
+
+use midiplayer_rs::midi::player::PLAYER_TRACK;
+use midiplayer_rs::output::{MidiOutput, PortRouter, matches_device};
+
+#[test]
+fn picks_devices_by_index_or_name() {
+    assert!(matches_device("1", 1, "Microsoft GS Wavetable Synth"));
+    assert!(!matches_device("0", 1, "Microsoft GS Wavetable Synth"));
+    assert!(matches_device(
+        "Wavetable",
+        0,
+        "Microsoft GS Wavetable Synth"
+    ));
+    assert!(!matches_device(
+        "loopMIDI",
+        0,
+        "Microsoft GS Wavetable Synth"
+    ));
+}
+
+#[derive(Default)]
+struct Recorder {
+    short: Mutex<Vec<u32>>,
+    long: Mutex<Vec<Vec<u8>>>,
+}
+
+impl MidiOutput for Recorder {
+    fn send_direct_data(&self, data: u32) {
+        self.short.lock().unwrap().push(data);
+    }
+
+    fn send_long_data(&self, data: &[u8]) {
+        self.long.lock().unwrap().push(data.to_vec());
+    }
+}
+
+#[test]
+fn routes_tracks_by_port() {
+    let first = Arc::new(Recorder::default());
+    let second = Arc::new(Recorder::default());
+    let outputs: Vec<Arc<dyn MidiOutput + Send + Sync>> = vec![first.clone(), second.clone()];
+    // Track 0 has no port, track 1 is on port 1 and track 2 on port 2,
+    // which has no output of its own
+    let router = PortRouter::new(outputs, &[None, Some(1), Some(2)], &[(1, 1)]);
+
+    router.send_direct_data(0x403C90, 0);
+    router.send_direct_data(0x403E91, 1);
+    router.send_direct_data(0x404092, 2);
+    // Tracks added by the player
+    router.send_direct_data(0xF8, 3);
+    router.send_long_data(&[0xF0, 0x7E, 0xF7], 1);
+    // Reset All Controllers from the file stays on its port, while All
+    // Notes Off from the player reaches every output
+    router.send_direct_data(0x79B1, 1);
+    router.send_direct_data(0x7BB0, PLAYER_TRACK);
+
+    assert_eq!(
+        *first.short.lock().unwrap(),
+        [0x403C90, 0x404092, 0xF8, 0x7BB0]
+    );
+    assert_eq!(*second.short.lock().unwrap(), [0x403E91, 0x79B1, 0x7BB0]);
+    assert!(first.long.lock().unwrap().is_empty());
+    assert_eq!(*second.long.lock().unwrap(), [vec![0xF0, 0x7E, 0xF7]]);
+}
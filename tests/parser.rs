@@ -0,0 +1,962 @@
+mod common;
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use common::{TrackBuilder, note, parse, smf, two_track_song, write_temp};
+use midiplayer_rs::midi::loader::{LoadWarning, load_midi_file};
+use midiplayer_rs::midi::player::{
+    Event, KeySignature, MetaTextKind, ParseProgress, SmpteOffset, TimeSignature, load_and_parse,
+    load_and_parse_with_progress, parse_midi_events, parse_midi_events_single_threaded,
+    parse_midi_events_single_threaded_with_progress, parse_midi_patterns, seek_to_tick,
+};
+
+#[test]
+fn merges_events_in_tick_order() {
+    let parsed = parse(96, &two_track_song());
+
+    let summary: Vec<(u32, u16, bool)> = parsed
+        .events
+        .iter()
+        .map(|e| (e.data(), e.track(), e.is_tempo()))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (600_000, 0, true),
+            (note(0x90, 60, 100), 1, false),
+            (note(0x80, 60, 0), 1, false),
+            (300_000, 0, true),
+            (note(0x90, 62, 100), 1, false),
+            (note(0x80, 62, 0), 1, false),
+        ]
+    );
+    assert_eq!(parsed.note_count, 2);
+    assert_eq!(parsed.total_ticks, 288);
+}
+
+#[test]
+fn packs_events_into_six_bytes() {
+    assert_eq!(std::mem::size_of::<Event>(), 6);
+
+    let tempo = Event::tempo(500_000);
+    assert!(tempo.is_tempo());
+    assert_eq!((tempo.data(), tempo.track()), (500_000, 0));
+
+    // Data with the top bit set doesn't read back as a tempo
+    let event = Event::new(0x8000_00F0, 7);
+    assert!(!event.is_tempo());
+    assert_eq!((event.data(), event.track()), (0xF0, 7));
+}
+
+#[test]
+fn builds_delta_table() {
+    let parsed = parse(96, &two_track_song());
+    assert_eq!(parsed.deltas, vec![(1, 96), (2, 96), (4, 96)]);
+}
+
+#[test]
+fn applies_tempo_changes_to_duration() {
+    let parsed = parse(96, &two_track_song());
+    // 2 quarter notes at 600ms + 1 quarter note at 300ms.
+    assert_eq!(parsed.total_duration, Duration::from_millis(1_500));
+    assert!(!parsed.duration_saturated);
+}
+
+#[test]
+fn uses_default_tempo_without_tempo_events() {
+    let track = TrackBuilder::new()
+        .note_on(0, 0, 60, 100)
+        .note_off(480, 0, 60)
+        .end(0);
+    let parsed = parse(480, &[track]);
+    assert_eq!(parsed.total_duration, Duration::from_millis(500));
+}
+
+#[test]
+fn decodes_multi_byte_deltas() {
+    let track = TrackBuilder::new()
+        .note_on(0, 0, 60, 100)
+        .note_off(0x3FFF, 0, 60)
+        .note_on(0x20_0000, 0, 60, 100)
+        .end(0);
+    let parsed = parse(96, &[track]);
+    assert_eq!(parsed.deltas, vec![(0, 0x3FFF), (1, 0x20_0000)]);
+    assert_eq!(parsed.total_ticks, 0x3FFF + 0x20_0000);
+}
+
+#[test]
+fn handles_running_status() {
+    let track = TrackBuilder::new()
+        .event(0, &[0x91, 60, 100])
+        .event(10, &[64, 100])
+        .event(10, &[60, 0])
+        .end(0);
+    let parsed = parse(96, &[track]);
+
+    let data: Vec<u32> = parsed.events.iter().map(|e| e.data()).collect();
+    assert_eq!(
+        data,
+        vec![note(0x91, 60, 100), note(0x91, 64, 100), note(0x91, 60, 0)]
+    );
+    // Velocity 0 note-ons are not counted as notes.
+    assert_eq!(parsed.note_count, 2);
+}
+
+#[test]
+fn meta_and_sysex_events_cancel_running_status() {
+    let track = TrackBuilder::new()
+        .event(0, &[0x91, 60, 100])
+        .meta(10, 0x06, b"A")
+        // A new status byte starts running status again
+        .event(0, &[0x92, 64, 100])
+        .event(10, &[64, 0])
+        .event(0, &[0xF0, 2, 0x01, 0xF7])
+        // Data bytes without a status can't reuse the meta or SysEx status
+        .event(10, &[60, 0])
+        .end(0);
+    let parsed = parse(96, &[track]);
+
+    let data: Vec<u32> = parsed
+        .events
+        .iter()
+        .filter(|e| !e.is_long())
+        .map(|e| e.data())
+        .collect();
+    assert_eq!(
+        data,
+        vec![note(0x91, 60, 100), note(0x92, 64, 100), note(0x92, 64, 0)]
+    );
+    assert_eq!(parsed.events.len(), 4);
+}
+
+#[test]
+fn skips_meta_events_other_than_tempo() {
+    let track = TrackBuilder::new()
+        .meta(0, 0x03, b"Piano")
+        .note_on(0, 0, 60, 100)
+        .meta(48, 0x06, b"Verse")
+        .note_off(48, 0, 60)
+        .end(0);
+    let parsed = parse(96, &[track]);
+    assert_eq!(parsed.events.len(), 2);
+    assert_eq!(parsed.deltas, vec![(0, 96)]);
+}
+
+#[test]
+fn single_threaded_parse_matches_parallel() {
+    let tracks: Vec<Vec<u8>> = (0..8u8)
+        .map(|i| {
+            TrackBuilder::new()
+                .tempo(u32::from(i) * 7, 400_000 + u32::from(i) * 1000)
+                .note_on(3, i, 60 + i, 100)
+                .note_off(24, i, 60 + i)
+                .note_on(0, i, 72, 90)
+                .note_off(u32::from(i) * 5, i, 72)
+                .end(0)
+        })
+        .collect();
+    let path = write_temp(&smf(1, 96, &tracks));
+
+    let (loaded, time_div, _) = load_midi_file(&path).unwrap();
+    let parallel = parse_midi_events(loaded, time_div);
+    let (loaded, time_div, _) = load_midi_file(&path).unwrap();
+    let sequential = parse_midi_events_single_threaded(loaded, time_div);
+
+    assert_eq!(parallel, sequential);
+}
+
+#[test]
+fn keeps_durations_longer_than_u64_nanoseconds() {
+    let mut track = TrackBuilder::new().tempo(0, 0xFF_FFFF);
+    for _ in 0..5 {
+        track = track.note_on(0x0FFF_FFFF, 0, 60, 100);
+    }
+    let parsed = parse(1, &[track.end(0)]);
+
+    // About 700 million years, far more than u64::MAX nanoseconds
+    let total_us = 5 * 0x0FFF_FFFFu128 * 0xFF_FFFF;
+    assert_eq!(parsed.total_us, total_us);
+    assert!(!parsed.duration_saturated);
+    assert_eq!(
+        parsed.total_duration,
+        Duration::from_micros(total_us as u64)
+    );
+    assert!(parsed.total_duration > Duration::from_nanos(u64::MAX));
+}
+
+#[test]
+fn collects_text_meta_events() {
+    let tracks = vec![
+        TrackBuilder::new()
+            .meta(0, 0x02, b"(c) Someone")
+            .meta(0, 0x03, b"Conductor")
+            .meta(96, 0x06, b"Chorus")
+            .end(0),
+        TrackBuilder::new()
+            .meta(0, 0x03, "Mélodie".as_bytes())
+            .meta(0, 0x04, b"Piano")
+            .note_on(0, 0, 60, 100)
+            .meta(48, 0x05, b"la")
+            .meta(0, 0x07, b"\xE9clair")
+            .meta(0, 0x01, b"plain text is ignored")
+            .note_off(48, 0, 60)
+            .end(0),
+    ];
+    let parsed = parse(96, &tracks);
+
+    let texts: Vec<(u64, MetaTextKind, u16, &str)> = parsed
+        .meta_texts
+        .iter()
+        .map(|(tick, m)| (*tick, m.kind, m.track, m.text.as_str()))
+        .collect();
+    assert_eq!(
+        texts,
+        vec![
+            (0, MetaTextKind::Copyright, 0, "(c) Someone"),
+            (0, MetaTextKind::TrackName, 0, "Conductor"),
+            (0, MetaTextKind::TrackName, 1, "Mélodie"),
+            (0, MetaTextKind::InstrumentName, 1, "Piano"),
+            (48, MetaTextKind::Lyric, 1, "la"),
+            (48, MetaTextKind::CuePoint, 1, "éclair"),
+            (96, MetaTextKind::Marker, 0, "Chorus"),
+        ]
+    );
+}
+
+#[test]
+fn collects_time_and_key_signatures() {
+    let tracks = vec![
+        TrackBuilder::new()
+            .meta(0, 0x58, &[4, 2, 24, 8])
+            .meta(0, 0x59, &[0, 0])
+            .meta(384, 0x58, &[6, 3, 24, 8])
+            .meta(0, 0x59, &[3, 0])
+            .meta(0, 0x58, &[6])
+            .end(0),
+        TrackBuilder::new()
+            .meta(192, 0x59, &[(-2i8) as u8, 1])
+            .end(0),
+    ];
+    let parsed = parse(96, &tracks);
+
+    assert_eq!(
+        parsed.time_signatures,
+        vec![
+            (
+                0,
+                TimeSignature {
+                    numerator: 4,
+                    denominator: 4,
+                    clocks_per_click: 24,
+                    thirty_seconds_per_quarter: 8,
+                }
+            ),
+            (
+                384,
+                TimeSignature {
+                    numerator: 6,
+                    denominator: 8,
+                    clocks_per_click: 24,
+                    thirty_seconds_per_quarter: 8,
+                }
+            ),
+        ]
+    );
+    assert_eq!(
+        parsed.key_signatures,
+        vec![
+            (
+                0,
+                KeySignature {
+                    sharps: 0,
+                    minor: false
+                }
+            ),
+            (
+                192,
+                KeySignature {
+                    sharps: -2,
+                    minor: true
+                }
+            ),
+            (
+                384,
+                KeySignature {
+                    sharps: 3,
+                    minor: false
+                }
+            ),
+        ]
+    );
+}
+
+#[test]
+fn reads_channel_prefixes_and_ports() {
+    let tracks = vec![
+        TrackBuilder::new()
+            .meta(0, 0x21, &[1])
+            .meta(0, 0x20, &[3])
+            .meta(0, 0x04, b"Piano")
+            // A channel message ends the prefix
+            .note_on(0, 3, 60, 100)
+            .meta(0, 0x04, b"Strings")
+            // Only the first port counts
+            .meta(10, 0x21, &[2])
+            .end(0),
+        TrackBuilder::new().note_on(0, 0, 60, 100).end(0),
+    ];
+    let parsed = parse(96, &tracks);
+
+    let channels: Vec<(&str, Option<u8>)> = parsed
+        .meta_texts
+        .iter()
+        .map(|(_, meta)| (meta.text.as_str(), meta.channel))
+        .collect();
+    assert_eq!(channels, vec![("Piano", Some(3)), ("Strings", None)]);
+    assert_eq!(parsed.track_ports, vec![Some(1), None]);
+}
+
+#[test]
+fn reads_the_smpte_offset() {
+    let tracks = vec![
+        // 01:00:00:12.50 at 25 fps, but later in the track
+        TrackBuilder::new()
+            .meta(10, 0x54, &[0x21, 0, 0, 12, 50])
+            .end(0),
+        // 00:00:02:15.00 at 29.97 drop-frame
+        TrackBuilder::new()
+            .meta(0, 0x54, &[0x40, 0, 2, 15, 0])
+            .note_on(0, 0, 60, 100)
+            .end(96),
+        TrackBuilder::new()
+            .meta(0, 0x54, &[0x60, 0, 3, 0, 0])
+            .end(0),
+    ];
+    let parsed = parse(96, &tracks);
+
+    // Only offsets at tick 0 count, the lowest track's wins
+    let offset = parsed.smpte_offset.unwrap();
+    assert_eq!(
+        offset,
+        SmpteOffset {
+            frames_per_second: 29,
+            hours: 0,
+            minutes: 0,
+            seconds: 2,
+            frames: 15,
+            subframes: 0,
+        }
+    );
+    assert_eq!(offset.duration(), Duration::from_nanos(2_500_500_000));
+    assert_eq!(offset.to_string(), "00:00:02:15.00 (29.97 drop-frame fps)");
+
+    let offset = SmpteOffset::from_bytes(&[0x21, 0, 0, 12, 50]).unwrap();
+    assert_eq!((offset.frames_per_second, offset.hours), (25, 1));
+    assert_eq!(offset.duration(), Duration::from_millis(3_600_500));
+    assert_eq!(SmpteOffset::from_bytes(&[0x21, 0, 0, 12]), None);
+
+    assert_eq!(parse(96, &two_track_song()).smpte_offset, None);
+}
+
+#[test]
+fn reassembles_sysex_continuation_packets() {
+    let track = TrackBuilder::new()
+        .event(0, &[0xF0, 5, 0x7E, 0x7F, 0x09, 0x01, 0xF7])
+        .event(10, &[0xF0, 3, 0x43, 0x10, 0x4C])
+        .event(5, &[0xF7, 2, 0x00, 0x00])
+        .event(5, &[0xF7, 3, 0x7E, 0x00, 0xF7])
+        // An escape outside of a SysEx message
+        .event(0, &[0xF7, 1, 0xF8])
+        .note_on(0, 0, 60, 100)
+        .end(0);
+    let parsed = parse(96, &[track]);
+
+    let messages: Vec<&[u8]> = parsed
+        .events
+        .iter()
+        .filter(|e| e.is_long())
+        .map(|e| parsed.long_message(e.data()).unwrap())
+        .collect();
+    assert_eq!(
+        messages,
+        vec![
+            &[0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7][..],
+            &[0xF0, 0x43, 0x10, 0x4C, 0x00, 0x00, 0x7E, 0x00, 0xF7][..],
+        ]
+    );
+    // Split messages are played at the tick of their first packet.
+    assert_eq!(parsed.deltas, vec![(0, 10), (1, 10)]);
+
+    assert_eq!(parsed.events.get(2).unwrap().data(), 0xF8);
+    assert_eq!(parsed.events.get(3).unwrap().data(), note(0x90, 60, 100));
+}
+
+#[test]
+fn keeps_escaped_bytes() {
+    let track = TrackBuilder::new()
+        // MIDI clock, then an MTC quarter frame and Start in one escape
+        .event(0, &[0xF7, 1, 0xF8])
+        .event(10, &[0xF7, 3, 0xF1, 0x23, 0xFA])
+        // A note, counted like any other
+        .event(10, &[0xF7, 3, 0x91, 64, 100])
+        // Bytes that aren't short messages are kept as they are
+        .event(10, &[0xF7, 2, 0x01, 0x02])
+        .end(0);
+    let parsed = parse(96, &[track]);
+
+    let events: Vec<u32> = parsed.events.iter().map(|e| e.data()).collect();
+    assert_eq!(events[..4], [0xF8, 0x23F1, 0xFA, note(0x91, 64, 100)]);
+    assert!(parsed.events.get(4).unwrap().is_long());
+    assert_eq!(parsed.long_message(events[4]), Some(&[0x01, 0x02][..]));
+    assert_eq!(parsed.deltas, vec![(0, 10), (2, 10), (3, 10)]);
+    assert_eq!(parsed.note_count, 1);
+    assert_eq!(parsed.channel_note_counts[1], 1);
+}
+
+#[test]
+fn indexes_sysex_across_tracks() {
+    let tracks = vec![
+        TrackBuilder::new().event(20, &[0xF0, 2, 0x01, 0xF7]).end(0),
+        TrackBuilder::new()
+            .event(0, &[0xF0, 2, 0x02, 0xF7])
+            .event(10, &[0xF0, 2, 0x03, 0xF7])
+            .end(0),
+    ];
+    let parsed = parse(96, &tracks);
+
+    let messages: Vec<(u16, &[u8])> = parsed
+        .events
+        .iter()
+        .map(|e| (e.track(), parsed.long_message(e.data()).unwrap()))
+        .collect();
+    assert_eq!(
+        messages,
+        vec![
+            (1, &[0xF0, 0x02, 0xF7][..]),
+            (1, &[0xF0, 0x03, 0xF7][..]),
+            (0, &[0xF0, 0x01, 0xF7][..]),
+        ]
+    );
+}
+
+#[test]
+fn parses_track_without_end_of_track() {
+    let parsed = parse(
+        96,
+        &[TrackBuilder::new()
+            .note_on(0, 0, 60, 100)
+            .note_off(96, 0, 60)
+            .build()],
+    );
+
+    assert_eq!(parsed.events.len(), 2);
+    assert_eq!(parsed.total_ticks, 96);
+}
+
+#[test]
+fn stops_at_message_cut_short() {
+    let mut body = TrackBuilder::new()
+        .note_on(0, 0, 60, 100)
+        .note_on(96, 0, 62, 100)
+        .build();
+    body.pop();
+    let parsed = parse(96, &[body]);
+
+    assert_eq!(parsed.events.len(), 1);
+    assert_eq!(parsed.events.get(0).unwrap().data(), note(0x90, 60, 100));
+}
+
+#[test]
+fn load_and_parse_matches_separate_steps() {
+    let mut tracks = two_track_song();
+    for key in 0..40 {
+        tracks.push(
+            TrackBuilder::new()
+                .note_on(key, 1, 30 + key as u8, 90)
+                .note_off(20, 1, 30 + key as u8)
+                .end(0),
+        );
+    }
+    let path = write_temp(&smf(1, 96, &tracks));
+
+    let (loaded, time_div, _) = load_midi_file(&path).unwrap();
+    let expected = parse_midi_events(loaded, time_div);
+
+    assert_eq!(load_and_parse(&path).unwrap(), (expected, 96));
+}
+
+#[test]
+fn merges_interleaved_tracks_in_file_order_at_equal_ticks() {
+    let tracks = vec![
+        TrackBuilder::new()
+            .note_on(0, 0, 60, 100)
+            .note_on(10, 0, 61, 100)
+            .note_on(20, 0, 62, 100)
+            .end(0),
+        TrackBuilder::new()
+            .note_on(5, 1, 70, 100)
+            .note_on(5, 1, 71, 100)
+            .tempo(20, 250_000)
+            .end(0),
+        TrackBuilder::new().note_on(10, 2, 80, 100).end(0),
+    ];
+    let parsed = parse(96, &tracks);
+
+    let order: Vec<(u16, bool)> = parsed
+        .events
+        .iter()
+        .map(|e| (e.track(), e.is_tempo()))
+        .collect();
+    assert_eq!(
+        order,
+        vec![
+            (0, false),
+            (1, false),
+            (0, false),
+            (1, false),
+            (2, false),
+            (0, true),
+            (0, false)
+        ]
+    );
+    assert_eq!(parsed.deltas, vec![(0, 5), (1, 5), (4, 20)]);
+}
+
+#[test]
+fn orders_simultaneous_tempo_changes_by_track() {
+    let tracks = vec![
+        TrackBuilder::new()
+            .note_on(0, 0, 60, 100)
+            .tempo(0, 400_000)
+            .tempo(96, 250_000)
+            .end(0),
+        TrackBuilder::new()
+            .tempo(0, 300_000)
+            .tempo(0, 350_000)
+            .tempo(96, 500_000)
+            .end(0),
+        TrackBuilder::new()
+            .tempo(0, 200_000)
+            .note_off(192, 0, 60)
+            .end(0),
+    ];
+    let parsed = parse(96, &tracks);
+
+    let tempos: Vec<u32> = parsed
+        .events
+        .iter()
+        .filter(|e| e.is_tempo())
+        .map(|e| e.data())
+        .collect();
+    assert_eq!(
+        tempos,
+        vec![400_000, 300_000, 350_000, 200_000, 250_000, 500_000]
+    );
+    assert!(parsed.events.iter().take(4).all(|e| e.is_tempo()));
+
+    // The last tempo at each tick wins: 96 ticks at 200ms, 96 at 500ms.
+    assert_eq!(parsed.total_duration, Duration::from_millis(700));
+}
+
+#[test]
+fn splits_gaps_longer_than_u32() {
+    // Skipped markers carry the time, so the notes end up over 2^32 ticks apart.
+    let mut track = TrackBuilder::new().note_on(0, 0, 60, 100);
+    for _ in 0..17 {
+        track = track.meta(0x0FFF_FFFF, 0x06, b"gap");
+    }
+    let parsed = parse(96, &[track.note_off(0x0FFF_FFFF, 0, 60).end(0)]);
+
+    let gap = 18 * 0x0FFF_FFFFu64;
+    assert!(gap > 1 << 32);
+    assert_eq!(parsed.total_ticks, gap);
+    assert_eq!(
+        parsed.deltas,
+        vec![(0, u32::MAX), (0, (gap - u64::from(u32::MAX)) as u32)]
+    );
+    assert_eq!(parsed.gaps().collect::<Vec<_>>(), vec![(0, gap)]);
+}
+
+#[test]
+fn iterates_events_with_their_ticks() {
+    let tracks = vec![
+        TrackBuilder::new()
+            .note_on(48, 0, 60, 100)
+            .note_off(0x0FFF_FFFF, 0, 60)
+            .end(0),
+        TrackBuilder::new()
+            .note_on(48, 1, 64, 100)
+            .note_off(0x0FFF_0000, 1, 64)
+            .end(0),
+    ];
+    let mut parsed = parse(96, &tracks);
+    // Split the last gap the way the parser does for gaps over u32::MAX ticks
+    let (idx, ticks) = parsed.deltas.pop().unwrap();
+    parsed.deltas.extend([(idx, ticks - 5), (idx, 5)]);
+
+    let ticks: Vec<(u64, u32)> = parsed
+        .iter_with_ticks()
+        .map(|(tick, event)| (tick, event.data()))
+        .collect();
+    assert_eq!(
+        ticks,
+        [
+            (48, note(0x90, 60, 100)),
+            (48, note(0x91, 64, 100)),
+            (48 + 0x0FFF_0000, note(0x81, 64, 0)),
+            (48 + 0x0FFF_FFFF, note(0x80, 60, 0)),
+        ]
+    );
+}
+
+#[test]
+fn plays_note_offs_before_retriggered_note_ons() {
+    // Track 0 restarts the key that track 1 releases on the same tick.
+    let tracks = vec![
+        TrackBuilder::new()
+            .note_on(96, 0, 60, 90)
+            .note_on(0, 0, 64, 90)
+            .note_off(96, 0, 60)
+            .note_off(0, 0, 64)
+            .end(0),
+        TrackBuilder::new()
+            .note_on(0, 0, 60, 100)
+            .note_off(96, 0, 60)
+            .end(0),
+        TrackBuilder::new()
+            .event(96, &[0xB0, 7, 80])
+            .note_on(0, 0, 64, 0)
+            .end(0),
+    ];
+    let parsed = parse(96, &tracks);
+
+    let data: Vec<(u32, u16)> = parsed
+        .events
+        .iter()
+        .map(|e| (e.data(), e.track()))
+        .collect();
+    assert_eq!(
+        data,
+        vec![
+            (note(0x90, 60, 100), 1),
+            (note(0x80, 60, 0), 1),
+            (note(0xB0, 7, 80), 2),
+            (note(0x90, 64, 0), 2),
+            (note(0x90, 60, 90), 0),
+            (note(0x90, 64, 90), 0),
+            (note(0x80, 60, 0), 0),
+            (note(0x80, 64, 0), 0),
+        ]
+    );
+    assert_eq!(parsed.deltas, vec![(0, 96), (5, 96)]);
+}
+
+#[test]
+fn plays_format_2_patterns_one_after_another() {
+    let patterns = vec![
+        TrackBuilder::new()
+            .tempo(0, 400_000)
+            .note_on(0, 0, 60, 100)
+            .note_off(96, 0, 60)
+            .end(48),
+        TrackBuilder::new()
+            .meta(0, 0x06, b"B")
+            .note_on(0, 0, 62, 100)
+            .note_off(96, 0, 62)
+            .end(0),
+    ];
+    let path = write_temp(&smf(2, 96, &patterns));
+
+    let (loaded, time_div, _) = load_midi_file(&path).unwrap();
+    let parsed = parse_midi_patterns(loaded, time_div);
+
+    let data: Vec<u32> = parsed.events.iter().map(|e| e.data()).collect();
+    assert_eq!(
+        data,
+        vec![
+            400_000,
+            note(0x90, 60, 100),
+            note(0x80, 60, 0),
+            note(0x90, 62, 100),
+            note(0x80, 62, 0),
+        ]
+    );
+    // The second pattern starts where the first one ends, End of Track included.
+    assert_eq!(parsed.deltas, vec![(1, 96), (2, 48), (3, 96)]);
+    assert_eq!(parsed.total_ticks, 240);
+    assert_eq!(parsed.meta_texts[0].0, 144);
+
+    // Loading and parsing in one go does the same for Format 2 files.
+    assert_eq!(load_and_parse(&path).unwrap(), (parsed, 96));
+}
+
+#[test]
+fn counts_notes_per_channel_and_track() {
+    let tracks = vec![
+        TrackBuilder::new().tempo(0, 400_000).end(0),
+        TrackBuilder::new()
+            .note_on(0, 9, 36, 100)
+            .note_on(0, 9, 38, 100)
+            .note_on(0, 0, 60, 100)
+            // Velocity 0 is a release, not a note
+            .note_on(10, 9, 36, 0)
+            .end(0),
+        TrackBuilder::new()
+            .note_on(0, 9, 42, 100)
+            .note_on(0, 3, 64, 100)
+            .end(0),
+    ];
+    let parsed = parse(96, &tracks);
+
+    let mut expected = [0u64; 16];
+    expected[0] = 1;
+    expected[3] = 1;
+    expected[9] = 3;
+    assert_eq!(parsed.channel_note_counts, expected);
+    assert_eq!(parsed.track_note_counts, vec![0, 3, 2]);
+    assert_eq!(parsed.busiest_channel(), Some((9, 3)));
+
+    let empty = parse(96, &[TrackBuilder::new().end(0)]);
+    assert_eq!(empty.busiest_channel(), None);
+}
+
+#[test]
+fn finds_max_polyphony() {
+    let tracks = vec![
+        TrackBuilder::new()
+            .note_on(0, 0, 60, 100)
+            .note_on(10, 0, 64, 100)
+            .note_off(10, 0, 60)
+            .note_off(10, 0, 64)
+            .end(0),
+        TrackBuilder::new()
+            .note_on(15, 1, 60, 100)
+            // The same key again is another voice
+            .note_on(0, 1, 60, 100)
+            .note_on(20, 1, 60, 0)
+            .note_off(0, 1, 60)
+            // Unmatched Note Offs don't go below zero
+            .note_off(0, 1, 70)
+            .end(0),
+        // Retriggered on the tick track 0 releases it
+        TrackBuilder::new()
+            .note_on(20, 0, 60, 100)
+            .note_off(5, 0, 60)
+            .end(0),
+    ];
+    let parsed = parse(96, &tracks);
+
+    // 60 and 64 on channel 0, then two 60s on channel 1 at tick 15
+    assert_eq!(parsed.max_polyphony, 4);
+    assert_eq!(parsed.max_polyphony_tick, 15);
+
+    // A tempo whose low byte looks like a Note On status
+    let empty = parse(96, &[TrackBuilder::new().tempo(0, 0x07_A190).end(0)]);
+    assert_eq!(empty.max_polyphony, 0);
+}
+
+#[test]
+fn builds_the_tempo_map() {
+    let parsed = parse(96, &two_track_song());
+    assert_eq!(
+        parsed.tempo_map,
+        vec![
+            (0, 600_000, Duration::ZERO),
+            // 192 ticks at 600ms/qn
+            (192, 300_000, Duration::from_millis(1200)),
+        ]
+    );
+
+    // The default tempo holds until the first change, and only the last of
+    // several changes on one tick is kept
+    let tracks = vec![
+        TrackBuilder::new()
+            .tempo(96, 250_000)
+            .tempo(0, 1_000_000)
+            .end(0),
+        TrackBuilder::new()
+            .note_on(0, 0, 60, 100)
+            .note_off(192, 0, 60)
+            .end(0),
+    ];
+    let parsed = parse(96, &tracks);
+    assert_eq!(
+        parsed.tempo_map,
+        vec![
+            (0, 500_000, Duration::ZERO),
+            (96, 1_000_000, Duration::from_millis(500)),
+        ]
+    );
+    assert_eq!(parsed.total_duration, Duration::from_millis(1500));
+
+    let parsed = parse(96, &[TrackBuilder::new().end(0)]);
+    assert!(parsed.tempo_map.is_empty());
+}
+
+#[test]
+fn converts_between_ticks_and_time() {
+    let parsed = parse(96, &two_track_song());
+    for (tick, ms) in [(0, 0), (96, 600), (192, 1200), (240, 1350), (288, 1500)] {
+        assert_eq!(
+            parsed.tick_to_duration(96, tick),
+            Duration::from_millis(ms),
+            "tick {tick}"
+        );
+        assert_eq!(
+            parsed.duration_to_tick(96, Duration::from_millis(ms)),
+            tick,
+            "{ms}ms"
+        );
+    }
+    // Times between two ticks round down
+    assert_eq!(
+        parsed.duration_to_tick(96, Duration::from_millis(1352)),
+        240
+    );
+    // Past the end, the last tempo carries on
+    assert_eq!(
+        parsed.duration_to_tick(96, Duration::from_millis(1800)),
+        384
+    );
+
+    // Ticks count from the start of the file, but playback starts at the
+    // first event
+    let tracks = vec![
+        TrackBuilder::new()
+            .note_on(48, 0, 60, 100)
+            .note_off(96, 0, 60)
+            .end(0),
+    ];
+    let parsed = parse(96, &tracks);
+    assert_eq!(parsed.tick_to_duration(96, 10), Duration::ZERO);
+    assert_eq!(parsed.tick_to_duration(96, 144), Duration::from_millis(500));
+    assert_eq!(parsed.duration_to_tick(96, Duration::ZERO), 48);
+    assert_eq!(seek_to_tick(&parsed, 48), (0, 0, 48));
+    assert_eq!(seek_to_tick(&parsed, 49), (1, 1, 144));
+}
+
+#[test]
+fn places_the_first_event_after_its_leading_delta() {
+    // A four-byte leading delta, and one on a track that starts with a meta
+    // event, which must not be counted twice
+    let rest = 0x0FFF_FFF0u32;
+    let tracks = vec![
+        TrackBuilder::new()
+            .meta(rest, 0x06, b"intro")
+            .note_on(8, 1, 64, 100)
+            .note_off(96, 1, 64)
+            .end(0),
+        TrackBuilder::new()
+            .note_on(rest, 0, 60, 100)
+            .note_off(96, 0, 60)
+            .end(0),
+    ];
+    let expected = [
+        (u64::from(rest), note(0x90, 60, 100)),
+        (u64::from(rest) + 8, note(0x91, 64, 100)),
+        (u64::from(rest) + 96, note(0x80, 60, 0)),
+        (u64::from(rest) + 104, note(0x81, 64, 0)),
+    ];
+
+    let path = write_temp(&smf(1, 96, &tracks));
+    let (loaded, _, _) = load_midi_file(&path).unwrap();
+    assert_eq!(loaded[1].tick, u64::from(rest));
+    let (streamed, _) = load_and_parse(&path).unwrap();
+    for parsed in [
+        parse(96, &tracks),
+        parse_midi_events_single_threaded(load_midi_file(&path).unwrap().0, 96),
+        streamed,
+    ] {
+        assert_eq!(parsed.first_tick, u64::from(rest));
+        assert_eq!(parsed.total_ticks, u64::from(rest) + 104);
+        assert_eq!(parsed.meta_texts[0].0, u64::from(rest));
+        let ticks: Vec<(u64, u32)> = parsed
+            .iter_with_ticks()
+            .map(|(tick, event)| (tick, event.data()))
+            .collect();
+        assert_eq!(ticks, expected);
+    }
+}
+
+#[test]
+fn leaves_silence_before_the_first_event_out_of_the_length() {
+    let tracks = vec![
+        TrackBuilder::new().tempo(48, 250_000).end(0),
+        TrackBuilder::new()
+            .note_on(48, 0, 60, 100)
+            .note_off(96, 0, 60)
+            .end(0),
+    ];
+    let parsed = parse(96, &tracks);
+
+    // Playback starts at tick 48, so only the 96 ticks after it count
+    assert_eq!(parsed.first_tick, 48);
+    assert_eq!(parsed.total_us, 250_000);
+    assert_eq!(parsed.total_duration, Duration::from_millis(250));
+    assert_eq!(parsed.tempo_map, vec![(48, 250_000, Duration::ZERO)]);
+}
+
+#[test]
+fn reports_parse_progress() {
+    let path = write_temp(&smf(1, 96, &two_track_song()));
+    let (tracks, time_div, _) = load_midi_file(&path).unwrap();
+
+    let reported = Mutex::new(Vec::new());
+    let parsed = parse_midi_events_single_threaded_with_progress(tracks, time_div, &|progress| {
+        reported.lock().unwrap().push(progress)
+    });
+
+    assert_eq!(
+        reported.into_inner().unwrap(),
+        vec![
+            ParseProgress::TracksParsed {
+                done: 0,
+                total: 2,
+                events: 0
+            },
+            ParseProgress::TracksParsed {
+                done: 1,
+                total: 2,
+                events: 0
+            },
+            ParseProgress::TracksParsed {
+                done: 2,
+                total: 2,
+                events: 4
+            },
+            ParseProgress::Merging { tracks: 2 },
+            ParseProgress::BuildingDeltas,
+            ParseProgress::Done {
+                events: parsed.events.len(),
+                notes: 2
+            },
+        ]
+    );
+}
+
+#[test]
+fn reports_load_warnings_as_progress() {
+    let tracks = two_track_song();
+    let mut bytes = smf(1, 96, &tracks);
+    bytes.truncate(bytes.len() - tracks[1].len() - 8);
+    let path = write_temp(&bytes);
+
+    let reported = Mutex::new(Vec::new());
+    load_and_parse_with_progress(&path, &|progress| reported.lock().unwrap().push(progress))
+        .unwrap();
+
+    let warnings: Vec<ParseProgress> = reported
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .filter(|progress| matches!(progress, ParseProgress::Warning(_)))
+        .collect();
+    assert_eq!(
+        warnings,
+        vec![ParseProgress::Warning(LoadWarning::MissingTracks {
+            found: 1,
+            declared: 2
+        })]
+    );
+}
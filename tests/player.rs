@@ -0,0 +1,1458 @@
+mod common;
+
+use std::sync::atomic::{AtomicBool, AtomicI8, AtomicI64, AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use common::{TrackBuilder, note, parse, two_track_song};
+use midiplayer_rs::midi::player::{
+    CatchUpMode, DEFAULT_BATCH_SIZE, EventKind, PLAYER_TRACK, PlaybackOptions, PlaybackStats,
+    PlaybackStrategy, PlayerControl, TICK_INDEX_STRIDE, TransposeRange, event_kind,
+    play_parsed_events, play_parsed_events_looped, play_parsed_events_with_options, remap_channel,
+    remap_drum_note, scale_velocity, seek_to_tick, solo_channels, transpose_message,
+};
+use midiplayer_rs::midi::track_mask::TrackMask;
+use midiplayer_rs::midi::utils::{AtomicF32, Clock, delay_execution_100ns};
+
+/// All Sound Off and All Notes Off on every channel, as sent at the end.
+fn silence() -> Vec<u32> {
+    (0..16)
+        .flat_map(|ch| [note(0xB0 | ch, 120, 0), note(0xB0 | ch, 123, 0)])
+        .collect()
+}
+
+#[test]
+fn plays_events_in_order_with_delays() {
+    let parsed = parse(96, &two_track_song());
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let delays = Arc::new(Mutex::new(Vec::new()));
+    let sent_clone = Arc::clone(&sent);
+    let delays_clone = Arc::clone(&delays);
+
+    play_parsed_events(
+        &parsed,
+        96,
+        move |data, track| sent_clone.lock().unwrap().push((data, track)),
+        Some(Box::new(move |t| delays_clone.lock().unwrap().push(t))),
+    );
+
+    let mut expected = vec![
+        (note(0x90, 60, 100), 1),
+        (note(0x80, 60, 0), 1),
+        (note(0x90, 62, 100), 1),
+        (note(0x80, 62, 0), 1),
+    ];
+    // All channels are silenced at the end
+    expected.extend(silence().into_iter().map(|data| (data, PLAYER_TRACK)));
+    assert_eq!(*sent.lock().unwrap(), expected);
+    // One delay per entry in the delta table.
+    assert_eq!(delays.lock().unwrap().len(), 3);
+}
+
+#[test]
+fn plays_with_tiny_batches() {
+    let parsed = parse(96, &two_track_song());
+
+    for (batch_size, lookahead_batches) in [(1, 1), (3, 2), (0, 0)] {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let delays = Arc::new(Mutex::new(Vec::new()));
+        let sent_clone = Arc::clone(&sent);
+        let delays_clone = Arc::clone(&delays);
+        play_parsed_events_with_options(
+            &parsed,
+            96,
+            move |data, track| sent_clone.lock().unwrap().push((data, track)),
+            Some(Box::new(move |t| delays_clone.lock().unwrap().push(t))),
+            PlaybackOptions {
+                strategy: PlaybackStrategy::Batched {
+                    batch_size,
+                    lookahead: lookahead_batches,
+                },
+                let_notes_ring: true,
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            *sent.lock().unwrap(),
+            [
+                (note(0x90, 60, 100), 1),
+                (note(0x80, 60, 0), 1),
+                (note(0x90, 62, 100), 1),
+                (note(0x80, 62, 0), 1),
+            ]
+        );
+        assert_eq!(delays.lock().unwrap().len(), 3);
+    }
+}
+
+#[test]
+fn applies_options_the_same_with_either_strategy() {
+    let mut tracks = two_track_song();
+    tracks[1] = TrackBuilder::new()
+        .event(0, &[0xB0, 7, 100])
+        .note_on(0, 0, 60, 100)
+        .note_off(96, 0, 60)
+        .note_on(96, 0, 62, 100)
+        .event(0, &[0xF0, 0x02, 0x7E, 0xF7])
+        .note_off(96, 0, 62)
+        .end(0);
+    let parsed = parse(96, &tracks);
+
+    let play = |strategy| {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let long = Arc::new(Mutex::new(Vec::new()));
+        let sent_clone = Arc::clone(&sent);
+        let long_clone = Arc::clone(&long);
+        let options = PlaybackOptions {
+            strategy,
+            start_tick: 96,
+            transpose: Some(Arc::new(AtomicI8::new(2))),
+            send_long_data: Some(Box::new(move |message: &[u8], track| {
+                long_clone.lock().unwrap().push((message.to_vec(), track))
+            })),
+            ..Default::default()
+        };
+        play_parsed_events_with_options(
+            &parsed,
+            96,
+            move |data, track| sent_clone.lock().unwrap().push((data, track)),
+            Some(Box::new(|_| {})),
+            options,
+        );
+        let sent = sent.lock().unwrap().clone();
+        let long = long.lock().unwrap().clone();
+        (sent, long)
+    };
+
+    let (sent, long) = play(PlaybackStrategy::Direct);
+    assert_eq!(
+        sent[..4],
+        [
+            (note(0xB0, 7, 100), 1),
+            (note(0x80, 62, 0), 1),
+            (note(0x90, 64, 100), 1),
+            (note(0x80, 64, 0), 1),
+        ]
+    );
+    assert_eq!(long, [(vec![0xF0, 0x7E, 0xF7], 1)]);
+    for (batch_size, lookahead) in [(1, 1), (2, 3), (DEFAULT_BATCH_SIZE, 1)] {
+        let batched = play(PlaybackStrategy::Batched {
+            batch_size,
+            lookahead,
+        });
+        assert_eq!(batched, (sent.clone(), long.clone()));
+    }
+}
+
+#[test]
+fn reports_playback_progress() {
+    let parsed = parse(96, &two_track_song());
+
+    let ticks = Arc::new(Mutex::new(Vec::new()));
+    let ticks_clone = Arc::clone(&ticks);
+    let options = PlaybackOptions {
+        progress: Some(Box::new(move |tick, _elapsed| {
+            ticks_clone.lock().unwrap().push(tick)
+        })),
+        progress_interval: Duration::ZERO,
+        ..Default::default()
+    };
+
+    play_parsed_events_with_options(&parsed, 96, |_, _| {}, Some(Box::new(|_| {})), options);
+
+    assert_eq!(*ticks.lock().unwrap(), vec![96, 192, 288]);
+}
+
+#[test]
+fn counts_ticks_from_the_start_of_the_file() {
+    let track = TrackBuilder::new()
+        .note_on(48, 0, 60, 100)
+        .note_off(96, 0, 60)
+        .end(0);
+    let parsed = parse(96, &[track]);
+    assert_eq!(parsed.first_tick, 48);
+
+    let ticks = Arc::new(Mutex::new(Vec::new()));
+    let ticks_clone = Arc::clone(&ticks);
+    let options = PlaybackOptions {
+        progress: Some(Box::new(move |tick, _elapsed| {
+            ticks_clone.lock().unwrap().push(tick)
+        })),
+        progress_interval: Duration::ZERO,
+        ..Default::default()
+    };
+
+    play_parsed_events_with_options(&parsed, 96, |_, _| {}, Some(Box::new(|_| {})), options);
+
+    // Playback starts at the first event, but the ticks it reports are
+    // those of the file
+    assert_eq!(*ticks.lock().unwrap(), vec![144]);
+}
+
+#[test]
+fn stops_when_flag_is_set() {
+    let parsed = parse(96, &two_track_song());
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let stop_clone = Arc::clone(&stop);
+    let sent_clone = Arc::clone(&sent);
+    let options = PlaybackOptions {
+        stop: Some(Arc::clone(&stop)),
+        ..Default::default()
+    };
+
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |data, _| {
+            let mut sent = sent_clone.lock().unwrap();
+            sent.push(data);
+            if sent.len() == 2 {
+                stop_clone.store(true, Ordering::Relaxed);
+            }
+        },
+        Some(Box::new(|_| {})),
+        options,
+    );
+
+    let sent = sent.lock().unwrap();
+    assert_eq!(&sent[..2], &[note(0x90, 60, 100), note(0x80, 60, 0)]);
+    assert_eq!(sent.len(), 2 + 32);
+    for channel in 0..16u8 {
+        assert!(sent.contains(&note(0xB0 | channel, 120, 0)));
+        assert!(sent.contains(&note(0xB0 | channel, 123, 0)));
+    }
+}
+
+#[test]
+fn stops_during_a_long_rest() {
+    let track = TrackBuilder::new()
+        .note_on(0, 0, 60, 100)
+        .note_off(960, 0, 60)
+        .end(0);
+    let parsed = parse(96, &[track]);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let delays = Arc::new(Mutex::new(Vec::new()));
+    let stop_clone = Arc::clone(&stop);
+    let sent_clone = Arc::clone(&sent);
+    let delays_clone = Arc::clone(&delays);
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |data, _| sent_clone.lock().unwrap().push(data),
+        Some(Box::new(move |t| {
+            delays_clone.lock().unwrap().push(t);
+            stop_clone.store(true, Ordering::Relaxed);
+        })),
+        PlaybackOptions {
+            stop: Some(stop),
+            ..Default::default()
+        },
+    );
+
+    // The 5s rest is waited in 100ms steps, and the stop is noticed after
+    // the first one
+    let delays = delays.lock().unwrap();
+    assert_eq!(delays.len(), 1);
+    assert!(delays[0] <= 1_000_000);
+    let sent = sent.lock().unwrap();
+    assert_eq!(sent[0], note(0x90, 60, 100));
+    assert_eq!(sent[1..], silence());
+}
+
+#[test]
+fn resumes_after_pause_without_catching_up() {
+    let track = TrackBuilder::new()
+        .note_on(0, 0, 60, 100)
+        .note_off(4, 0, 60)
+        .note_on(4, 0, 62, 100)
+        .note_off(4, 0, 62)
+        .end(0);
+    let parsed = parse(96, &[track]);
+
+    let (control_tx, control_rx) = crossbeam_channel::unbounded();
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let delays = Arc::new(Mutex::new(Vec::new()));
+    let sent_clone = Arc::clone(&sent);
+    let delays_clone = Arc::clone(&delays);
+
+    let options = PlaybackOptions {
+        control: Some(control_rx),
+        let_notes_ring: true,
+        ..Default::default()
+    };
+
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |data, _| {
+            let mut sent = sent_clone.lock().unwrap();
+            if sent.is_empty() {
+                control_tx.send(PlayerControl::Pause).unwrap();
+                let resume_tx = control_tx.clone();
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(50));
+                    resume_tx.send(PlayerControl::Resume).unwrap();
+                });
+            }
+            sent.push(data);
+        },
+        Some(Box::new(move |t| {
+            delays_clone.lock().unwrap().push(t);
+            delay_execution_100ns(t);
+        })),
+        options,
+    );
+
+    let sent = sent.lock().unwrap();
+    assert_eq!(sent[0], note(0x90, 60, 100));
+    assert!(sent[1..33].contains(&note(0xB0, 123, 0)));
+    assert_eq!(
+        &sent[33..],
+        &[note(0x80, 60, 0), note(0x90, 62, 100), note(0x80, 62, 0)]
+    );
+
+    // Every gap is still waited out after the pause instead of being skipped.
+    assert_eq!(delays.lock().unwrap().len(), parsed.deltas.len());
+}
+
+#[test]
+fn stops_on_control_message() {
+    let parsed = parse(96, &two_track_song());
+    let (control_tx, control_rx) = crossbeam_channel::unbounded();
+    control_tx.send(PlayerControl::Stop).unwrap();
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent_clone = Arc::clone(&sent);
+    let options = PlaybackOptions {
+        control: Some(control_rx),
+        ..Default::default()
+    };
+
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |data, _| sent_clone.lock().unwrap().push(data),
+        Some(Box::new(|_| {})),
+        options,
+    );
+
+    let sent = sent.lock().unwrap();
+    assert_eq!(sent[0], note(0x90, 60, 100));
+    assert_eq!(sent.len(), 1 + 32);
+}
+
+#[test]
+fn loops_with_fresh_tempo_each_pass() {
+    let parsed = parse(96, &two_track_song());
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let delays = Arc::new(Mutex::new(Vec::new()));
+    let sent_clone = Arc::clone(&sent);
+    let delays_clone = Arc::clone(&delays);
+
+    play_parsed_events_looped(
+        &parsed,
+        96,
+        move |data, _| sent_clone.lock().unwrap().push(data),
+        Some(Box::new(move |t| delays_clone.lock().unwrap().push(t))),
+        Some(2),
+        PlaybackOptions::default(),
+    );
+
+    let pass = vec![
+        note(0x90, 60, 100),
+        note(0x80, 60, 0),
+        note(0x90, 62, 100),
+        note(0x80, 62, 0),
+    ];
+    let notes_off: Vec<u32> = (0..16).map(|ch| note(0xB0 | ch, 123, 0)).collect();
+    assert_eq!(
+        *sent.lock().unwrap(),
+        [&pass[..], &notes_off, &pass, &silence()].concat()
+    );
+
+    // Each pass starts from the default tempo again and re-applies the tempo map.
+    let expected = [
+        6_000_000, 6_000_000, 3_000_000, 6_000_000, 6_000_000, 3_000_000,
+    ];
+    let delays = delays.lock().unwrap();
+    assert_eq!(delays.len(), expected.len());
+    for (delay, expected) in delays.iter().zip(expected) {
+        assert!(
+            (expected - 10_000..=expected).contains(delay),
+            "{delay} vs {expected}"
+        );
+    }
+}
+
+#[test]
+fn seeks_with_silent_fast_forward() {
+    let track = TrackBuilder::new()
+        .event(0, &[0xC0, 5])
+        .event(0, &[0xB0, 7, 100])
+        .note_on(0, 0, 60, 100)
+        .event(48, &[0xE0, 0, 0x40])
+        .note_off(48, 0, 60)
+        .note_on(0, 0, 62, 100)
+        .note_off(96, 0, 62)
+        .end(0);
+    let parsed = parse(96, &[track]);
+
+    assert_eq!(seek_to_tick(&parsed, 0), (0, 0, 0));
+    assert_eq!(seek_to_tick(&parsed, 1), (3, 1, 48));
+    assert_eq!(seek_to_tick(&parsed, 96), (4, 2, 96));
+    assert_eq!(seek_to_tick(&parsed, 1000), (7, 3, 192));
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let delays = Arc::new(Mutex::new(Vec::new()));
+    let sent_clone = Arc::clone(&sent);
+    let delays_clone = Arc::clone(&delays);
+    let options = PlaybackOptions {
+        start_tick: 96,
+        let_notes_ring: true,
+        ..Default::default()
+    };
+
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |data, _| sent_clone.lock().unwrap().push(data),
+        Some(Box::new(move |t| delays_clone.lock().unwrap().push(t))),
+        options,
+    );
+
+    assert_eq!(
+        *sent.lock().unwrap(),
+        vec![
+            0x05C0,
+            note(0xB0, 7, 100),
+            note(0xE0, 0, 0x40),
+            note(0x80, 60, 0),
+            note(0x90, 62, 100),
+            note(0x80, 62, 0),
+        ]
+    );
+    assert_eq!(delays.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn mutes_channels_live() {
+    let track = TrackBuilder::new()
+        .note_on(0, 0, 60, 100)
+        .note_on(0, 1, 64, 100)
+        .event(0, &[0xB1, 7, 90])
+        .note_off(10, 0, 60)
+        .note_off(0, 1, 64)
+        .note_on(10, 0, 62, 100)
+        .note_on(0, 1, 65, 100)
+        .end(0);
+    let parsed = parse(96, &[track]);
+
+    let mute = Arc::new(AtomicU16::new(0));
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent_clone = Arc::clone(&sent);
+    let mute_clone = Arc::clone(&mute);
+    let options = PlaybackOptions {
+        channel_mute: Some(Arc::clone(&mute)),
+        let_notes_ring: true,
+        ..Default::default()
+    };
+
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |data, _| {
+            let mut sent = sent_clone.lock().unwrap();
+            sent.push(data);
+            if sent.len() == 3 {
+                // Solo channel 0 after the first block.
+                mute_clone.store(solo_channels(&[0]), Ordering::Relaxed);
+            }
+        },
+        Some(Box::new(|_| {})),
+        options,
+    );
+
+    let mut expected = vec![note(0x90, 60, 100), note(0x91, 64, 100), note(0xB1, 7, 90)];
+    expected.extend((1..16).map(|ch| note(0xB0 | ch, 123, 0)));
+    expected.extend([note(0x80, 60, 0), note(0x90, 62, 100)]);
+    assert_eq!(*sent.lock().unwrap(), expected);
+}
+
+#[test]
+fn mutes_tracks_live() {
+    let tracks = vec![
+        TrackBuilder::new()
+            .note_on(0, 0, 60, 100)
+            .note_off(10, 0, 60)
+            .note_on(10, 0, 62, 100)
+            .end(0),
+        TrackBuilder::new()
+            .note_on(0, 2, 64, 100)
+            .note_off(10, 2, 64)
+            .note_on(10, 3, 65, 100)
+            .end(0),
+    ];
+    let parsed = parse(96, &tracks);
+
+    let mask = Arc::new(TrackMask::new(2));
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent_clone = Arc::clone(&sent);
+    let mask_clone = Arc::clone(&mask);
+    let options = PlaybackOptions {
+        track_mute: Some(Arc::clone(&mask)),
+        let_notes_ring: true,
+        ..Default::default()
+    };
+
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |data, _| {
+            let mut sent = sent_clone.lock().unwrap();
+            sent.push(data);
+            if sent.len() == 2 {
+                mask_clone.solo(&[0]);
+            }
+        },
+        Some(Box::new(|_| {})),
+        options,
+    );
+
+    assert!(mask.is_muted(1));
+    assert!(!mask.is_muted(0));
+    assert_eq!(
+        *sent.lock().unwrap(),
+        vec![
+            note(0x90, 60, 100),
+            note(0x92, 64, 100),
+            // Track 1 uses channels 2 and 3.
+            note(0xB2, 123, 0),
+            note(0xB3, 123, 0),
+            note(0x80, 60, 0),
+            note(0x82, 64, 0),
+            note(0x90, 62, 100),
+        ]
+    );
+}
+
+#[test]
+fn transposes_notes_except_drums() {
+    let track = TrackBuilder::new()
+        .note_on(0, 0, 60, 100)
+        .note_on(0, 9, 36, 100)
+        .note_on(0, 1, 125, 100)
+        .event(0, &[0xB0, 64, 127])
+        .note_off(10, 0, 60)
+        .note_off(0, 9, 36)
+        .note_off(0, 1, 125)
+        .end(0);
+    let parsed = parse(96, &[track]);
+
+    let play = |range| {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sent_clone = Arc::clone(&sent);
+        let options = PlaybackOptions {
+            transpose: Some(Arc::new(AtomicI8::new(5))),
+            transpose_range: range,
+            let_notes_ring: true,
+            ..Default::default()
+        };
+        play_parsed_events_with_options(
+            &parsed,
+            96,
+            move |data, _| sent_clone.lock().unwrap().push(data),
+            Some(Box::new(|_| {})),
+            options,
+        );
+        Arc::try_unwrap(sent).unwrap().into_inner().unwrap()
+    };
+
+    assert_eq!(
+        play(TransposeRange::Clamp),
+        vec![
+            note(0x90, 65, 100),
+            note(0x99, 36, 100),
+            note(0x91, 127, 100),
+            note(0xB0, 64, 127),
+            note(0x80, 65, 0),
+            note(0x89, 36, 0),
+            note(0x81, 127, 0),
+        ]
+    );
+    assert_eq!(
+        play(TransposeRange::Drop),
+        vec![
+            note(0x90, 65, 100),
+            note(0x99, 36, 100),
+            note(0xB0, 64, 127),
+            note(0x80, 65, 0),
+            note(0x89, 36, 0),
+        ]
+    );
+}
+
+#[test]
+fn transposes_with_drums_and_negative_shift() {
+    let data = note(0x99, 36, 100);
+    assert_eq!(
+        transpose_message(data, -12, TransposeRange::Clamp, true),
+        Some(note(0x99, 24, 100))
+    );
+    assert_eq!(
+        transpose_message(data, -12, TransposeRange::Clamp, false),
+        Some(data)
+    );
+    assert_eq!(
+        transpose_message(note(0x80, 3, 0), -5, TransposeRange::Drop, false),
+        None
+    );
+}
+
+#[test]
+fn scales_note_on_velocities() {
+    assert_eq!(scale_velocity(note(0x90, 60, 100), 0.5), note(0x90, 60, 50));
+    assert_eq!(
+        scale_velocity(note(0x93, 60, 100), 2.0),
+        note(0x93, 60, 127)
+    );
+    // Quiet notes still sound, unless the scale is 0
+    assert_eq!(scale_velocity(note(0x90, 60, 1), 0.2), note(0x90, 60, 1));
+    assert_eq!(scale_velocity(note(0x90, 60, 100), 0.0), note(0x90, 60, 0));
+    // Note-off-as-note-on and other messages are untouched.
+    assert_eq!(scale_velocity(note(0x90, 60, 0), 0.5), note(0x90, 60, 0));
+    assert_eq!(scale_velocity(note(0x80, 60, 64), 0.5), note(0x80, 60, 64));
+    assert_eq!(scale_velocity(note(0xB0, 7, 100), 0.5), note(0xB0, 7, 100));
+}
+
+#[test]
+fn applies_live_velocity_scale() {
+    let parsed = parse(96, &two_track_song());
+
+    let scale = Arc::new(AtomicF32::new(0.5));
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent_clone = Arc::clone(&sent);
+    let scale_clone = Arc::clone(&scale);
+    let options = PlaybackOptions {
+        velocity_scale: Some(Arc::clone(&scale)),
+        let_notes_ring: true,
+        ..Default::default()
+    };
+
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |data, _| {
+            sent_clone.lock().unwrap().push(data);
+            scale_clone.store(0.25);
+        },
+        Some(Box::new(|_| {})),
+        options,
+    );
+
+    assert_eq!(
+        *sent.lock().unwrap(),
+        vec![
+            note(0x90, 60, 50),
+            note(0x80, 60, 0),
+            note(0x90, 62, 25),
+            note(0x80, 62, 0),
+        ]
+    );
+}
+
+/// The delays asked for when playing a file whose SMPTE offset is 1.62s.
+fn delays_with_smpte_offset(options: PlaybackOptions) -> Vec<i64> {
+    let track = TrackBuilder::new()
+        .meta(0, 0x54, &[0x20, 0, 1, 15, 50])
+        .note_on(0, 0, 60, 100)
+        .note_off(96, 0, 60)
+        .note_on(96, 0, 62, 100)
+        .end(0);
+    let parsed = parse(96, &[track]);
+
+    let delays = Arc::new(Mutex::new(Vec::new()));
+    let delays_clone = Arc::clone(&delays);
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        |_, _| {},
+        Some(Box::new(move |t| delays_clone.lock().unwrap().push(t))),
+        options,
+    );
+    delays.lock().unwrap().clone()
+}
+
+#[test]
+fn waits_for_the_smpte_offset() {
+    // Nothing is waited for unless asked
+    let delays = delays_with_smpte_offset(PlaybackOptions::default());
+    assert_eq!(delays.len(), 2);
+
+    // 100ms at a time, then the file plays as usual
+    let delays = delays_with_smpte_offset(PlaybackOptions {
+        smpte_offset: true,
+        ..Default::default()
+    });
+    let mut expected = vec![1_000_000; 16];
+    expected.push(200_000);
+    assert_eq!(delays[..17], expected);
+    assert_eq!(delays.len(), 19);
+
+    // The offset is the time of tick 0, a seek past it starts right away
+    let delays = delays_with_smpte_offset(PlaybackOptions {
+        smpte_offset: true,
+        start_tick: 96,
+        ..Default::default()
+    });
+    assert_eq!(delays.len(), 1);
+}
+
+#[test]
+fn stops_while_waiting_for_the_smpte_offset() {
+    let stop = Arc::new(AtomicBool::new(false));
+    let sent = Arc::new(AtomicBool::new(false));
+    let sent_clone = Arc::clone(&sent);
+    let stop_clone = Arc::clone(&stop);
+    let parsed = parse(
+        96,
+        &[TrackBuilder::new()
+            .meta(0, 0x54, &[0x00, 1, 0, 0, 0])
+            .note_on(0, 0, 60, 100)
+            .end(0)],
+    );
+    let options = PlaybackOptions {
+        smpte_offset: true,
+        stop: Some(Arc::clone(&stop)),
+        ..Default::default()
+    };
+
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |_, _| sent_clone.store(true, Ordering::Relaxed),
+        Some(Box::new(move |_| stop_clone.store(true, Ordering::Relaxed))),
+        options,
+    );
+    assert!(!sent.load(Ordering::Relaxed));
+}
+
+#[test]
+fn sends_escaped_bytes() {
+    let track = TrackBuilder::new()
+        .event(0, &[0xF7, 1, 0xFA])
+        .event(0, &[0xF7, 2, 0x01, 0x02])
+        .event(10, &[0xF7, 1, 0xF8])
+        .end(0);
+    let parsed = parse(96, &[track]);
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent_clone = Arc::clone(&sent);
+    let long_clone = Arc::clone(&sent);
+    let options = PlaybackOptions {
+        send_long_data: Some(Box::new(move |message: &[u8], _| {
+            long_clone.lock().unwrap().push(message.to_vec())
+        })),
+        let_notes_ring: true,
+        ..Default::default()
+    };
+
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |data, _| sent_clone.lock().unwrap().push(vec![data as u8]),
+        Some(Box::new(|_| {})),
+        options,
+    );
+
+    // Realtime bytes go out as short messages, the rest as long data
+    assert_eq!(
+        *sent.lock().unwrap(),
+        vec![vec![0xFA], vec![0x01, 0x02], vec![0xF8]]
+    );
+}
+
+#[test]
+fn forwards_sysex_to_long_data_callback() {
+    let track = TrackBuilder::new()
+        .event(0, &[0xF0, 5, 0x7E, 0x7F, 0x09, 0x01, 0xF7])
+        .note_on(0, 0, 60, 100)
+        .note_off(10, 0, 60)
+        .end(0);
+    let parsed = parse(96, &[track]);
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent_clone = Arc::clone(&sent);
+    let long_clone = Arc::clone(&sent);
+    let options = PlaybackOptions {
+        send_long_data: Some(Box::new(move |message: &[u8], _| {
+            long_clone.lock().unwrap().push(message.to_vec())
+        })),
+        let_notes_ring: true,
+        ..Default::default()
+    };
+
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |data, _| {
+            sent_clone
+                .lock()
+                .unwrap()
+                .push(data.to_le_bytes()[..3].to_vec())
+        },
+        Some(Box::new(|_| {})),
+        options,
+    );
+
+    assert_eq!(
+        *sent.lock().unwrap(),
+        vec![
+            vec![0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7],
+            vec![0x90, 60, 100],
+            vec![0x80, 60, 0],
+        ]
+    );
+}
+
+#[test]
+fn scales_delays_by_speed() {
+    let parsed = parse(96, &two_track_song());
+
+    let delays = Arc::new(Mutex::new(Vec::new()));
+    let delays_clone = Arc::clone(&delays);
+    let options = PlaybackOptions {
+        speed: Some(Arc::new(AtomicF32::new(2.0))),
+        ..Default::default()
+    };
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        |_, _| {},
+        Some(Box::new(move |t| delays_clone.lock().unwrap().push(t))),
+        options,
+    );
+
+    // 96 ticks at 600ms per quarter note, played twice as fast
+    let first = delays.lock().unwrap()[0];
+    assert!((2_990_000..=3_000_000).contains(&first), "delay {}", first);
+}
+
+#[test]
+fn plays_split_gaps_as_one_delay() {
+    let track = TrackBuilder::new()
+        .tempo(0, 1)
+        .note_on(0, 0, 60, 100)
+        .note_off(0x0FFF_FFFF, 0, 60)
+        .end(0);
+    let mut parsed = parse(1, &[track]);
+    // Split the gap the way the parser does for gaps over u32::MAX ticks
+    parsed.deltas = vec![(1, 0x0FFF_0000), (1, 0xFFFF)];
+
+    let delays = Arc::new(Mutex::new(Vec::new()));
+    let delays_clone = Arc::clone(&delays);
+    play_parsed_events(
+        &parsed,
+        1,
+        |_, _| {},
+        Some(Box::new(move |t| delays_clone.lock().unwrap().push(t))),
+    );
+
+    // 1us per tick
+    let expected = 0x0FFF_FFFF * 10;
+    let delays = delays.lock().unwrap();
+    assert_eq!(delays.len(), 1);
+    assert!(
+        (expected - 10_000..=expected).contains(&delays[0]),
+        "{}",
+        delays[0]
+    );
+}
+
+#[test]
+fn seeks_past_split_gaps() {
+    let track = TrackBuilder::new()
+        .note_on(0, 0, 60, 100)
+        .note_off(0x0FFF_FFFF, 0, 60)
+        .end(0);
+    let mut parsed = parse(96, &[track]);
+    parsed.deltas = vec![(0, 0x0FFF_0000), (0, 0xFFFF)];
+
+    assert_eq!(seek_to_tick(&parsed, 1), (1, 2, 0x0FFF_FFFF));
+}
+
+#[test]
+fn finds_events_by_tick_through_the_index() {
+    // Over a thousand gaps, so the index has a few entries
+    let mut track = TrackBuilder::new().note_on(48, 0, 60, 100);
+    for n in 0..600 {
+        track = track
+            .note_off(10 + n % 7, 0, 60)
+            .note_on(0, 0, 60, 100)
+            .note_on(5, 1, 64, 100);
+    }
+    let mut parsed = parse(96, &[track.note_off(10, 0, 60).end(0)]);
+    assert!(parsed.deltas.len() >= 4 * TICK_INDEX_STRIDE);
+
+    let ticks: Vec<u64> = parsed.iter_with_ticks().map(|(tick, _)| tick).collect();
+    for tick in (0..=parsed.total_ticks + 100).step_by(3) {
+        let expected = ticks.partition_point(|&t| t < tick);
+        assert_eq!(parsed.index_at_tick(tick), expected, "tick {}", tick);
+        if let Some(&landed) = ticks.get(expected) {
+            assert_eq!(seek_to_tick(&parsed, tick).2, landed, "tick {}", tick);
+        }
+    }
+    // Before the first event and past the last
+    assert_eq!(parsed.index_at_tick(0), 0);
+    assert_eq!(parsed.index_at_tick(48), 0);
+    assert_eq!(parsed.index_at_tick(u64::MAX), parsed.events.len());
+    assert_eq!(
+        seek_to_tick(&parsed, u64::MAX),
+        (parsed.events.len(), parsed.deltas.len(), parsed.total_ticks)
+    );
+
+    // Without a rebuild, an index for another start or delta table isn't
+    // used
+    let last = ticks.len() - 1;
+    parsed.first_tick += 1000;
+    assert_eq!(parsed.index_at_tick(ticks[last] + 1000), last);
+    parsed.first_tick -= 1000;
+    parsed.deltas.truncate(parsed.deltas.len() - 1);
+    assert_eq!(parsed.index_at_tick(ticks[last]), parsed.events.len());
+    parsed.rebuild_tick_index();
+    assert_eq!(parsed.index_at_tick(ticks[100]), 100);
+}
+
+#[test]
+fn lets_unreleased_notes_ring_when_asked() {
+    let track = TrackBuilder::new()
+        .note_on(0, 0, 60, 100)
+        .note_on(96, 1, 64, 100)
+        .end(0);
+    let parsed = parse(96, &[track]);
+
+    for let_notes_ring in [false, true] {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sent_clone = Arc::clone(&sent);
+        let options = PlaybackOptions {
+            let_notes_ring,
+            ..Default::default()
+        };
+        play_parsed_events_with_options(
+            &parsed,
+            96,
+            move |data, _| sent_clone.lock().unwrap().push(data),
+            Some(Box::new(|_| {})),
+            options,
+        );
+
+        let notes = vec![note(0x90, 60, 100), note(0x91, 64, 100)];
+        let expected = if let_notes_ring {
+            notes
+        } else {
+            [notes, silence()].concat()
+        };
+        assert_eq!(*sent.lock().unwrap(), expected);
+    }
+}
+
+#[test]
+fn classifies_velocity_0_note_ons_as_note_offs() {
+    assert_eq!(event_kind(note(0x93, 60, 100)), EventKind::NoteOn);
+    assert_eq!(event_kind(note(0x93, 60, 0)), EventKind::NoteOff);
+    assert_eq!(event_kind(note(0x83, 60, 64)), EventKind::NoteOff);
+    assert_eq!(event_kind(note(0xA0, 60, 10)), EventKind::PolyPressure);
+    assert_eq!(event_kind(note(0xB0, 7, 100)), EventKind::ControlChange);
+    assert_eq!(event_kind(0x05C0), EventKind::ProgramChange);
+    assert_eq!(event_kind(0x40D0), EventKind::ChannelPressure);
+    assert_eq!(event_kind(note(0xE0, 0, 0x40)), EventKind::PitchBend);
+    assert_eq!(event_kind(0xF8), EventKind::System);
+
+    // Releases are transposed like the Note Ons they end, but never scaled
+    assert_eq!(
+        transpose_message(note(0x90, 60, 0), 2, TransposeRange::Clamp, false),
+        Some(note(0x90, 62, 0))
+    );
+    assert_eq!(scale_velocity(note(0x90, 60, 0), 2.0), note(0x90, 60, 0));
+}
+
+/// A clock that only moves when it's advanced.
+#[derive(Default)]
+struct VirtualClock(AtomicI64);
+
+impl VirtualClock {
+    fn advance(&self, units: i64) {
+        self.0.fetch_add(units, Ordering::Relaxed);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now_100ns(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[test]
+fn subtracts_send_time_from_delays_with_a_virtual_clock() {
+    let parsed = parse(96, &two_track_song());
+
+    let clock = Arc::new(VirtualClock::default());
+    let delays = Arc::new(Mutex::new(Vec::new()));
+    let send_clock = Arc::clone(&clock);
+    let delay_clock = Arc::clone(&clock);
+    let delays_clone = Arc::clone(&delays);
+    let options = PlaybackOptions {
+        clock: Some(clock.clone()),
+        let_notes_ring: true,
+        ..Default::default()
+    };
+
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        // Every message takes 100us to send
+        move |_, _| send_clock.advance(1_000),
+        Some(Box::new(move |t| {
+            delays_clone.lock().unwrap().push(t);
+            delay_clock.advance(t);
+        })),
+        options,
+    );
+
+    // Each gap is shortened by the time spent sending the events before it,
+    // so the song still ends exactly on time.
+    assert_eq!(
+        *delays.lock().unwrap(),
+        vec![5_999_000, 5_999_000, 2_999_000]
+    );
+    assert_eq!(clock.now_100ns(), 15_001_000);
+}
+
+/// The delays of playing `two_track_song` when sending the first message
+/// stalls for a second.
+fn delays_after_a_stall(max_drift: Duration) -> Vec<i64> {
+    let parsed = parse(96, &two_track_song());
+
+    let clock = Arc::new(VirtualClock::default());
+    let delays = Arc::new(Mutex::new(Vec::new()));
+    let send_clock = Arc::clone(&clock);
+    let delay_clock = Arc::clone(&clock);
+    let delays_clone = Arc::clone(&delays);
+    let stalled = AtomicBool::new(false);
+    let options = PlaybackOptions {
+        clock: Some(clock.clone()),
+        let_notes_ring: true,
+        max_drift,
+        ..Default::default()
+    };
+
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |_, _| {
+            if !stalled.swap(true, Ordering::Relaxed) {
+                send_clock.advance(10_000_000);
+            }
+        },
+        Some(Box::new(move |t| {
+            delays_clone.lock().unwrap().push(t);
+            delay_clock.advance(t);
+        })),
+        options,
+    );
+
+    delays.lock().unwrap().clone()
+}
+
+#[test]
+fn catches_up_on_at_most_max_drift() {
+    // The first 600ms gap is skipped. The player is then 1s behind where
+    // that gap should have started, and only `max_drift` of that counts
+    // against the next gap
+    assert_eq!(
+        delays_after_a_stall(Duration::from_millis(10)),
+        vec![6_000_000, 3_000_000]
+    );
+    assert_eq!(
+        delays_after_a_stall(Duration::from_millis(700)),
+        vec![5_000_000, 3_000_000]
+    );
+    assert_eq!(
+        delays_after_a_stall(Duration::from_secs(1)),
+        vec![2_000_000, 3_000_000]
+    );
+}
+
+#[test]
+fn measures_timing_error() {
+    let parsed = parse(96, &two_track_song());
+    let clock = Arc::new(VirtualClock::default());
+    let send_clock = Arc::clone(&clock);
+    let delay_clock = Arc::clone(&clock);
+    let stalled = AtomicBool::new(false);
+    let options = PlaybackOptions {
+        clock: Some(clock.clone()),
+        let_notes_ring: true,
+        timing_stats: true,
+        ..Default::default()
+    };
+
+    let stats = play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |_, _| {
+            if !stalled.swap(true, Ordering::Relaxed) {
+                send_clock.advance(10_000_000);
+            }
+        },
+        Some(Box::new(move |t| delay_clock.advance(t))),
+        options,
+    );
+
+    // The stall makes the first gap 1s late. Only 600ms of that is caught
+    // up, by skipping the gap, and the rest is dropped by the drift clamp,
+    // so the other two gaps stay 400ms late
+    assert_eq!(
+        stats,
+        PlaybackStats {
+            gaps: 3,
+            total_error: Duration::from_millis(1_800),
+            max_lateness: Duration::from_secs(1),
+            drift_clamps: 1,
+        }
+    );
+    assert_eq!(stats.mean_error(), Duration::from_millis(600));
+}
+
+/// The messages sent when playing a dense passage right after sending the
+/// first message stalls for a second.
+fn sent_after_a_stall(catch_up: CatchUpMode) -> Vec<u32> {
+    let parsed = parse(
+        96,
+        &[TrackBuilder::new()
+            .note_on(0, 0, 60, 100)
+            .event(12, &[0xE0, 0, 0x50])
+            .note_on(12, 0, 62, 100)
+            .note_off(12, 0, 62)
+            .event(12, &[0xB0, 64, 127])
+            .event(0, &[0xB0, 7, 100])
+            // Back on time after a long enough gap
+            .note_on(912, 0, 64, 100)
+            .note_off(96, 0, 64)
+            .note_off(0, 0, 60)
+            .end(0)],
+    );
+
+    let clock = Arc::new(VirtualClock::default());
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let send_clock = Arc::clone(&clock);
+    let delay_clock = Arc::clone(&clock);
+    let sent_clone = Arc::clone(&sent);
+    let options = PlaybackOptions {
+        clock: Some(clock.clone()),
+        let_notes_ring: true,
+        catch_up,
+        ..Default::default()
+    };
+
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |data, _| {
+            let mut sent = sent_clone.lock().unwrap();
+            if sent.is_empty() {
+                send_clock.advance(10_000_000);
+            }
+            sent.push(data);
+        },
+        Some(Box::new(move |t| delay_clock.advance(t))),
+        options,
+    );
+
+    sent.lock().unwrap().clone()
+}
+
+#[test]
+fn skips_events_to_catch_up() {
+    let bend = note(0xE0, 0, 0x50);
+    let pedal = note(0xB0, 64, 127);
+    let volume = note(0xB0, 7, 100);
+    let all = vec![
+        note(0x90, 60, 100),
+        bend,
+        note(0x90, 62, 100),
+        note(0x80, 62, 0),
+        pedal,
+        volume,
+        note(0x90, 64, 100),
+        note(0x80, 64, 0),
+        note(0x80, 60, 0),
+    ];
+    assert_eq!(sent_after_a_stall(CatchUpMode::Absorb), all);
+
+    let without = |skipped: &[u32]| -> Vec<u32> {
+        all.iter()
+            .copied()
+            .filter(|data| !skipped.contains(data))
+            .collect()
+    };
+    // Pedals are never skipped
+    assert_eq!(
+        sent_after_a_stall(CatchUpMode::SkipNonNote),
+        without(&[bend, volume])
+    );
+    // The Note Off of a skipped note is skipped with it
+    assert_eq!(
+        sent_after_a_stall(CatchUpMode::SkipAll),
+        without(&[bend, volume, note(0x90, 62, 100), note(0x80, 62, 0)])
+    );
+
+    assert_eq!("skip-all".parse(), Ok(CatchUpMode::SkipAll));
+    assert_eq!(CatchUpMode::SkipNonNote.to_string(), "skip-non-note");
+    assert!("skip".parse::<CatchUpMode>().is_err());
+}
+
+#[test]
+fn loops_a_region_and_cuts_notes_at_its_end() {
+    let parsed = parse(96, &two_track_song());
+
+    let clock = Arc::new(VirtualClock::default());
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let delays = Arc::new(Mutex::new(Vec::new()));
+    let sent_clone = Arc::clone(&sent);
+    let delays_clone = Arc::clone(&delays);
+    let delay_clock = Arc::clone(&clock);
+    let options = PlaybackOptions {
+        start_tick: 192,
+        end_tick: Some(240),
+        clock: Some(clock.clone()),
+        ..Default::default()
+    };
+
+    play_parsed_events_looped(
+        &parsed,
+        96,
+        move |data, _| sent_clone.lock().unwrap().push(data),
+        Some(Box::new(move |t| {
+            delays_clone.lock().unwrap().push(t);
+            delay_clock.advance(t);
+        })),
+        Some(2),
+        options,
+    );
+
+    // The note held at the end of the region is released between passes
+    let pass = vec![note(0x90, 62, 100)];
+    let notes_off: Vec<u32> = (0..16).map(|ch| note(0xB0 | ch, 123, 0)).collect();
+    assert_eq!(
+        *sent.lock().unwrap(),
+        [&pass[..], &notes_off, &pass, &silence()].concat()
+    );
+    // Each pass waits 48 ticks at the tempo in effect at the start, 300ms/qn
+    assert_eq!(*delays.lock().unwrap(), vec![1_500_000, 1_500_000]);
+}
+
+#[test]
+fn stops_at_the_end_tick() {
+    let parsed = parse(96, &two_track_song());
+
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent_clone = Arc::clone(&sent);
+    let options = PlaybackOptions {
+        end_tick: Some(192),
+        let_notes_ring: true,
+        ..Default::default()
+    };
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |data, _| sent_clone.lock().unwrap().push(data),
+        Some(Box::new(|_| {})),
+        options,
+    );
+
+    assert_eq!(
+        *sent.lock().unwrap(),
+        vec![note(0x90, 60, 100), note(0x80, 60, 0)]
+    );
+}
+
+#[test]
+fn fades_out_expression_before_the_end() {
+    // Two seconds long, the fade covers the second half
+    let track = TrackBuilder::new()
+        .note_on(0, 0, 60, 100)
+        .event(96, &[0xB0, 11, 100])
+        .event(96, &[0xB0, 11, 100])
+        .note_off(192, 0, 60)
+        .end(0);
+    let parsed = parse(96, &[track]);
+
+    let clock = Arc::new(VirtualClock::default());
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let delays = Arc::new(Mutex::new(Vec::new()));
+    let sent_clone = Arc::clone(&sent);
+    let delays_clone = Arc::clone(&delays);
+    let delay_clock = Arc::clone(&clock);
+    let options = PlaybackOptions {
+        fade_out: Some(Duration::from_secs(1)),
+        let_notes_ring: true,
+        clock: Some(clock.clone()),
+        ..Default::default()
+    };
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |data, _| sent_clone.lock().unwrap().push(data),
+        Some(Box::new(move |t| {
+            delays_clone.lock().unwrap().push(t);
+            delay_clock.advance(t);
+        })),
+        options,
+    );
+
+    let sent = sent.lock().unwrap();
+    // Full expression first, then the file's own change before the fade
+    assert_eq!(
+        sent[..3],
+        [
+            note(0xB0, 11, 127),
+            note(0x90, 60, 100),
+            note(0xB0, 11, 100)
+        ]
+    );
+    // The file's change inside the fade is dropped and the expression ramps
+    // down once per 50ms step
+    let levels: Vec<u32> = sent[3..]
+        .iter()
+        .take_while(|&&data| data & 0xFFFF == 0x0BB0)
+        .map(|data| data >> 16)
+        .collect();
+    let expected: Vec<u32> = (1..20)
+        .map(|step| (127.0 * (1.0 - step as f64 / 20.0)).round() as u32)
+        .collect();
+    assert_eq!(levels, expected);
+    assert_eq!(sent[3 + levels.len()], note(0x80, 60, 0));
+    // Silenced at the end even though notes are left ringing otherwise
+    assert_eq!(sent[4 + levels.len()..], silence());
+
+    // The 1s gap in the fade is waited for in 50ms steps
+    let delays = delays.lock().unwrap();
+    assert_eq!(delays[2..], [500_000; 20]);
+}
+
+#[test]
+fn remaps_channels_after_muting() {
+    let track = TrackBuilder::new()
+        .note_on(0, 0, 60, 100)
+        .note_on(0, 1, 62, 100)
+        .event(96, &[0xC0, 5])
+        .note_off(0, 0, 60)
+        .note_off(0, 1, 62)
+        .end(0);
+    let parsed = parse(96, &[track]);
+
+    let mut map: [u8; 16] = std::array::from_fn(|channel| channel as u8);
+    map[0] = 3;
+    map[1] = 0;
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent_clone = Arc::clone(&sent);
+    let options = PlaybackOptions {
+        channel_map: Some(map),
+        // Muting uses the channels of the file
+        channel_mute: Some(Arc::new(AtomicU16::new(1 << 1))),
+        let_notes_ring: true,
+        ..Default::default()
+    };
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |data, _| sent_clone.lock().unwrap().push(data),
+        Some(Box::new(|_| {})),
+        options,
+    );
+
+    assert_eq!(
+        *sent.lock().unwrap(),
+        vec![note(0x93, 60, 100), 0x05C3, note(0x83, 60, 0)]
+    );
+
+    // Only channel messages are remapped
+    assert_eq!(
+        remap_channel(note(0x90, 60, 100), &map),
+        note(0x93, 60, 100)
+    );
+    assert_eq!(remap_channel(0xF0, &map), 0xF0);
+    assert_eq!(remap_channel(0xF8, &map), 0xF8);
+}
+
+#[test]
+fn remaps_drum_notes_only_on_channel_10() {
+    let track = TrackBuilder::new()
+        .note_on(0, 9, 36, 100)
+        .note_on(0, 0, 36, 100)
+        .note_off(96, 9, 36)
+        .note_off(0, 0, 36)
+        .end(0);
+    let parsed = parse(96, &[track]);
+
+    // Play the GM Bass Drum 1 with the Acoustic Bass Drum
+    let mut drum_map: [u8; 128] = std::array::from_fn(|key| key as u8);
+    drum_map[36] = 35;
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let sent_clone = Arc::clone(&sent);
+    let options = PlaybackOptions {
+        drum_map: Some(drum_map),
+        transpose: Some(Arc::new(AtomicI8::new(2))),
+        let_notes_ring: true,
+        ..Default::default()
+    };
+    play_parsed_events_with_options(
+        &parsed,
+        96,
+        move |data, _| sent_clone.lock().unwrap().push(data),
+        Some(Box::new(|_| {})),
+        options,
+    );
+
+    // Melodic notes are transposed but never remapped
+    assert_eq!(
+        *sent.lock().unwrap(),
+        vec![
+            note(0x99, 35, 100),
+            note(0x90, 38, 100),
+            note(0x89, 35, 0),
+            note(0x80, 38, 0),
+        ]
+    );
+    assert_eq!(
+        remap_drum_note(note(0xB9, 36, 0), &drum_map),
+        note(0xB9, 36, 0)
+    );
+}
+
+#[test]
+fn reports_tempo_changes_in_bpm() {
+    let parsed = parse(96, &two_track_song());
+
+    for (start_tick, expected) in [(0, vec![100.0, 200.0]), (200, vec![200.0])] {
+        let tempos = Arc::new(Mutex::new(Vec::new()));
+        let tempos_clone = Arc::clone(&tempos);
+        let options = PlaybackOptions {
+            on_tempo: Some(Box::new(move |bpm| tempos_clone.lock().unwrap().push(bpm))),
+            start_tick,
+            ..Default::default()
+        };
+        play_parsed_events_with_options(&parsed, 96, |_, _| {}, Some(Box::new(|_| {})), options);
+
+        // After a seek, the tempo in effect is reported once
+        assert_eq!(*tempos.lock().unwrap(), expected, "start at {start_tick}");
+    }
+}
@@ -0,0 +1,51 @@
+use std::fs;
+use std::path::PathBuf;
+
+use midiplayer_rs::playlist::{expand_playlist, is_midi_file, shuffle};
+
+#[test]
+fn recognizes_midi_extensions() {
+    assert!(is_midi_file("song.mid".as_ref()));
+    assert!(is_midi_file("SONG.MIDI".as_ref()));
+    assert!(is_midi_file("song.rmi".as_ref()));
+    assert!(is_midi_file("song.mid.GZ".as_ref()));
+    assert!(!is_midi_file("notes.txt.gz".as_ref()));
+    assert!(!is_midi_file("notes.txt".as_ref()));
+    assert!(!is_midi_file("mid".as_ref()));
+}
+
+#[test]
+fn expands_directories_in_order() {
+    let dir = std::env::temp_dir().join(format!("midiplayer_rs_playlist_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for name in ["b.mid", "a.MID", "c.midi", "readme.txt"] {
+        fs::write(dir.join(name), b"").unwrap();
+    }
+
+    let single = PathBuf::from("first.mid");
+    let playlist = expand_playlist(&[single.clone(), dir.clone()]).unwrap();
+    assert_eq!(
+        playlist,
+        vec![
+            single,
+            dir.join("a.MID"),
+            dir.join("b.mid"),
+            dir.join("c.midi")
+        ]
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn shuffles_deterministically() {
+    let mut a: Vec<u32> = (0..20).collect();
+    let mut b = a.clone();
+    shuffle(&mut a, 42);
+    shuffle(&mut b, 42);
+    assert_eq!(a, b);
+    assert_ne!(a, (0..20).collect::<Vec<_>>());
+
+    a.sort();
+    assert_eq!(a, (0..20).collect::<Vec<_>>());
+}
@@ -0,0 +1,45 @@
+use midiplayer_rs::midi::reset::SynthReset;
+use midiplayer_rs::midi::utils::pack_short_message;
+
+#[test]
+fn parses_reset_names() {
+    assert_eq!("none".parse(), Ok(SynthReset::None));
+    assert_eq!("GM".parse(), Ok(SynthReset::Gm));
+    assert_eq!(" gs ".parse(), Ok(SynthReset::Gs));
+    assert_eq!("xg".parse(), Ok(SynthReset::Xg));
+    assert!("mt32".parse::<SynthReset>().is_err());
+    assert_eq!(SynthReset::Gs.to_string(), "gs");
+}
+
+#[test]
+fn sends_sysex_then_default_volume_and_pan() {
+    let mut short = Vec::new();
+    let mut long = Vec::new();
+    SynthReset::Gm.send(
+        &mut |data, _track| short.push(data),
+        &mut |message: &[u8]| long.push(message.to_vec()),
+    );
+
+    assert_eq!(long, vec![vec![0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7]]);
+    assert_eq!(short.len(), 32);
+    assert_eq!(short[0], pack_short_message(0xB0, 7, 100));
+    assert_eq!(short[1], pack_short_message(0xB0, 10, 64));
+    assert_eq!(short[31], pack_short_message(0xBF, 10, 64));
+}
+
+#[test]
+fn gs_and_xg_resets_are_valid_sysex() {
+    for reset in [SynthReset::Gs, SynthReset::Xg] {
+        let sysex = reset.sysex().unwrap();
+        assert_eq!(sysex.first(), Some(&0xF0));
+        assert_eq!(sysex.last(), Some(&0xF7));
+        assert!(sysex[1..sysex.len() - 1].iter().all(|&b| b < 0x80));
+    }
+}
+
+#[test]
+fn none_sends_nothing() {
+    let (mut short, mut long) = (0, 0);
+    SynthReset::None.send(&mut |_, _| short += 1, &mut |_: &[u8]| long += 1);
+    assert_eq!((short, long), (0, 0));
+}
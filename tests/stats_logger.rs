@@ -0,0 +1,142 @@
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use midiplayer_rs::stats_logger::{StatsCsv, StatsLogger, StatsMonitor};
+
+/// A logger with one completed frame per value, and an empty current frame.
+fn logger_with_frames(frames: &[u32]) -> StatsLogger {
+    let logger = StatsLogger::new(Duration::from_secs(1), frames.len() + 1);
+    for &count in frames {
+        logger.increment(count);
+        logger.next_frame();
+    }
+    logger
+}
+
+#[test]
+fn sums_events_over_history() {
+    let logger = logger_with_frames(&[10, 20, 30]);
+    logger.increment(5);
+    assert_eq!(logger.get_window_events(), 65);
+}
+
+#[test]
+fn measures_rate_over_wall_time() {
+    // 500 events per second, with frames advanced on time
+    let logger = StatsLogger::new(Duration::from_secs(1), 10);
+    assert_eq!(logger.frame_length(), Duration::from_millis(100));
+    let start = Instant::now();
+    for frame in 1..=30 {
+        logger.increment(50);
+        logger.next_frame_at(start + Duration::from_millis(100) * frame);
+    }
+    let eps = logger.get_eps_at(start + Duration::from_secs(3));
+    assert!((495..=505).contains(&eps), "{}", eps);
+
+    // The same rate with frames advanced late still measures the same
+    let logger = StatsLogger::new(Duration::from_secs(1), 10);
+    let start = Instant::now();
+    for frame in 1..=30 {
+        logger.increment(75);
+        logger.next_frame_at(start + Duration::from_millis(150) * frame);
+    }
+    let eps = logger.get_eps_at(start + Duration::from_millis(4500));
+    assert!((495..=505).contains(&eps), "{}", eps);
+}
+
+#[test]
+fn reports_min_max_and_percentiles() {
+    let logger = logger_with_frames(&[40, 10, 30, 20, 50]);
+    // The frame being filled doesn't count
+    logger.increment(1000);
+
+    assert_eq!(logger.get_min(), 10);
+    assert_eq!(logger.get_max(), 50);
+    assert_eq!(logger.get_percentile(0.0), 10);
+    assert_eq!(logger.get_percentile(50.0), 30);
+    assert_eq!(logger.get_percentile(90.0), 50);
+    assert_eq!(logger.get_percentile(100.0), 50);
+}
+
+#[test]
+fn keeps_all_time_peak() {
+    let logger = StatsLogger::new(Duration::from_secs(2), 2);
+    let start = Instant::now();
+    logger.increment(100);
+    logger.next_frame_at(start + Duration::from_secs(1));
+    logger.next_frame_at(start + Duration::from_secs(2));
+    logger.next_frame_at(start + Duration::from_secs(3));
+    logger.increment(10);
+
+    // 10 events over the two frames since 2s
+    assert_eq!(logger.get_eps_at(start + Duration::from_secs(4)), 5);
+    assert_eq!(logger.get_peak_eps(), 100);
+}
+
+#[test]
+fn writes_stats_csv_rows() {
+    let path = std::env::temp_dir().join(format!("midiplayer_rs_stats_{}.csv", std::process::id()));
+    let mut csv = StatsCsv::create(&path).unwrap();
+    csv.write_row(Duration::from_millis(16), 12, 720, Some(48), Some(3.5))
+        .unwrap();
+    csv.write_row(Duration::from_millis(32), 0, 720, None, None)
+        .unwrap();
+    csv.flush().unwrap();
+
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "timestamp,events_this_frame,events_per_sec,voice_count,rendering_time\n\
+         0.016,12,720,48,3.5\n\
+         0.032,0,720,,\n"
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn reports_current_frame_events() {
+    let logger = logger_with_frames(&[10]);
+    logger.increment(3);
+    logger.increment(4);
+    assert_eq!(logger.get_frame_events(), 7);
+}
+
+#[test]
+fn hands_frames_to_the_callback() {
+    let frames = Arc::new(Mutex::new(Vec::new()));
+    let frames_clone = Arc::clone(&frames);
+    let monitor = StatsMonitor::start(Duration::from_secs(1), 100, None, move |stats| {
+        frames_clone.lock().unwrap().push(stats)
+    });
+    monitor.counter().fetch_add(25, Ordering::Relaxed);
+    while frames.lock().unwrap().len() < 3 {
+        thread::sleep(Duration::from_millis(1));
+    }
+    let logger = monitor.stop();
+
+    let frames = frames.lock().unwrap();
+    assert_eq!(frames.iter().map(|f| f.events_this_frame).sum::<u32>(), 25);
+    assert!(frames.iter().all(|f| f.voice_count.is_none()));
+    assert!(frames.windows(2).all(|w| w[0].timestamp < w[1].timestamp));
+    assert_eq!(logger.get_window_events(), 25);
+    assert!(logger.get_peak_eps() > 0);
+}
+
+#[test]
+fn keeps_counts_across_frame_boundaries() {
+    // Enough frames that none are dropped, so every count must stay
+    let logger = Arc::new(StatsLogger::new(Duration::from_secs(1), 2_001));
+    let producer_logger = Arc::clone(&logger);
+    let producer = thread::spawn(move || {
+        for _ in 0..200_000 {
+            producer_logger.increment(1);
+        }
+    });
+    for _ in 0..2_000 {
+        logger.next_frame();
+    }
+    producer.join().unwrap();
+
+    assert_eq!(logger.get_window_events(), 200_000);
+}
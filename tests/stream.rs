@@ -0,0 +1,276 @@
+mod common;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use common::{TrackBuilder, note, smf, two_track_song};
+use midiplayer_rs::midi::loader::{LoadOptions, TrackSelection, load_midi_buffer_selected};
+use midiplayer_rs::midi::player::{
+    PLAYER_TRACK, PlaybackOptions, parse_midi_events, parse_midi_patterns,
+    play_parsed_events_with_options,
+};
+use midiplayer_rs::midi::stream::{play_patterns_streaming, play_tracks_streaming};
+use midiplayer_rs::midi::track_data::TrackData;
+
+type Sent = Arc<Mutex<Vec<(u32, u16)>>>;
+type Delays = Arc<Mutex<Vec<i64>>>;
+type DelayFn = Box<dyn FnMut(i64) + Send>;
+
+fn load(tracks: &[Vec<u8>], selection: Option<&str>) -> Vec<TrackData> {
+    let selection = selection.map(|s| s.parse::<TrackSelection>().unwrap());
+    let bytes = Arc::new(smf(1, 96, tracks));
+    load_midi_buffer_selected(bytes, selection.as_ref(), &LoadOptions::default())
+        .unwrap()
+        .tracks
+}
+
+/// The messages sent and the delays asked for, without sleeping.
+fn recorder() -> (Sent, Delays, impl FnMut(u32, u16) + Send + 'static, DelayFn) {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let delays = Arc::new(Mutex::new(Vec::new()));
+    let sent_clone = Arc::clone(&sent);
+    let delays_clone = Arc::clone(&delays);
+    (
+        sent,
+        delays,
+        move |data, track| sent_clone.lock().unwrap().push((data, track)),
+        Box::new(move |t| delays_clone.lock().unwrap().push(t)),
+    )
+}
+
+/// Whether each delay is within 1ms of the expected one. Only the time
+/// spent reading events is taken off.
+fn close_to(delays: &[i64], expected: &[i64]) -> bool {
+    delays.len() == expected.len()
+        && delays
+            .iter()
+            .zip(expected)
+            .all(|(delay, expected)| (delay - expected).abs() < 10_000)
+}
+
+#[test]
+fn plays_like_the_parsed_player() {
+    let tracks = vec![
+        TrackBuilder::new()
+            .tempo(0, 600_000)
+            .note_on(0, 0, 60, 100)
+            .tempo(96, 300_000)
+            .note_off(0, 0, 60)
+            .note_on(0, 0, 62, 100)
+            .note_off(96, 0, 62)
+            .end(0),
+        TrackBuilder::new()
+            .event(96, &[0xF0, 0x02, 0x7E, 0xF7])
+            .event(0, &[0xB1, 7, 100])
+            .note_on(0, 1, 64, 100)
+            .meta(0, 0x01, b"text")
+            .note_off(48, 1, 64)
+            .end(0),
+    ];
+
+    let (streamed, streamed_delays, send, delay) = recorder();
+    play_tracks_streaming(
+        load(&tracks, None),
+        96,
+        send,
+        Some(delay),
+        PlaybackOptions::default(),
+    );
+
+    let (parsed, parsed_delays, send, delay) = recorder();
+    play_parsed_events_with_options(
+        &parse_midi_events(load(&tracks, None), 96),
+        96,
+        send,
+        Some(delay),
+        PlaybackOptions::default(),
+    );
+
+    assert_eq!(*streamed.lock().unwrap(), *parsed.lock().unwrap());
+    // Note Ons wait for the Note Off and the controller at tick 96
+    assert_eq!(
+        streamed.lock().unwrap()[1..5],
+        [
+            (note(0x80, 60, 0), 0),
+            (note(0xB1, 7, 100), 1),
+            (note(0x90, 62, 100), 0),
+            (note(0x91, 64, 100), 1),
+        ]
+    );
+    assert!(close_to(
+        &streamed_delays.lock().unwrap(),
+        &[6_000_000, 1_500_000, 1_500_000]
+    ));
+    assert!(close_to(
+        &parsed_delays.lock().unwrap(),
+        &[6_000_000, 1_500_000, 1_500_000]
+    ));
+}
+
+#[test]
+fn keeps_track_numbers_of_a_selection() {
+    let (sent, delays, send, delay) = recorder();
+    play_tracks_streaming(
+        load(&two_track_song(), Some("1")),
+        96,
+        send,
+        Some(delay),
+        PlaybackOptions {
+            let_notes_ring: true,
+            ..Default::default()
+        },
+    );
+
+    // Without the conductor track the default tempo of 500ms per beat is used
+    assert_eq!(
+        *sent.lock().unwrap(),
+        [
+            (note(0x90, 60, 100), 1),
+            (note(0x80, 60, 0), 1),
+            (note(0x90, 62, 100), 1),
+            (note(0x80, 62, 0), 1),
+        ]
+    );
+    assert!(close_to(
+        &delays.lock().unwrap(),
+        &[5_000_000, 5_000_000, 5_000_000]
+    ));
+}
+
+#[test]
+fn plays_nothing_without_events() {
+    let (sent, delays, send, delay) = recorder();
+    play_tracks_streaming(
+        load(&[TrackBuilder::new().meta(0, 0x03, b"name").end(0)], None),
+        96,
+        send,
+        Some(delay),
+        PlaybackOptions::default(),
+    );
+
+    assert!(sent.lock().unwrap().is_empty());
+    assert!(delays.lock().unwrap().is_empty());
+}
+
+#[test]
+fn seeks_like_the_parsed_player() {
+    let tracks = vec![
+        TrackBuilder::new()
+            .tempo(0, 600_000)
+            .event(0, &[0xC0, 5])
+            .note_on(0, 0, 60, 100)
+            .tempo(96, 300_000)
+            .note_off(0, 0, 60)
+            .note_on(0, 0, 62, 100)
+            .note_off(96, 0, 62)
+            .end(0),
+    ];
+    let options = || PlaybackOptions {
+        start_tick: 90,
+        ..Default::default()
+    };
+
+    let (streamed, streamed_delays, send, delay) = recorder();
+    play_tracks_streaming(load(&tracks, None), 96, send, Some(delay), options());
+
+    let (parsed, parsed_delays, send, delay) = recorder();
+    play_parsed_events_with_options(
+        &parse_midi_events(load(&tracks, None), 96),
+        96,
+        send,
+        Some(delay),
+        options(),
+    );
+
+    assert_eq!(*streamed.lock().unwrap(), *parsed.lock().unwrap());
+    // The program is restored and the notes before the seek point skipped
+    assert_eq!(
+        streamed.lock().unwrap()[..3],
+        [
+            (note(0xC0, 5, 0), 0),
+            (note(0x80, 60, 0), 0),
+            (note(0x90, 62, 100), 0)
+        ]
+    );
+    assert!(close_to(&streamed_delays.lock().unwrap(), &[3_000_000]));
+    assert!(close_to(&parsed_delays.lock().unwrap(), &[3_000_000]));
+}
+
+#[test]
+fn plays_patterns_one_after_another() {
+    let tracks = vec![
+        TrackBuilder::new()
+            .note_on(0, 0, 60, 100)
+            .note_off(96, 0, 60)
+            .end(96),
+        TrackBuilder::new().meta(0, 0x03, b"empty").end(48),
+        TrackBuilder::new()
+            .note_on(0, 1, 64, 100)
+            .note_off(96, 1, 64)
+            .end(0),
+    ];
+
+    let (streamed, streamed_delays, send, delay) = recorder();
+    play_patterns_streaming(
+        load(&tracks, None),
+        96,
+        send,
+        Some(delay),
+        PlaybackOptions::default(),
+    );
+
+    let (parsed, parsed_delays, send, delay) = recorder();
+    play_parsed_events_with_options(
+        &parse_midi_patterns(load(&tracks, None), 96),
+        96,
+        send,
+        Some(delay),
+        PlaybackOptions::default(),
+    );
+
+    assert_eq!(*streamed.lock().unwrap(), *parsed.lock().unwrap());
+    assert_eq!(
+        streamed.lock().unwrap()[..4],
+        [
+            (note(0x90, 60, 100), 0),
+            (note(0x80, 60, 0), 0),
+            (note(0x91, 64, 100), 2),
+            (note(0x81, 64, 0), 2),
+        ]
+    );
+    // The second pattern starts after the rest at the end of the first one
+    // and the length of the empty one
+    let expected = [5_000_000, 7_500_000, 5_000_000];
+    assert!(close_to(&streamed_delays.lock().unwrap(), &expected));
+    assert!(close_to(&parsed_delays.lock().unwrap(), &expected));
+}
+
+#[test]
+fn stops_when_flag_is_set() {
+    let stop = Arc::new(AtomicBool::new(false));
+    let stop_clone = Arc::clone(&stop);
+    let (sent, _, mut send, delay) = recorder();
+    play_tracks_streaming(
+        load(&two_track_song(), None),
+        96,
+        move |data, track| {
+            send(data, track);
+            if data == note(0x80, 60, 0) {
+                stop_clone.store(true, Ordering::Relaxed);
+            }
+        },
+        Some(delay),
+        PlaybackOptions {
+            stop: Some(stop),
+            ..Default::default()
+        },
+    );
+
+    let sent = sent.lock().unwrap();
+    assert_eq!(
+        sent[..2],
+        [(note(0x90, 60, 100), 1), (note(0x80, 60, 0), 1)]
+    );
+    assert_eq!(sent.len(), 2 + 32);
+    assert!(sent[2..].iter().all(|&(_, track)| track == PLAYER_TRACK));
+}
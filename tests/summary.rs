@@ -0,0 +1,39 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{parse, two_track_song};
+use midiplayer_rs::midi::summary::Summary;
+
+#[test]
+fn builds_summary_from_parsed_file() {
+    let parsed = parse(96, &two_track_song());
+    let summary = Summary::new("song.mid", 2, &parsed, 96, Duration::from_millis(3));
+
+    assert_eq!(summary.tracks, 2);
+    assert_eq!(summary.events, 6);
+    assert_eq!(summary.note_count, 2);
+    assert_eq!(summary.total_duration, Duration::from_millis(1500));
+    assert_eq!(summary.min_send_budget.unwrap().tick, 192);
+}
+
+#[test]
+fn formats_summary_as_json() {
+    let parsed = parse(96, &two_track_song());
+    let summary = Summary::new(
+        r#"dir\"song".mid"#,
+        2,
+        &parsed,
+        96,
+        Duration::from_micros(2_500),
+    );
+
+    assert_eq!(
+        summary.to_json(),
+        "{\"file\":\"dir\\\\\\\"song\\\".mid\",\"tracks\":2,\
+         \"time_division\":\"96 ticks/quarter note\",\"events\":6,\"note_count\":2,\
+         \"total_ticks\":288,\"total_duration_ms\":1500,\"parse_time_ms\":2.500,\
+         \"min_event_interval_ms\":300.000,\"min_event_interval_tick\":192,\
+         \"duration_saturated\":false,\"warnings\":[]}"
+    );
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{TrackBuilder, smf, write_temp};
+use midiplayer_rs::midi::loader::load_midi_file;
+use midiplayer_rs::midi::player::parse_midi_events;
+use midiplayer_rs::midi::time_division::TimeDivision;
+
+#[test]
+fn decodes_raw_division() {
+    assert_eq!(TimeDivision::from_raw(480), TimeDivision::Metrical(480));
+    assert_eq!(
+        TimeDivision::from_raw(0xE728),
+        TimeDivision::Smpte {
+            fps: 25,
+            ticks_per_frame: 40
+        }
+    );
+    assert_eq!(
+        TimeDivision::from_raw(0xE350),
+        TimeDivision::Smpte {
+            fps: 29,
+            ticks_per_frame: 80
+        }
+    );
+}
+
+#[test]
+fn converts_ticks_to_time() {
+    let metrical = TimeDivision::Metrical(96);
+    assert_eq!(metrical.ticks_to_us(96, 500_000), 500_000);
+    assert_eq!(metrical.multiplier(480_000), 50_000.0);
+
+    let smpte = TimeDivision::Smpte {
+        fps: 25,
+        ticks_per_frame: 40,
+    };
+    // 1000 ticks per second regardless of tempo.
+    assert_eq!(smpte.ticks_to_us(1000, 500_000), 1_000_000);
+    assert_eq!(smpte.ticks_to_us(1000, 250_000), 1_000_000);
+    assert_eq!(smpte.multiplier(123), 10_000.0);
+
+    let drop_frame = TimeDivision::Smpte {
+        fps: 29,
+        ticks_per_frame: 100,
+    };
+    assert_eq!(drop_frame.ticks_to_us(2997, 500_000), 1_000_000);
+}
+
+#[test]
+fn parses_smpte_file_durations() {
+    // -25 fps, 40 ticks per frame.
+    let time_div = 0xE728;
+    let tracks = vec![
+        TrackBuilder::new().tempo(0, 250_000).end(0),
+        TrackBuilder::new()
+            .note_on(0, 0, 60, 100)
+            .note_off(500, 0, 60)
+            .note_on(0, 0, 62, 100)
+            .note_off(1000, 0, 62)
+            .end(0),
+    ];
+    let path = write_temp(&smf(1, time_div, &tracks));
+
+    let (loaded, loaded_div, _) = load_midi_file(&path).unwrap();
+    assert_eq!(loaded_div, time_div);
+
+    let parsed = parse_midi_events(loaded, loaded_div);
+    assert_eq!(parsed.total_duration, Duration::from_millis(1_500));
+}
@@ -0,0 +1,65 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{note, parse, two_track_song};
+use midiplayer_rs::midi::player::ParsedMidi;
+use midiplayer_rs::midi::truncate::{truncate_duration, truncate_events};
+
+/// The tick and message of every short event, in playback order.
+fn timeline(parsed: &ParsedMidi) -> Vec<(u64, u32)> {
+    parsed
+        .iter_with_ticks()
+        .filter(|(_, event)| !event.is_tempo() && !event.is_long())
+        .map(|(tick, event)| (tick, event.data()))
+        .collect()
+}
+
+fn all_notes_off_at(tick: u64) -> impl Iterator<Item = (u64, u32)> {
+    (0..16).map(move |channel| (tick, note(0xB0 | channel, 123, 0)))
+}
+
+#[test]
+fn keeps_the_first_events() {
+    let mut parsed = parse(96, &two_track_song());
+    assert!(!truncate_events(&mut parsed, 96, 6));
+
+    // The second tempo change at tick 192 is the first event cut
+    assert!(truncate_events(&mut parsed, 96, 3));
+    let mut expected = vec![(0, note(0x90, 60, 100)), (96, note(0x80, 60, 0))];
+    expected.extend(all_notes_off_at(192));
+    assert_eq!(timeline(&parsed), expected);
+
+    assert_eq!(parsed.events.len(), 3 + 16);
+    assert_eq!(parsed.note_count, 1);
+    assert_eq!(parsed.total_ticks, 192);
+    assert_eq!(parsed.total_duration, Duration::from_millis(1200));
+    assert_eq!(parsed.tempo_map.len(), 1);
+}
+
+#[test]
+fn cuts_at_a_time() {
+    let mut parsed = parse(96, &two_track_song());
+    assert!(!truncate_duration(&mut parsed, 96, Duration::from_secs(2)));
+
+    // 100ms into the second tempo segment, 32 ticks at 300000us per beat
+    assert!(truncate_duration(
+        &mut parsed,
+        96,
+        Duration::from_millis(1300)
+    ));
+    let mut expected = vec![
+        (0, note(0x90, 60, 100)),
+        (96, note(0x80, 60, 0)),
+        (192, note(0x90, 62, 100)),
+    ];
+    // The held note is released at the cut
+    expected.extend(all_notes_off_at(224));
+    assert_eq!(timeline(&parsed), expected);
+
+    assert_eq!(parsed.note_count, 2);
+    assert_eq!(parsed.max_polyphony, 1);
+    assert_eq!(parsed.total_ticks, 224);
+    assert_eq!(parsed.total_duration, Duration::from_millis(1300));
+    assert_eq!(parsed.tempo_map.len(), 2);
+}
@@ -0,0 +1,191 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, Instant};
+
+use midiplayer_rs::midi::utils::{
+    Clock, SystemClock, delay_execution_100ns, duration_from_100ns, duration_to_100ns,
+    get_time_100ns, get_time_ns, pack_short_message, play_note, set_spin_margin, spin_margin,
+    split_short_messages, unpack_short_message,
+};
+use midiplayer_rs::output::MidiOutput;
+
+#[test]
+fn packs_short_messages() {
+    assert_eq!(pack_short_message(0x93, 60, 100), 0x0064_3C93);
+    assert_eq!(pack_short_message(0xC0, 5, 0), 0x0000_05C0);
+}
+
+#[test]
+fn unpacks_short_messages() {
+    assert_eq!(unpack_short_message(0x0064_3C93), ([0x93, 60, 100], 3));
+    assert_eq!(unpack_short_message(0x0000_05C0), ([0xC0, 5, 0], 2));
+    assert_eq!(unpack_short_message(0x0000_00F8), ([0xF8, 0, 0], 1));
+}
+
+#[test]
+fn splits_raw_bytes_into_short_messages() {
+    assert_eq!(
+        split_short_messages(&[0xF8, 0xF1, 0x23, 0x90, 60, 100, 0xFA]),
+        Some(vec![0xF8, 0x23F1, 0x0064_3C90, 0xFA])
+    );
+    assert_eq!(split_short_messages(&[]), Some(vec![]));
+    // Data without a status, cut off messages and SysEx aren't short messages
+    assert_eq!(split_short_messages(&[0x01, 0x02]), None);
+    assert_eq!(split_short_messages(&[0x90, 60]), None);
+    assert_eq!(split_short_messages(&[0x90, 60, 0xF8]), None);
+    assert_eq!(split_short_messages(&[0xF0, 0x7E, 0xF7]), None);
+}
+
+/// Records the messages sent to it with the time of `clock`, and moves
+/// `clock` on by `step` for each one.
+#[derive(Default)]
+struct Recorder {
+    clock: AtomicI64,
+    step: i64,
+    sent: Mutex<Vec<(u32, i64)>>,
+}
+
+impl MidiOutput for Recorder {
+    fn send_direct_data(&self, data: u32) {
+        let now = self.clock.fetch_add(self.step, Ordering::Relaxed);
+        self.sent.lock().unwrap().push((data, now));
+    }
+
+    fn send_long_data(&self, _: &[u8]) {}
+}
+
+impl Clock for Recorder {
+    fn now_100ns(&self) -> i64 {
+        self.clock.load(Ordering::Relaxed)
+    }
+}
+
+#[test]
+fn plays_a_single_note() {
+    let recorder = Recorder::default();
+    let start = Instant::now();
+    play_note(
+        &recorder,
+        &SystemClock,
+        None,
+        0x12,
+        64,
+        90,
+        Duration::from_millis(5),
+    );
+
+    assert!(start.elapsed() >= Duration::from_millis(5));
+    let sent: Vec<u32> = recorder
+        .sent
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|(data, _)| data)
+        .collect();
+    assert_eq!(
+        sent,
+        vec![
+            pack_short_message(0x92, 64, 90),
+            pack_short_message(0x82, 64, 0)
+        ]
+    );
+}
+
+#[test]
+fn times_notes_with_the_given_clock() {
+    // Sending the Note On takes the whole second, so there is nothing left
+    // to wait for
+    let recorder = Recorder {
+        step: 10_000_000,
+        ..Default::default()
+    };
+    let start = Instant::now();
+    play_note(
+        &recorder,
+        &recorder,
+        None,
+        0,
+        60,
+        100,
+        Duration::from_secs(1),
+    );
+
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert_eq!(
+        recorder.sent.into_inner().unwrap(),
+        vec![
+            (pack_short_message(0x90, 60, 100), 0),
+            (pack_short_message(0x80, 60, 0), 10_000_000)
+        ]
+    );
+
+    // A clock that only moves when sending is advanced by the delay
+    let recorder = Recorder {
+        step: 1_000,
+        ..Default::default()
+    };
+    let mut delays = Vec::new();
+    let mut delay = |units: i64| {
+        delays.push(units);
+        recorder.clock.fetch_add(units, Ordering::Relaxed);
+    };
+    play_note(
+        &recorder,
+        &recorder,
+        Some(&mut delay),
+        0,
+        60,
+        100,
+        Duration::from_secs(1),
+    );
+
+    assert_eq!(delays, vec![9_999_000]);
+    assert_eq!(
+        recorder.sent.into_inner().unwrap(),
+        vec![
+            (pack_short_message(0x90, 60, 100), 0),
+            (pack_short_message(0x80, 60, 0), 10_000_000)
+        ]
+    );
+}
+
+#[test]
+fn delays_at_least_the_requested_time() {
+    // A spin-only delay and one that sleeps first
+    for delay in [Duration::from_micros(500), Duration::from_millis(5)] {
+        let start = Instant::now();
+        delay_execution_100ns((delay.as_nanos() / 100) as i64);
+        let elapsed = start.elapsed();
+        assert!(elapsed >= delay, "{:?} < {:?}", elapsed, delay);
+        assert!(elapsed < delay + Duration::from_millis(5), "{:?}", elapsed);
+    }
+}
+
+#[test]
+fn sets_the_spin_margin() {
+    assert_eq!(spin_margin(), Duration::from_millis(2));
+    set_spin_margin(Duration::from_secs(5));
+    assert!(spin_margin() < Duration::from_secs(1));
+    set_spin_margin(Duration::from_millis(2));
+    assert_eq!(spin_margin(), Duration::from_millis(2));
+}
+
+#[test]
+fn converts_100ns_units() {
+    let duration = Duration::new(3, 123_456_700);
+    assert_eq!(duration_to_100ns(duration), 31_234_567);
+    assert_eq!(duration_from_100ns(31_234_567), duration);
+    assert_eq!(duration_from_100ns(-5), Duration::ZERO);
+    assert_eq!(duration_to_100ns(Duration::MAX), i64::MAX);
+}
+
+#[test]
+fn clock_is_monotonic() {
+    let mut last = get_time_ns();
+    for _ in 0..1000 {
+        let now = get_time_ns();
+        assert!(now >= last);
+        last = now;
+    }
+    assert!(get_time_100ns() >= (last / 100) as i64);
+}
@@ -0,0 +1,306 @@
+mod common;
+
+use std::io::Cursor;
+
+use proptest::prelude::*;
+
+use common::{TrackBuilder, parse, smf, two_track_song, varlen, write_temp};
+use midiplayer_rs::midi::loader::load_midi_file;
+use midiplayer_rs::midi::loader::{MidiFormat, load_midi_reader};
+use midiplayer_rs::midi::player::{ParsedMidi, parse_midi_events};
+use midiplayer_rs::midi::writer::{encode_variable_length, write_midi, write_midi_file};
+
+/// Write `parsed` and read it back.
+fn round_trip(parsed: &ParsedMidi, time_div: u16) -> (Vec<u8>, ParsedMidi) {
+    let mut bytes = Vec::new();
+    write_midi(parsed, time_div, &mut bytes).unwrap();
+
+    let (tracks, time_div, format) = load_midi_reader(Cursor::new(bytes.clone())).unwrap();
+    assert_eq!(format, MidiFormat::SingleTrack);
+    (bytes, parse_midi_events(tracks, time_div))
+}
+
+#[test]
+fn encodes_variable_length_values() {
+    for value in [0, 0x40, 0x7F, 0x80, 0x2000, 0x3FFF, 0x4000, 0x0FFF_FFFF] {
+        let mut out = Vec::new();
+        encode_variable_length(value, &mut out);
+        assert_eq!(out, varlen(value), "value {:#X}", value);
+    }
+}
+
+#[test]
+fn writes_format_0_header() {
+    let parsed = parse(480, &two_track_song());
+    let (bytes, _) = round_trip(&parsed, 480);
+
+    assert_eq!(&bytes[..14], b"MThd\0\0\0\x06\0\0\0\x01\x01\xE0");
+    assert_eq!(&bytes[14..18], b"MTrk");
+    assert!(bytes.ends_with(&[0xFF, 0x2F, 0x00]));
+}
+
+#[test]
+fn round_trips_events_and_timing() {
+    let parsed = parse(96, &two_track_song());
+    let (_, reparsed) = round_trip(&parsed, 96);
+
+    let strip = |p: &ParsedMidi| -> Vec<(u32, bool)> {
+        p.events.iter().map(|e| (e.data(), e.is_tempo())).collect()
+    };
+    assert_eq!(strip(&reparsed), strip(&parsed));
+    assert_eq!(reparsed.deltas, parsed.deltas);
+    assert_eq!(reparsed.total_duration, parsed.total_duration);
+}
+
+#[test]
+fn round_trips_sysex() {
+    let parsed = parse(
+        96,
+        &[TrackBuilder::new()
+            .event(0, &[0xF0, 0x05, 0x7E, 0x7F, 0x09, 0x01, 0xF7])
+            .note_on(10, 0, 60, 100)
+            .end(0)],
+    );
+    let (_, reparsed) = round_trip(&parsed, 96);
+
+    assert!(reparsed.events.get(0).unwrap().is_long());
+    assert_eq!(
+        reparsed.long_message(reparsed.events.get(0).unwrap().data()),
+        Some(&[0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7][..])
+    );
+    assert_eq!(reparsed.deltas, parsed.deltas);
+}
+
+#[test]
+fn round_trips_escaped_bytes() {
+    let parsed = parse(
+        96,
+        &[TrackBuilder::new()
+            .event(0, &[0xF7, 2, 0xF1, 0x23])
+            .event(10, &[0xF7, 2, 0x01, 0x02])
+            .note_on(0, 0, 60, 100)
+            .end(0)],
+    );
+    let (bytes, reparsed) = round_trip(&parsed, 96);
+
+    // Written as escapes, not as bare system messages
+    assert!(bytes.windows(4).any(|w| w == [0xF7, 2, 0xF1, 0x23]));
+    assert!(bytes.windows(4).any(|w| w == [0xF7, 2, 0x01, 0x02]));
+    assert_eq!(reparsed.events.get(0).unwrap().data(), 0x23F1);
+    assert_eq!(
+        reparsed.long_message(reparsed.events.get(1).unwrap().data()),
+        Some(&[0x01, 0x02][..])
+    );
+    assert_eq!(reparsed.deltas, parsed.deltas);
+}
+
+#[test]
+fn writes_to_a_file() {
+    let parsed = parse(96, &two_track_song());
+    let path =
+        std::env::temp_dir().join(format!("midiplayer_rs_writer_{}.mid", std::process::id()));
+    write_midi_file(&parsed, 96, &path).unwrap();
+
+    let mut expected = Vec::new();
+    write_midi(&parsed, 96, &mut expected).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), expected);
+}
+
+#[test]
+fn bridges_gaps_longer_than_a_delta_time() {
+    let mut track = TrackBuilder::new().note_on(0, 0, 60, 100);
+    for _ in 0..17 {
+        track = track.meta(0x0FFF_FFFF, 0x01, b"gap");
+    }
+    let parsed = parse(96, &[track.note_off(0x0FFF_FFFF, 0, 60).end(0)]);
+
+    let (_, read_back) = round_trip(&parsed, 96);
+    assert_eq!(read_back, parsed);
+}
+
+/// What an event of a generated or parsed file carries, with SysEx messages
+/// resolved so files can be compared regardless of their buffer layout.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Item {
+    Tempo(u32),
+    Short(u32),
+    Long(Vec<u8>),
+}
+
+/// An event and where it is: `(tick, track, item)`.
+type Timed = (u64, u16, Item);
+
+/// One generated event: how long before it, what it is, and the bytes it
+/// is made of. See `build_tracks`.
+#[derive(Debug, Clone)]
+enum Step {
+    Tempo(u32),
+    SysEx(Vec<u8>),
+    Text,
+    /// Status, data bytes and whether to repeat the status byte under
+    /// running status.
+    Channel(u8, [u8; 2], bool),
+}
+
+fn delta() -> impl Strategy<Value = u32> {
+    prop_oneof![
+        1 => 0..0x1000_0000u32,
+        3 => Just(0u32),
+        6 => 0..200u32,
+    ]
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    prop_oneof![
+        1 => (100_000..2_000_000u32).prop_map(Step::Tempo),
+        1 => prop::collection::vec(0..0x80u8, 0..8).prop_map(Step::SysEx),
+        1 => Just(Step::Text),
+        17 => (0x80..0xF0u8, [0..0x80u8, 0..0x80u8], any::<bool>())
+            .prop_map(|(status, data, repeat)| Step::Channel(status, data, repeat)),
+    ]
+}
+
+/// Events of up to four tracks, with the rest at the end of each track.
+fn random_steps() -> impl Strategy<Value = Vec<(Vec<(u32, Step)>, u32)>> {
+    prop::collection::vec(
+        (prop::collection::vec((delta(), step()), 0..40), 0..100u32),
+        1..5,
+    )
+}
+
+/// Well-formed tracks from `steps`, and the events they should parse to.
+///
+/// Channel messages of every kind use running status at random. Deltas are
+/// mostly short, with some up to the largest variable-length value, so gaps
+/// need several bytes and the merged gaps can exceed `u32::MAX` ticks.
+/// Tempo changes are only kept on the first track, each different from the
+/// one before, so the parser keeps all of them.
+fn build_tracks(steps: &[(Vec<(u32, Step)>, u32)]) -> (Vec<Vec<u8>>, Vec<Timed>) {
+    let mut tracks = Vec::new();
+    let mut expected = Vec::new();
+    for (track, (events, end)) in steps.iter().enumerate() {
+        let track = track as u16;
+        let mut builder = TrackBuilder::new();
+        let mut tick = 0u64;
+        let mut running = None;
+        let mut tempo = 500_000;
+        for (delta, step) in events {
+            let delta = *delta;
+            tick += u64::from(delta);
+
+            let item = match step {
+                Step::Tempo(us_per_qn) if track == 0 => {
+                    let us_per_qn = if *us_per_qn == tempo {
+                        us_per_qn + 1
+                    } else {
+                        *us_per_qn
+                    };
+                    tempo = us_per_qn;
+                    builder = builder.tempo(delta, us_per_qn);
+                    running = None;
+                    Item::Tempo(us_per_qn)
+                }
+                Step::SysEx(body) => {
+                    let mut bytes = vec![0xF0];
+                    bytes.extend(varlen(body.len() as u32 + 1));
+                    bytes.extend(body);
+                    bytes.push(0xF7);
+                    builder = builder.event(delta, &bytes);
+                    running = None;
+
+                    let mut message = vec![0xF0];
+                    message.extend(body);
+                    message.push(0xF7);
+                    Item::Long(message)
+                }
+                Step::Tempo(_) | Step::Text => {
+                    // Other meta events are skipped by the parser
+                    builder = builder.meta(delta, 0x01, b"text");
+                    running = None;
+                    continue;
+                }
+                &Step::Channel(status, data, repeat) => {
+                    let len = if matches!(status & 0xF0, 0xC0 | 0xD0) {
+                        1
+                    } else {
+                        2
+                    };
+
+                    let mut bytes = Vec::new();
+                    if running != Some(status) || repeat {
+                        bytes.push(status);
+                    }
+                    bytes.extend(&data[..len]);
+                    builder = builder.event(delta, &bytes);
+                    running = Some(status);
+
+                    let mut packed = u32::from(status);
+                    for (i, &byte) in data[..len].iter().enumerate() {
+                        packed |= u32::from(byte) << (8 * (i + 1));
+                    }
+                    Item::Short(packed)
+                }
+            };
+            expected.push((
+                tick,
+                if matches!(item, Item::Tempo(_)) {
+                    0
+                } else {
+                    track
+                },
+                item,
+            ));
+        }
+        tracks.push(builder.end(*end));
+    }
+    expected.sort();
+    (tracks, expected)
+}
+
+/// The events of `parsed` with their ticks, from the first event.
+fn timed_items(parsed: &ParsedMidi) -> Vec<Timed> {
+    parsed
+        .iter_with_ticks()
+        .map(|(tick, event)| {
+            let item = if event.is_tempo() {
+                Item::Tempo(event.data())
+            } else if event.is_long() {
+                Item::Long(parsed.long_message(event.data()).unwrap().to_vec())
+            } else {
+                Item::Short(event.data())
+            };
+            (tick, event.track(), item)
+        })
+        .collect()
+}
+
+proptest! {
+    #[test]
+    fn round_trips_random_tracks(steps in random_steps()) {
+        let (tracks, expected) = build_tracks(&steps);
+
+        let path = write_temp(&smf(1, 96, &tracks));
+        let (loaded, time_div, _) = load_midi_file(&path).unwrap();
+        let _ = std::fs::remove_file(path);
+        let parsed = parse_midi_events(loaded, time_div);
+
+        // Same-tick events may be merged in any order
+        let mut items = timed_items(&parsed);
+        items.sort();
+        prop_assert_eq!(items, expected);
+
+        // Written as one track, the file keeps every event in order and at
+        // its tick, including any silence before the first one
+        let (_, reparsed) = round_trip(&parsed, 96);
+        let untracked = |p: &ParsedMidi| -> Vec<(u64, Item)> {
+            timed_items(p)
+                .into_iter()
+                .map(|(tick, _, item)| (tick, item))
+                .collect()
+        };
+        prop_assert_eq!(untracked(&reparsed), untracked(&parsed));
+        prop_assert_eq!(reparsed.first_tick, parsed.first_tick);
+        prop_assert_eq!(&reparsed.deltas, &parsed.deltas);
+        prop_assert_eq!(reparsed.total_duration, parsed.total_duration);
+    }
+}